@@ -11,7 +11,7 @@ use encoding_rs as enc;
 use marked::{
     chain_filters,
     filter,
-    html::parse_buffered,
+    html::{parse_buffered, parse_buffered_with_report},
     logger::setup_logger,
     EncodingHint,
 };
@@ -73,6 +73,9 @@ fn run() -> Result<(), Flaw> {
                 .short("f")
                 .long("filter-banned")
                 .help("Filter banned tags and attributes"),
+            Arg::with_name("errors")
+                .long("errors")
+                .help("Report non-fatal parse errors (to STDERR)"),
             Arg::with_name("text-normalize")
                 .short("t")
                 .long("text-normalize")
@@ -122,9 +125,24 @@ fn run() -> Result<(), Flaw> {
         Box::new(io::stdin())
     };
 
-    let mut doc = parse_buffered(eh, &mut input)?;
-
-    // FIXME: report non-fatal errors?
+    let mut doc = if mtch.is_present("errors") {
+        let (doc, report) = parse_buffered_with_report(
+            eh, &mut input, &Default::default()
+        )?;
+        let errors = report.parse_errors();
+        for e in errors.errors() {
+            eprintln!("{}:{}", fin.unwrap_or("-"), e);
+        }
+        if errors.truncated() {
+            eprintln!(
+                "{}: ({} more errors)",
+                fin.unwrap_or("-"),
+                errors.count() - errors.errors().len());
+        }
+        doc
+    } else {
+        parse_buffered(eh, &mut input)?
+    };
 
     if mtch.is_present("filter-banned") {
         doc.filter_breadth(chain_filters!(
@@ -77,6 +77,64 @@ pub(crate) fn is_all_ctrl_ws(st: &StrTendril) -> bool {
     st.as_ref().chars().all(|c| char_class(c) != CharClass::Unclassified)
 }
 
+/// True if the char is allowed in XML 1.0 documents (the _Char_ production).
+pub(crate) fn is_xml_char(c: char) -> bool {
+    match c {
+        '\u{9}' | '\u{A}' | '\u{D}' => true,
+        '\u{20}'..='\u{D7FF}' => true,
+        '\u{E000}'..='\u{FFFD}' => true,
+        '\u{10000}'..='\u{10FFFF}' => true,
+        _ => false,
+    }
+}
+
+/// True if the char may start an XML 1.0 name, excluding `:` (the
+/// _NameStartChar_ production, as restricted for an _NCName_).
+pub(crate) fn is_ncname_start_char(c: char) -> bool {
+    match c {
+        'A'..='Z' | '_' | 'a'..='z' => true,
+        '\u{C0}'..='\u{D6}' | '\u{D8}'..='\u{F6}' => true,
+        '\u{F8}'..='\u{2FF}' | '\u{370}'..='\u{37D}' => true,
+        '\u{37F}'..='\u{1FFF}' | '\u{200C}'..='\u{200D}' => true,
+        '\u{2070}'..='\u{218F}' | '\u{2C00}'..='\u{2FEF}' => true,
+        '\u{3001}'..='\u{D7FF}' | '\u{F900}'..='\u{FDCF}' => true,
+        '\u{FDF0}'..='\u{FFFD}' | '\u{10000}'..='\u{EFFFF}' => true,
+        _ => false,
+    }
+}
+
+/// True if the char is allowed in an XML 1.0 name, excluding `:` (the
+/// _NameChar_ production, as restricted for an _NCName_).
+pub(crate) fn is_ncname_char(c: char) -> bool {
+    match c {
+        '-' | '.' | '0'..='9' | '\u{B7}' => true,
+        '\u{300}'..='\u{36F}' | '\u{203F}'..='\u{2040}' => true,
+        c => is_ncname_start_char(c),
+    }
+}
+
+/// True if the string is a valid XML namespaces _NCName_ (a name without
+/// any `:`).
+pub(crate) fn is_ncname(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if is_ncname_start_char(c) => chars.all(is_ncname_char),
+        _ => false,
+    }
+}
+
+/// True if the char is allowed in an XML public identifier (the _PubidChar_
+/// production).
+pub(crate) fn is_pubid_char(c: char) -> bool {
+    match c {
+        ' ' | '\r' | '\n' => true,
+        'a'..='z' | 'A'..='Z' | '0'..='9' => true,
+        '-' | '\'' | '(' | ')' | '+' | ',' | '.' | '/' | ':' | '=' | '?' |
+        ';' | '!' | '*' | '#' | '@' | '$' | '_' | '%' => true,
+        _ => false,
+    }
+}
+
 // Return CharClass for a char
 fn char_class(c: char) -> CharClass {
     use CharClass::*;
@@ -143,6 +201,18 @@ mod tests {
         assert_eq!(WhiteSpace,   char_class('\n'));
     }
 
+    #[test]
+    fn test_ncname() {
+        assert!(is_ncname("p"));
+        assert!(is_ncname("_a-b.c\u{B7}9"));
+        assert!(is_ncname("\u{3A8}"));
+        assert!(!is_ncname(""));
+        assert!(!is_ncname("a:b"));
+        assert!(!is_ncname("a<b"));
+        assert!(!is_ncname("-a"));
+        assert!(!is_ncname("9"));
+    }
+
     #[test]
     fn replace() {
         assert_clean("",  "" );
@@ -13,7 +13,7 @@
 use std::borrow::Cow;
 use std::io;
 
-use log::trace;
+use log::{info, trace};
 use encoding_rs as enc;
 use enc::DecoderResult;
 
@@ -22,12 +22,21 @@ use tendril::fmt as form;
 use tendril::stream::Utf8LossyDecoder;
 
 mod encoding_hint;
+pub(crate) mod media_type;
+mod report;
 
 pub use encoding_hint::{
-    EncodingHint, SharedEncodingHint,
+    EncodingHint, Hint, HintSource, SharedEncodingHint,
 };
+pub use report::ParseReport;
 
-use crate::READ_BUFFER_SIZE;
+use crate::{BOM_CONF, INITIAL_BUFFER_SIZE, READ_BUFFER_SIZE};
+
+#[cfg(feature = "detect")]
+use crate::DETECT_CONF;
+
+#[cfg(feature = "detect")]
+use log::debug;
 
 /// A `TendrilSink` adaptor that takes bytes, decodes them as the given
 /// character encoding, while replacing any ill-formed byte sequences with
@@ -132,6 +141,336 @@ impl<Sink, A> TendrilSink<form::Bytes, A> for Decoder<Sink, A>
     }
 }
 
+/// Read and decode the given stream until end, processing with a sink
+/// returned by `new_sink`.
+///
+/// The [`SharedEncodingHint`] must have a top (e.g. default) encoding, which
+/// will be used initially for decoding bytes. The [`INITIAL_BUFFER_SIZE`]
+/// bytes of the stream are buffered and if a compelling alternative encoding
+/// hint is found via a leading Byte-Order-Mark (BOM) or by the sink, the
+/// processing is restarted from the beginning with that encoding and a new
+/// sink, and continued until the end. The `new_sink` parameter is true for the
+/// initial sink, which should check for and add encoding hints, and false for
+/// any restart. Returns the sink output and, if restarted, the count of
+/// decoding errors prior to the restart.
+pub(crate) fn read_buffered<S, F, R>(
+    hint: &SharedEncodingHint,
+    r: &mut R,
+    new_sink: F)
+    -> Result<(S::Output, Option<u32>), io::Error>
+    where S: TendrilSink<form::UTF8>,
+          F: Fn(bool) -> S,
+          R: io::Read
+{
+    let mut decoder = BufferedDecoder::new(hint.clone(), new_sink);
+    while decoder.read_chunk(r)? {}
+    Ok(decoder.finish())
+}
+
+/// Read and decode the given asynchronous stream until end, as per
+/// [`read_buffered`], yielding to the executor after processing each
+/// chunk.
+#[cfg(feature = "async")]
+pub(crate) async fn read_buffered_async<S, F, R>(
+    hint: &SharedEncodingHint,
+    r: &mut R,
+    new_sink: F)
+    -> Result<(S::Output, Option<u32>), io::Error>
+    where S: TendrilSink<form::UTF8>,
+          F: Fn(bool) -> S,
+          R: tokio::io::AsyncRead + Unpin
+{
+    let mut decoder = BufferedDecoder::new(hint.clone(), new_sink);
+    while decoder.read_chunk_async(r).await? > 0 {}
+    Ok(decoder.finish())
+}
+
+// A future which yields to the executor once, before completing, like
+// `tokio::task::yield_now` but without requiring a runtime.
+#[cfg(feature = "async")]
+struct YieldNow(bool);
+
+#[cfg(feature = "async")]
+impl std::future::Future for YieldNow {
+    type Output = ();
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>)
+        -> std::task::Poll<()>
+    {
+        if self.0 {
+            std::task::Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            std::task::Poll::Pending
+        }
+    }
+}
+
+// A push-based decoder of bytes of unknown encoding, processing with a sink
+// returned by `new_sink`, as described for `read_buffered`.
+//
+// This is a `TendrilSink` for bytes, with output of the sink output and, if
+// restarted, the count of decoding errors prior to the restart.
+pub(crate) struct BufferedDecoder<S, F>
+    where S: TendrilSink<form::UTF8>, F: Fn(bool) -> S
+{
+    hint: SharedEncodingHint,
+    new_sink: F,
+    decoder: Option<Decoder<S>>,
+    // The initial bytes, while buffering
+    buff: Option<Tendril<form::Bytes>>,
+    prior_errors: Option<u32>,
+}
+
+impl<S, F> BufferedDecoder<S, F>
+    where S: TendrilSink<form::UTF8>, F: Fn(bool) -> S
+{
+    pub(crate) fn new(hint: SharedEncodingHint, new_sink: F) -> Self {
+        let enc = hint.borrow().top()
+            .expect("EnodingHint default encoding required");
+
+        // Decoders are "Sink adaptors" that also impl TendrilSink.
+        // The decoder is consumed to finish the parse.
+        let decoder = Some(Decoder::new(enc, new_sink(true)));
+        BufferedDecoder {
+            hint,
+            new_sink,
+            decoder,
+            buff: Some(Tendril::with_capacity(INITIAL_BUFFER_SIZE)),
+            prior_errors: None,
+        }
+    }
+
+    /// Return true while the initial bytes are buffered, during which the
+    /// sink may yet be replaced on restart.
+    #[cfg(feature = "xml")]
+    pub(crate) fn is_buffering(&self) -> bool {
+        self.buff.is_some()
+    }
+
+    /// Read and process the next chunk of the given stream, retrying on
+    /// interrupt. Returns false (without processing) at the end of the
+    /// stream, after which this should be finished.
+    pub(crate) fn read_chunk<R>(&mut self, r: &mut R) -> Result<bool, io::Error>
+        where R: io::Read
+    {
+        let (mut tendril, size) = self.read_buffer();
+        loop {
+            match r.read(&mut tendril) {
+                Ok(n) => return Ok(self.process_read(tendril, size, n)),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e)
+            }
+        }
+    }
+
+    /// Read and process the next chunk of the given asynchronous stream, as
+    /// per `read_chunk`, yielding to the executor after processing. Returns
+    /// the number of bytes read, or 0 (without processing) at the end of the
+    /// stream.
+    #[cfg(feature = "async")]
+    pub(crate) async fn read_chunk_async<R>(&mut self, r: &mut R)
+        -> Result<usize, io::Error>
+        where R: tokio::io::AsyncRead + Unpin
+    {
+        use tokio::io::AsyncReadExt;
+
+        let (mut tendril, size) = self.read_buffer();
+        let n = loop {
+            match r.read(&mut tendril).await {
+                Ok(n) => break n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e)
+            }
+        }; // repeat on interrupt
+        if self.process_read(tendril, size, n) {
+            YieldNow(false).await;
+        }
+        Ok(n)
+    }
+
+    // Return a new buffer for reading, and its size: up to the remaining
+    // initial buffer size while buffering, and thereafter READ_BUFFER_SIZE.
+    fn read_buffer(&self) -> (Tendril<form::Bytes>, u32) {
+        let size = match self.buff {
+            Some(ref buff) => INITIAL_BUFFER_SIZE - buff.len32(),
+            None => READ_BUFFER_SIZE,
+        };
+        let mut tendril = Tendril::<form::Bytes>::new();
+        unsafe {
+            tendril.push_uninitialized(size);
+        }
+        (tendril, size)
+    }
+
+    // Process n bytes read into a buffer of the given size, returning false
+    // if n is 0 (the end of the stream).
+    fn process_read(
+        &mut self,
+        mut tendril: Tendril<form::Bytes>,
+        size: u32,
+        n: usize)
+        -> bool
+    {
+        if n == 0 {
+            trace!("read 0 bytes (end)");
+            return false;
+        }
+        trace!("read {} bytes", n);
+        tendril.pop_back(size - n as u32);
+        self.process(tendril);
+        true
+    }
+
+    // End buffering, after (at most) the INITIAL_BUFFER_SIZE bytes or earlier
+    // with a compelling hint. If the top encoding has changed, restart with a
+    // new decoder and sink, and re-process the buffered bytes. If finished is
+    // given, it is the output of the original decoder, already finished at
+    // the end of the stream.
+    fn end_buffering(&mut self, finished: Option<S::Output>)
+        -> Option<S::Output>
+    {
+        let buff = self.buff.take().unwrap();
+
+        // Statistical detection of the buffered bytes, or entire stream if
+        // already finished, unless a BOM was found. Detection only guesses
+        // ASCII-compatible encodings, so it is ignored if the top encoding
+        // is otherwise (e.g. UTF-16).
+        #[cfg(feature = "detect")]
+        {
+            if bom_enc(&buff).is_none() {
+                if let Some(enc) = detect(&buff, finished.is_some()) {
+                    let mut hint = self.hint.borrow_mut();
+                    if hint.could_read_from(enc) {
+                        hint.add_hint_from(
+                            enc, DETECT_CONF, HintSource::Detection
+                        );
+                    }
+                }
+            }
+        }
+
+        let (changed, errors) = {
+            let hint = self.hint.borrow();
+            trace!("revised hint: {:?}", hint);
+            (hint.changed(), hint.errors())
+        };
+
+        if let Some(enc) = changed {
+            info!(
+                "Reparsing with enc {}, buffered: {}, prior enc errors: {}",
+                enc.name(), buff.len(), errors
+            );
+            self.hint.borrow_mut().clear_errors();
+            self.prior_errors = Some(errors);
+
+            // Replace decoder and re-process, consuming the tendril buffer.
+            let mut decoder = Decoder::new(enc, (self.new_sink)(false));
+            decoder.process(buff);
+            if finished.is_some() {
+                return Some(decoder.finish());
+            }
+            self.decoder = Some(decoder);
+            None
+        } else {
+            finished
+        }
+    }
+}
+
+impl<S, F> TendrilSink<form::Bytes> for BufferedDecoder<S, F>
+    where S: TendrilSink<form::UTF8>, F: Fn(bool) -> S
+{
+    type Output = (S::Output, Option<u32>);
+
+    fn process(&mut self, mut t: Tendril<form::Bytes>) {
+        if t.is_empty() {
+            return;
+        }
+        if let Some(ref mut buff) = self.buff {
+            let i = buff.len32();
+            let n = std::cmp::min(t.len32(), INITIAL_BUFFER_SIZE - i);
+            buff.push_slice(&t[..n as usize]);
+
+            // One time, leading Byte-order-mark (BOM) detection for UTF-16
+            // little/big endian, or UTF-8, after buffering initial 3 bytes.
+            // This is part of the `decode` algorithm of the Encoding
+            // Standard which is not implemented by either encoding_rs or
+            // html5ever. html5ever will ignore a BOM character so we need
+            // not remove it before processing.  If the new hint is
+            // compelling, then end buffering early to reprocess with a new
+            // decoder.
+            let mut compelling = false;
+            if i < 3 && (i + n) >= 3 {
+                if let Some(enc) = bom_enc(buff) {
+                    compelling = self.hint.borrow_mut()
+                        .add_hint_from(enc, BOM_CONF, HintSource::Bom);
+                }
+            }
+            if !compelling {
+                self.decoder.as_mut().unwrap().process(t.subtendril(0, n));
+            }
+            let full = i + n == INITIAL_BUFFER_SIZE;
+            if compelling || full || self.hint.borrow().changed().is_some() {
+                self.end_buffering(None);
+            }
+            t.pop_front(n);
+            if t.is_empty() {
+                return;
+            }
+        }
+        self.decoder.as_mut().unwrap().process(t);
+    }
+
+    fn error(&mut self, desc: Cow<'static, str>) {
+        self.decoder.as_mut().unwrap().error(desc);
+    }
+
+    fn finish(mut self) -> Self::Output {
+        let output = self.decoder.take().unwrap().finish();
+        let output = if self.buff.is_some() {
+            self.end_buffering(Some(output)).unwrap()
+        } else {
+            output
+        };
+        (output, self.prior_errors)
+    }
+}
+
+// Return the encoding detected for the given bytes, if any non-ASCII bytes are
+// found, and the detected encoding scores higher than some alternative.
+#[cfg(feature = "detect")]
+fn detect(buff: &[u8], last: bool) -> Option<&'static enc::Encoding> {
+    let mut detector = chardetng::EncodingDetector::new();
+    if !detector.feed(buff, last) {
+        trace!("detect: only ASCII found");
+        return None;
+    }
+    let (enc, assured) = detector.guess_assess(None, true);
+    debug!("detect: guessed {} (assured: {})", enc.name(), assured);
+    if assured {
+        Some(enc)
+    } else {
+        None
+    }
+}
+
+// Return encoding for any Byte-Order-Mark found at start of buff.
+pub(crate) fn bom_enc(buff: &[u8]) -> Option<&'static enc::Encoding> {
+    if buff.len() < 3 {
+        return None;
+    }
+    match (buff[0], buff[1], buff[2]) {
+        (0xFE, 0xFF,    _) => Some(enc::UTF_16BE),
+        (0xFF, 0xFE,    _) => Some(enc::UTF_16LE),
+        (0xEF, 0xBB, 0xBF) => Some(enc::UTF_8),
+        _ => None
+    }
+}
+
 fn decode_to_sink<Sink, A>(
     mut inpt: Tendril<form::Bytes, A>,
     decoder: &mut enc::Decoder,
@@ -4,8 +4,10 @@ use std::collections::HashMap;
 use std::rc::Rc;
 
 use encoding_rs as enc;
+use mime::Mime;
 
-use crate::DEFAULT_CONF;
+use crate::decode::media_type::parse_media_type;
+use crate::{DEFAULT_CONF, HTTP_CTYPE_CONF};
 
 /// A set of confidence-weighted evidence that a text document is in a
 /// particular encoding.
@@ -16,6 +18,48 @@ pub struct EncodingHint {
     confidence: f32,
     errors: u32,
     changed: bool,
+    hints: Vec<Hint>,
+}
+
+/// The source of an encoding hint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HintSource {
+    /// An initial default, as per [`EncodingHint::shared_default`].
+    Default,
+
+    /// The charset of an HTTP Content-Type header, as per
+    /// [`EncodingHint::add_content_type_hint`].
+    HttpContentType,
+
+    /// An HTML `<meta>` element `charset` or `http-equiv` Content-Type.
+    Meta,
+
+    /// The encoding of an XML declaration.
+    XmlDeclaration,
+
+    /// A leading Byte-Order-Mark (BOM).
+    Bom,
+
+    /// Statistical detection (with the _detect_ feature).
+    Detection,
+
+    /// A hint added via [`EncodingHint::add_hint`],
+    /// [`EncodingHint::add_label_hint`] or
+    /// [`EncodingHint::shared_with_hint`], of unspecified source.
+    Other,
+}
+
+/// A single hint, as recorded by `EncodingHint`.
+#[derive(Clone, Copy, Debug)]
+pub struct Hint {
+    /// The hinted encoding.
+    pub encoding: &'static enc::Encoding,
+
+    /// The confidence of this hint.
+    pub confidence: f32,
+
+    /// Where the hint came from.
+    pub source: HintSource,
 }
 
 /// An `EncodingHint` that can be shared between `Decoder` and `Sink`, by
@@ -32,6 +76,7 @@ impl EncodingHint {
             confidence: 0.0,
             errors: 0,
             changed: false,
+            hints: Vec::new(),
         }
     }
 
@@ -39,7 +84,7 @@ impl EncodingHint {
     /// [`DEFAULT_CONF`] confidence, wrapped for sharing.
     pub fn shared_default(enc: &'static enc::Encoding) -> SharedEncodingHint {
         let mut eh = EncodingHint::new();
-        eh.add_hint(enc, DEFAULT_CONF);
+        eh.add_hint_from(enc, DEFAULT_CONF, HintSource::Default);
         eh.clear_changed();
         Rc::new(RefCell::new(eh))
     }
@@ -55,6 +100,23 @@ impl EncodingHint {
         Rc::new(RefCell::new(eh))
     }
 
+    /// Construct a new Encoding hint with the specified default encoding at
+    /// [`DEFAULT_CONF`] confidence, and any charset of the given HTTP
+    /// Content-Type header value at [`HTTP_CTYPE_CONF`], wrapped for sharing.
+    /// Also returns the parsed media type, as per
+    /// [`EncodingHint::add_content_type_hint`].
+    pub fn shared_with_content_type(
+        enc: &'static enc::Encoding,
+        content_type: &str)
+        -> (SharedEncodingHint, Option<Mime>)
+    {
+        let mut eh = EncodingHint::new();
+        eh.add_hint_from(enc, DEFAULT_CONF, HintSource::Default);
+        let mtype = eh.add_content_type_hint(content_type);
+        eh.clear_changed();
+        (Rc::new(RefCell::new(eh)), mtype)
+    }
+
     /// Add a hint for an encoding, by label ASCII-intepreted bytes, and some
     /// positive confidence value.  If no encoding (or applicable replacement)
     /// is found for the specified label, returns false.  Return true if an
@@ -70,13 +132,51 @@ impl EncodingHint {
         }
     }
 
+    /// Add a hint from an HTTP Content-Type header value, e.g. `text/html;
+    /// charset=Shift_JIS`, with [`HTTP_CTYPE_CONF`] confidence.
+    ///
+    /// The value is parsed as a media type per RFC 7231, with
+    /// case-insensitive type, subtype and parameter names, and quoted or
+    /// unquoted parameter values. Malformed parameters are ignored.  A hint is
+    /// only added if a `charset` parameter is found with a known encoding
+    /// label. Returns the parsed media type, if valid, regardless of any
+    /// charset, for example to distinguish HTML from XML content.
+    pub fn add_content_type_hint(&mut self, content_type: &str)
+        -> Option<Mime>
+    {
+        let mtype = parse_media_type(content_type)?;
+        if let Some(cs) = mtype.get_param(mime::CHARSET) {
+            let label = cs.as_str().trim().as_bytes();
+            if let Some(enc) = enc::Encoding::for_label(label) {
+                self.add_hint_from(
+                    enc, HTTP_CTYPE_CONF, HintSource::HttpContentType
+                );
+            }
+        }
+        Some(mtype)
+    }
+
     /// Add a hint for the specified encoding and some positive confidence
     /// value. Return true if this hint changes the top most confident
     /// encoding.
     pub fn add_hint(&mut self, enc: &'static enc::Encoding, confidence: f32)
         -> bool
+    {
+        self.add_hint_from(enc, confidence, HintSource::Other)
+    }
+
+    /// Add a hint for the specified encoding, some positive confidence value
+    /// and its source. Return true if this hint changes the top most
+    /// confident encoding.
+    pub fn add_hint_from(
+        &mut self,
+        enc: &'static enc::Encoding,
+        confidence: f32,
+        source: HintSource)
+        -> bool
     {
         assert!(confidence > 0.0);
+        self.hints.push(Hint { encoding: enc, confidence, source });
 
         let new_conf = *(
             self.encodings.entry(enc)
@@ -130,6 +230,32 @@ impl EncodingHint {
         self.top
     }
 
+    /// Return the source contributing the most confidence to the top
+    /// encoding, if at least one encoding has been hinted.
+    pub fn top_source(&self) -> Option<HintSource> {
+        let top = self.top?;
+        let mut sources: Vec<(HintSource, f32)> = Vec::new();
+        for h in self.hints.iter().filter(|h| h.encoding == top) {
+            if let Some(s) = sources.iter_mut().find(|s| s.0 == h.source) {
+                s.1 += h.confidence;
+            } else {
+                sources.push((h.source, h.confidence));
+            }
+        }
+        let mut best: Option<(HintSource, f32)> = None;
+        for &(s, c) in &sources {
+            if best.map_or(true, |b| c > b.1) {
+                best = Some((s, c));
+            }
+        }
+        best.map(|b| b.0)
+    }
+
+    /// Return all hints added, in order.
+    pub fn hints(&self) -> &[Hint] {
+        &self.hints
+    }
+
     /// Return the summed confidence value for the top (most confident)
     /// encoding. Returns 0.0 if no hint has been provided.
     pub fn confidence(&self) -> f32 {
@@ -219,6 +345,79 @@ mod tests {
         assert_eq!(0.3 + 0.4, encs.confidence());
     }
 
+    #[test]
+    fn content_type_hint() {
+        let mut eh = EncodingHint::new();
+        let m = eh.add_content_type_hint("text/html; charset=Shift_JIS");
+        assert_eq!("text/html", m.unwrap().essence_str());
+        assert_eq!(enc::SHIFT_JIS, eh.top().unwrap());
+        assert_eq!(HTTP_CTYPE_CONF, eh.confidence());
+
+        let mut eh = EncodingHint::new();
+        let m = eh.add_content_type_hint(
+            "Application/XHTML+XML;Charset=\"ISO-8859-5\"");
+        let m = m.unwrap();
+        assert_eq!("application/xhtml+xml", m.essence_str());
+        assert_eq!(Some(mime::XML), m.suffix());
+        assert_eq!(enc::ISO_8859_5, eh.top().unwrap());
+
+        let mut eh = EncodingHint::new();
+        let m = eh.add_content_type_hint("text/plain");
+        assert_eq!("text/plain", m.unwrap().essence_str());
+        assert_eq!(None, eh.top());
+    }
+
+    #[test]
+    fn content_type_hint_malformed() {
+        for ctype in &[
+            "text/html; charset",
+            "text/html;;charset=utf-8;",
+            "text/html; foo=; charset=utf-8",
+            "text/html; charset=\"utf-8",
+            "text/html; foo=\"a;b\"; charset = utf-8",
+            "text/html; foo bar=1; charset=utf-8",
+            "text/html; charset=utf-8; title=a b",
+        ] {
+            let mut eh = EncodingHint::new();
+            let m = eh.add_content_type_hint(ctype);
+            assert_eq!("text/html", m.expect(ctype).essence_str(), "{}", ctype);
+            if ctype.ends_with("charset") {
+                assert_eq!(None, eh.top(), "{}", ctype);
+            } else {
+                assert_eq!(Some(enc::UTF_8), eh.top(), "{}", ctype);
+            }
+        }
+
+        let mut eh = EncodingHint::new();
+        assert_eq!(None, eh.add_content_type_hint("html; charset=utf-8"));
+        assert_eq!(None, eh.add_content_type_hint(""));
+        assert_eq!(None, eh.top());
+    }
+
+    #[test]
+    fn shared_with_content_type() {
+        let (eh, m) = EncodingHint::shared_with_content_type(
+            enc::UTF_8, "text/xml; charset=windows-1251");
+        assert_eq!("text/xml", m.unwrap().essence_str());
+        let eh = eh.borrow();
+        assert_eq!(enc::WINDOWS_1251, eh.top().unwrap());
+        assert_eq!(None, eh.changed());
+    }
+
+    #[test]
+    fn top_source() {
+        let mut eh = EncodingHint::new();
+        assert_eq!(None, eh.top_source());
+        eh.add_hint_from(enc::UTF_8, DEFAULT_CONF, HintSource::Default);
+        assert_eq!(Some(HintSource::Default), eh.top_source());
+        eh.add_hint_from(enc::SHIFT_JIS, 0.05, HintSource::Meta);
+        eh.add_hint_from(enc::SHIFT_JIS, 0.05, HintSource::Meta);
+        eh.add_content_type_hint("text/html; charset=Shift_JIS");
+        assert_eq!(Some(HintSource::Meta), eh.top_source());
+        assert_eq!(4, eh.hints().len());
+        assert_eq!(HintSource::HttpContentType, eh.hints()[3].source);
+    }
+
     #[test]
     fn could_read_from() {
         let mut eh = EncodingHint::new();
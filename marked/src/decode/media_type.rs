@@ -0,0 +1,113 @@
+use log::debug;
+use mime::Mime;
+
+/// Parse a media type, as found in an HTTP Content-Type header or HTML
+/// `http-equiv` meta element, per RFC 7231 (section 3.1.1.1).
+///
+/// Type, subtype and parameter names are case-insensitive and parameter
+/// values may be quoted. If the value is malformed, then any malformed
+/// parameters are dropped and parsing retried, so that the type and any
+/// remaining (e.g. charset) parameters are still returned. Returns `None` if
+/// the type/subtype itself is malformed.
+pub(crate) fn parse_media_type(value: &str) -> Option<Mime> {
+    let value = value.trim();
+    match value.parse::<Mime>() {
+        Ok(m) => Some(m),
+        Err(e) => {
+            debug!("media type {:?}: {}, retrying leniently", value, e);
+            let mut parts = split_params(value).into_iter();
+            let essence = parts.next()?.trim();
+            let mut repaired = essence.to_owned();
+            for param in parts {
+                if let Some((name, pval)) = repair_param(param) {
+                    repaired.push_str("; ");
+                    repaired.push_str(name);
+                    repaired.push('=');
+                    repaired.push_str(&pval);
+                }
+            }
+            repaired.parse::<Mime>()
+                .or_else(|_| essence.parse::<Mime>())
+                .ok()
+        }
+    }
+}
+
+// Split value on ';' delimiters outside of quoted strings.
+fn split_params(value: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if quoted && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if c == ';' && !quoted {
+            parts.push(&value[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+// Return a well-formed (name, value) for the given parameter, with the value
+// quoted if necessary, or None if it can't be repaired.
+fn repair_param(param: &str) -> Option<(&str, String)> {
+    let param = param.trim();
+    if param.is_empty() {
+        return None;
+    }
+    let eq = param.find('=')?;
+    let name = param[..eq].trim();
+    if name.is_empty() || !name.chars().all(is_token_char) {
+        return None;
+    }
+    let value = param[eq+1..].trim();
+    let value = if value.starts_with('"') {
+        // Unescape, tolerating a missing end quote.
+        let mut unq = String::with_capacity(value.len());
+        let mut escaped = false;
+        for c in value[1..].chars() {
+            if escaped {
+                unq.push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                break;
+            } else {
+                unq.push(c);
+            }
+        }
+        unq
+    } else {
+        value.to_owned()
+    };
+    if value.is_empty() || value.chars().any(|c| c.is_control()) {
+        return None;
+    }
+    if value.chars().all(is_token_char) {
+        Some((name, value))
+    } else {
+        let mut q = String::with_capacity(value.len() + 2);
+        q.push('"');
+        for c in value.chars() {
+            if c == '"' || c == '\\' {
+                q.push('\\');
+            }
+            q.push(c);
+        }
+        q.push('"');
+        Some((name, q))
+    }
+}
+
+// Is c a valid "tchar" of an RFC 7230 token?
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
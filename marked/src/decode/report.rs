@@ -0,0 +1,81 @@
+use encoding_rs as enc;
+
+use crate::decode::{EncodingHint, Hint, HintSource};
+use crate::html::ParseErrors;
+
+/// A report of the encoding decision and any errors of a buffered parse, as
+/// returned by
+/// [`html::parse_buffered_with_report`](crate::html::parse_buffered_with_report).
+#[derive(Clone, Debug)]
+pub struct ParseReport {
+    encoding: &'static enc::Encoding,
+    source: HintSource,
+    confidence: f32,
+    hints: Vec<Hint>,
+    prior_replacements: Option<u32>,
+    replacements: u32,
+    parse_errors: ParseErrors,
+}
+
+impl ParseReport {
+    pub(crate) fn new(
+        hint: &EncodingHint,
+        prior_replacements: Option<u32>,
+        parse_errors: ParseErrors)
+        -> ParseReport
+    {
+        ParseReport {
+            encoding: hint.top().expect("EncodingHint top encoding"),
+            source: hint.top_source().expect("EncodingHint top source"),
+            confidence: hint.confidence(),
+            hints: hint.hints().to_vec(),
+            prior_replacements,
+            replacements: hint.errors(),
+            parse_errors,
+        }
+    }
+
+    /// Return the final encoding used to decode the document.
+    pub fn encoding(&self) -> &'static enc::Encoding {
+        self.encoding
+    }
+
+    /// Return the source contributing the most confidence to the final
+    /// encoding.
+    pub fn source(&self) -> HintSource {
+        self.source
+    }
+
+    /// Return the summed confidence of the final encoding.
+    pub fn confidence(&self) -> f32 {
+        self.confidence
+    }
+
+    /// Return all encoding hints considered, in the order added.
+    pub fn hints(&self) -> &[Hint] {
+        &self.hints
+    }
+
+    /// Return true if the parse was restarted with a new encoding.
+    pub fn reparsed(&self) -> bool {
+        self.prior_replacements.is_some()
+    }
+
+    /// Return the count of ill-formed byte sequences which were replaced with
+    /// U+FFFD replacement characters, when decoding with the final encoding.
+    pub fn replacements(&self) -> u32 {
+        self.replacements
+    }
+
+    /// If the parse was restarted, return the count of replacements made with
+    /// the prior encoding, before the restart.
+    pub fn prior_replacements(&self) -> Option<u32> {
+        self.prior_replacements
+    }
+
+    /// Return the (non-fatal) parse errors reported by the parser, excluding
+    /// decoding errors.
+    pub fn parse_errors(&self) -> &ParseErrors {
+        &self.parse_errors
+    }
+}
@@ -9,6 +9,7 @@
 
 //! An efficient and simple DOM-like container and associated tools.
 
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::fmt;
 use std::iter;
@@ -19,23 +20,50 @@ use std::ops::{Deref, DerefMut};
 #[doc(no_inline)]
 pub use html5ever::{Attribute, LocalName, Namespace, QualName};
 
+#[doc(no_inline)]
+pub use html5ever::interface::QuirksMode;
+
 #[doc(no_inline)]
 pub use tendril::StrTendril;
 
 // custom ordering of these effects rustdoc for Document, etc.
 
+mod id_map;
+mod limits;
+mod node_mut;
 mod node_ref;
+mod pretty;
 mod serializer;
+mod snapshot;
+mod span;
+mod structural;
+mod template;
+mod xml_serializer;
+pub mod diff;
 #[macro_use] pub mod filter;
 pub mod html;
+pub mod xpath;
 
 #[cfg(feature = "xml")]
 pub mod xml;
 
+#[cfg(feature = "css")]
+pub mod css;
+
+#[cfg(feature = "serde")]
+mod serde;
+
 #[cfg(test)]
 mod tests;
 
+pub use id_map::NodeIdMap;
+pub use limits::{Limit, LimitError, ParseLimits};
+pub use node_mut::NodeMut;
 pub use node_ref::{NodeRef, Descender, Selector};
+pub use pretty::PrettyOptions;
+pub use span::{SourcePos, SourceSpan};
+pub use structural::CompareOptions;
+pub use xml_serializer::XmlOptions;
 
 /// A DOM-like container for a tree of markup elements and text.
 ///
@@ -50,6 +78,11 @@ pub use node_ref::{NodeRef, Descender, Selector};
 /// nodes, including the [`Document::root_element()`], if present.
 pub struct Document {
     nodes: Vec<Node>,
+    // Optional side table of source spans, by node index
+    spans: Vec<Option<SourceSpan>>,
+    // Side table of HTML template contents, by template element
+    templates: BTreeMap<NodeId, Document>,
+    quirks_mode: QuirksMode,
 }
 
 /// A `Node` identifier as a u32 index into a `Document`s `Node` vector.
@@ -100,12 +133,20 @@ pub enum NodeData {
 #[derive(Clone, Debug)]
 pub struct DocumentType {
     pub name: StrTendril,
+
+    /// The public identifier, or empty if none.
+    pub public_id: StrTendril,
+
+    /// The system identifier, or empty if none.
+    pub system_id: StrTendril,
+
     _priv: ()
 }
 
 /// Processing instruction details.
 #[derive(Clone, Debug)]
 pub struct ProcessingInstruction {
+    pub target: StrTendril,
     pub data: StrTendril,
     _priv: ()
 }
@@ -139,7 +180,12 @@ impl Document {
         let mut nodes = Vec::with_capacity(count as usize);
         nodes.push(Node::new(NodeData::Hole));     // Index 0: Padding
         nodes.push(Node::new(NodeData::Document)); // Index 1: DOCUMENT_NODE_ID
-        Document { nodes }
+        Document {
+            nodes,
+            spans: Vec::new(),
+            templates: BTreeMap::new(),
+            quirks_mode: QuirksMode::NoQuirks,
+        }
     }
 
     /// Return total number of `Node`s.
@@ -166,6 +212,17 @@ impl Document {
         self.len() < 2
     }
 
+    /// Return the quirks mode of this `Document`, as finally determined by the
+    /// HTML parser, or `QuirksMode::NoQuirks` otherwise.
+    pub fn quirks_mode(&self) -> QuirksMode {
+        self.quirks_mode
+    }
+
+    /// Set the quirks mode of this `Document`.
+    pub fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.quirks_mode = mode;
+    }
+
     /// Return the root element `NodeId` for this Document, or None if there is
     /// no such qualified element.
     ///
@@ -226,6 +283,16 @@ impl Document {
         NodeId(unsafe { NonZeroU32::new_unchecked(next_index) })
     }
 
+    // Push an unlinked document node, as the root of nodes to be moved to a
+    // separate fragment (e.g. template contents) while parsing.
+    fn push_fragment_root(&mut self) -> NodeId {
+        let next_index = self.nodes.len()
+            .try_into()
+            .expect("Document (u32) node index overflow");
+        self.nodes.push(Node::new(NodeData::Document));
+        NodeId(unsafe { NonZeroU32::new_unchecked(next_index) })
+    }
+
     /// Detach the specified node ID and return it and its children moved into
     /// a new independent `Document` fragment.
     ///
@@ -291,16 +358,70 @@ impl Document {
             let nid = self.insert_before_sibling(
                 sibling,
                 Node::new(onode.take_data()));
+            self.copy_source_span(nid, &other, oid);
+            self.move_template_contents(nid, &mut other, oid);
             for coid in other.children(oid).collect::<Vec<_>>() {
                 self.append_move(nid, &mut other, coid);
             }
         }
     }
 
+    /// Replace the specified node ID and its descendants with the contents of
+    /// an other `Document`, e.g. as obtained from [`Document::deep_clone`],
+    /// and return the replaced `NodeData`.
+    ///
+    /// The `Document` is consumed (its contents moved to self) and its nodes
+    /// are inserted in order, in place of the replaced node. Panics if called
+    /// with the synthetic DOCUMENT_NODE_ID. As with [`Document::unlink`], the
+    /// replaced node's descendants are left in place but un-referenced.
+    pub fn replace(&mut self, id: NodeId, other: Document) -> NodeData {
+        assert!(
+            id != Document::DOCUMENT_NODE_ID,
+            "Can't replace the synthetic document node");
+        self.attach_before_sibling(id, other);
+        self.unlink(id)
+    }
+
+    /// Wrap the specified node ID in a new element, inserted in its place,
+    /// and return the new element's ID.
+    ///
+    /// Panics if the node has no parent, e.g. the synthetic DOCUMENT_NODE_ID.
+    pub fn wrap(&mut self, id: NodeId, element: Element) -> NodeId {
+        self.wrap_range(id, id, element)
+    }
+
+    /// Wrap the sibling nodes from first to last, inclusive, in a new element,
+    /// inserted in their place, and return the new element's ID.
+    ///
+    /// Panics if the nodes have no parent or if last is not first or a
+    /// following sibling of first.
+    pub fn wrap_range(&mut self, first: NodeId, last: NodeId, element: Element)
+        -> NodeId
+    {
+        assert!(
+            self.node_and_following_siblings(first).any(|id| id == last),
+            "wrap_range last isn't first or a following sibling");
+
+        let wrapper = self.insert_before_sibling(
+            first,
+            Node::new_elem(element));
+        let mut next = Some(first);
+        while let Some(id) = next {
+            next = self[id].next_sibling;
+            self.append(wrapper, id);
+            if id == last {
+                break;
+            }
+        }
+        wrapper
+    }
+
     /// Move node oid in odoc and all its descendants, appending to id in
     /// self.
     fn append_move(&mut self, id: NodeId, odoc: &mut Document, oid: NodeId) {
         let id = self.append_child(id, Node::new(odoc[oid].take_data()));
+        self.copy_source_span(id, odoc, oid);
+        self.move_template_contents(id, odoc, oid);
         let mut ns = NodeStack2::new();
         ns.push_if(odoc[oid].first_child, id);
 
@@ -309,6 +430,8 @@ impl Document {
             let nid = self.append_child(id, Node::new(onode.take_data()));
             ns.push_if(onode.next_sibling, id);
             ns.push_if(onode.first_child, nid);
+            self.copy_source_span(nid, odoc, oid);
+            self.move_template_contents(nid, odoc, oid);
         }
     }
 
@@ -380,6 +503,17 @@ impl Document {
         self[parent].last_child = Some(new_child);
     }
 
+    /// Prepend node as new first child of given parent, and return its new ID.
+    pub fn prepend_child(&mut self, parent: NodeId, node: Node)
+        -> NodeId
+    {
+        if let Some(first) = self[parent].first_child {
+            self.insert_before_sibling(first, node)
+        } else {
+            self.append_child(parent, node)
+        }
+    }
+
     /// Insert node before the given sibling and return its new ID.
     pub fn insert_before_sibling(&mut self, sibling: NodeId, node: Node)
         -> NodeId
@@ -477,7 +611,27 @@ impl Document {
 
     /// Compact in place, by removing `Node`s that are no longer referenced
     /// from the document node.
+    ///
+    /// Note that all remaining nodes are renumbered, in tree order. Use
+    /// [`Document::compact_with_map`] to translate retained `NodeId`s.
     pub fn compact(&mut self) {
+        self.compact_each(|_, _| {});
+    }
+
+    /// Compact in place as per [`Document::compact`], returning a
+    /// [`NodeIdMap`] of original to new `NodeId`s. Nodes removed (e.g.
+    /// previously detached or unlinked) are mapped to `None`.
+    pub fn compact_with_map(&mut self) -> NodeIdMap {
+        let mut map = NodeIdMap::with_len(self.nodes.len());
+        self.compact_each(|id, nid| map.insert(id, nid));
+        map
+    }
+
+    // Compact in place, calling f with each original and new `NodeId`.
+    fn compact_each<F>(&mut self, mut f: F)
+        where F: FnMut(NodeId, NodeId)
+    {
+        f(Document::DOCUMENT_NODE_ID, Document::DOCUMENT_NODE_ID);
         let mut ndoc = Document::with_capacity(self.len() + 1);
         let mut ns = NodeStack2::new();
         ns.push_if(
@@ -487,6 +641,9 @@ impl Document {
         while let Some((id, nid)) = ns.pop() {
             let nnode = Node::new(self[id].take_data());
             let ncid = ndoc.append_child(nid, nnode);
+            ndoc.copy_source_span(ncid, self, id);
+            ndoc.move_template_contents(ncid, self, id);
+            f(id, ncid);
             ns.push_if(self[id].next_sibling, nid);
             ns.push_if(self[id].first_child, ncid);
         }
@@ -499,6 +656,128 @@ impl Document {
         }
 
         self.nodes = ndoc.nodes;
+        self.spans = ndoc.spans;
+        self.templates = ndoc.templates;
+    }
+
+    // Check that all node links are in range and consistent, such that the
+    // document node and any other nodes without a parent are the roots of
+    // trees including all nodes, and only elements (or holes) have children.
+    // For a `Document` read from an external representation.
+    pub(crate) fn check_links(&self) -> Result<(), String> {
+        let len = self.nodes.len();
+        if len < 2 {
+            return Err("missing document node".to_owned());
+        }
+        let doc_node = &self.nodes[1];
+        if let NodeData::Document = doc_node.data {} else {
+            return Err("invalid document node".to_owned());
+        }
+        if doc_node.parent.is_some() ||
+            doc_node.prev_sibling.is_some() ||
+            doc_node.next_sibling.is_some()
+        {
+            return Err("document node with parent or siblings".to_owned());
+        }
+
+        let get = |id: Option<NodeId>| -> Result<Option<&Node>, String> {
+            match id {
+                Some(id) if id.0.get() as usize >= len => {
+                    Err(format!("node {} out of range", id.0))
+                }
+                Some(id) => Ok(Some(&self[id])),
+                None => Ok(None),
+            }
+        };
+
+        for i in 1..len {
+            let id = Some(NodeId(NonZeroU32::new(i as u32).unwrap()));
+            let node = &self.nodes[i];
+            let err = || Err(format!("node {} has inconsistent links", i));
+            if i > 1 {
+                if let NodeData::Document = node.data {
+                    return Err(format!("node {} is a document node", i));
+                }
+            }
+            if let Some(p) = get(node.parent)? {
+                // A Hole may remain the parent of an unlinked sub-tree
+                match p.data {
+                    NodeData::Document |
+                    NodeData::Elem(_) |
+                    NodeData::Hole => {}
+                    _ => {
+                        return Err(format!(
+                            "node {} has a parent which can't have children", i
+                        ));
+                    }
+                }
+            }
+            if let Some(c) = get(node.first_child)? {
+                if c.parent != id || c.prev_sibling.is_some() {
+                    return err();
+                }
+            }
+            if let Some(c) = get(node.last_child)? {
+                if c.parent != id || c.next_sibling.is_some() {
+                    return err();
+                }
+            }
+            if node.first_child.is_some() != node.last_child.is_some() {
+                return err();
+            }
+            if let Some(s) = get(node.next_sibling)? {
+                if s.prev_sibling != id || s.parent != node.parent {
+                    return err();
+                }
+            }
+            if let Some(s) = get(node.prev_sibling)? {
+                if s.next_sibling != id || s.parent != node.parent {
+                    return err();
+                }
+            }
+            if node.parent.is_none() &&
+                (node.prev_sibling.is_some() || node.next_sibling.is_some())
+            {
+                return err();
+            }
+        }
+
+        // Walk the children of each tree root (including the document node),
+        // which must reach each node exactly once, so that there are no
+        // cycles via parent links. Sibling chains are acyclic per the above.
+        let mut visited = vec![false; len];
+        let mut stack = Vec::new();
+        for i in 1..len {
+            if self.nodes[i].parent.is_none() {
+                stack.push(NodeId(NonZeroU32::new(i as u32).unwrap()));
+            }
+            while let Some(id) = stack.pop() {
+                let j = id.0.get() as usize;
+                if visited[j] {
+                    return Err(format!("node {} is linked twice", j));
+                }
+                visited[j] = true;
+                let node = &self.nodes[j];
+                let mut last = None;
+                let mut next = node.first_child;
+                while let Some(child) = next {
+                    stack.push(child);
+                    last = next;
+                    next = self[child].next_sibling;
+                }
+                if last != node.last_child {
+                    return Err(format!("node {} has inconsistent children", j));
+                }
+            }
+        }
+        if let Some(i) = visited.iter().skip(1).position(|&v| !v) {
+            return Err(format!("node {} is not reachable", i + 1));
+        }
+
+        for &id in self.templates.keys() {
+            get(Some(id))?;
+        }
+        Ok(())
     }
 
     /// Create a new `Document` from the ordered sub-tree rooted in the node
@@ -530,6 +809,8 @@ impl Document {
         oid: NodeId)
     {
         let id = self.append_child(id, Node::new(odoc[oid].data.clone()));
+        self.copy_source_span(id, odoc, oid);
+        self.clone_template_contents(id, odoc, oid);
         for child in odoc.children(oid) {
             self.append_deep_clone(id, odoc, child);
         }
@@ -542,7 +823,14 @@ impl Document {
     /// same as the original. As compared with `deep_clone(DOCUMENT_NODE_ID)`
     /// this is faster but potentially much less memory efficient.
     pub fn bulk_clone(&self) -> Document {
-        Document { nodes: self.nodes.clone() }
+        Document {
+            nodes: self.nodes.clone(),
+            spans: self.spans.clone(),
+            templates: self.templates.iter()
+                .map(|(&id, d)| (id, d.bulk_clone()))
+                .collect(),
+            quirks_mode: self.quirks_mode,
+        }
     }
 
     /// Replace the specified node ID with its children, and return the
@@ -0,0 +1,534 @@
+// Copyright © 2021 David Kellum
+//
+// The `selectors::Element` implementation here was originally derived from
+// `kuchiki::select` (version 0.8.1) source, found here:
+//
+// https://github.com/kuchiki-rs/kuchiki
+// (No copyright notice.)
+// Licensed (per Cargo.toml) under the MIT license
+
+//! Support for CSS selector queries on `Document` (_css_ feature).
+//!
+//! This module is enabled at build time via the _css_ non-default feature. It
+//! integrates the _selectors_ crate (as used by servo) for parsing and
+//! matching of CSS Selectors (level 3 and parts of level 4).
+//!
+//! A [`CssSelector`] is parsed once and may be reused for any number of
+//! matches. The [`NodeRef::select_css`] and [`Document::select_css`]
+//! convenience methods parse a selector and return the same [`Selector`]
+//! iterator as [`NodeRef::select`]. In hot loops, prefer to parse once and
+//! use [`CssSelector::matches`] as the predicate:
+//!
+//! ```no_run
+//! use marked::{css::CssSelector, html};
+//!
+//! let doc = html::parse_utf8(
+//!     b"<article><p class=lead>See <a href=/x>this</a></p></article>"
+//! );
+//! let sel = CssSelector::parse("article > p.lead a[href]")?;
+//! for link in doc.document_node_ref().select(|n| sel.matches(n)) {
+//!     assert_eq!("/x", link.attr("href").unwrap().as_ref());
+//! }
+//! # Ok::<(), marked::css::CssError>(())
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+
+use cssparser::{CowRcStr, ParseError, ParserInput, SourceLocation, ToCss};
+use selectors::attr::{
+    AttrSelectorOperation, CaseSensitivity, NamespaceConstraint
+};
+use selectors::context::QuirksMode;
+use selectors::matching::{
+    matches_selector_list, ElementSelectorFlags, MatchingContext, MatchingMode,
+};
+use selectors::parser::{SelectorList, SelectorParseErrorKind};
+use selectors::OpaqueElement;
+
+use crate::dom::{
+    html::{a, ns, t},
+    Document, LocalName, Namespace, NodeData, NodeRef, Selector,
+};
+
+/// A parsed (compiled) list of CSS selectors.
+///
+/// Cloning is inexpensive, as the parsed selectors are reference counted.
+#[derive(Clone)]
+pub struct CssSelector(SelectorList<Simple>);
+
+impl CssSelector {
+    /// Parse a CSS selector list, e.g. `"div.note > p, p.lead"`.
+    ///
+    /// Returns an error on syntax errors or for unsupported selectors,
+    /// including any pseudo-elements.
+    pub fn parse(css: &str) -> Result<CssSelector, CssError> {
+        let mut input = ParserInput::new(css);
+        let mut parser = cssparser::Parser::new(&mut input);
+        SelectorList::parse(&SimpleParser, &mut parser)
+            .map(CssSelector)
+            .map_err(CssError::from)
+    }
+
+    /// Return true if the referenced node is an element matching any of the
+    /// selectors. Returns false for all other node types.
+    pub fn matches(&self, node: &NodeRef<'_>) -> bool {
+        if node.as_element().is_none() {
+            return false;
+        }
+        let mut context = MatchingContext::new(
+            MatchingMode::Normal,
+            None,
+            None,
+            QuirksMode::NoQuirks,
+        );
+        matches_selector_list(&self.0, node, &mut context)
+    }
+}
+
+impl FromStr for CssSelector {
+    type Err = CssError;
+
+    fn from_str(css: &str) -> Result<CssSelector, CssError> {
+        CssSelector::parse(css)
+    }
+}
+
+impl fmt::Display for CssSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.to_css(f)
+    }
+}
+
+impl fmt::Debug for CssSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CssSelector({})", self)
+    }
+}
+
+/// A CSS selector parsing error.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CssError {
+    line: u32,
+    column: u32,
+    message: String,
+}
+
+impl CssError {
+    /// Return the (1-based) line number where the error was found.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// Return the (1-based) column number where the error was found.
+    pub fn column(&self) -> u32 {
+        self.column
+    }
+}
+
+impl<'i> From<ParseError<'i, SelectorParseErrorKind<'i>>> for CssError {
+    fn from(err: ParseError<'i, SelectorParseErrorKind<'i>>) -> CssError {
+        CssError {
+            line: err.location.line + 1,
+            column: err.location.column,
+            message: format!("{:?}", err.kind),
+        }
+    }
+}
+
+impl fmt::Display for CssError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid CSS selector at {}:{}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl StdError for CssError {}
+
+/// CSS selector query methods.
+impl<'a> NodeRef<'a> {
+    /// Parse the given CSS selector list and return an iterator over all
+    /// descendants of this node that match.
+    ///
+    /// This is the equivalent of [`NodeRef::select`] with a
+    /// [`CssSelector::matches`] predicate. Like `select`, when elements fail
+    /// to match, their children are scanned, depth-first, in search of all
+    /// matches, but the descendants of matching elements are _not_ scanned.
+    pub fn select_css(&self, css: &str)
+        -> Result<Selector<'a, impl FnMut(&NodeRef<'a>) -> bool + 'a>, CssError>
+    {
+        let sel = CssSelector::parse(css)?;
+        Ok(self.select(move |n| sel.matches(n)))
+    }
+
+    /// Parse the given CSS selector list and return the first matching
+    /// descendant of this node, if any.
+    ///
+    /// This is the equivalent of [`NodeRef::find`] with a
+    /// [`CssSelector::matches`] predicate.
+    pub fn find_css(&self, css: &str) -> Result<Option<NodeRef<'a>>, CssError>
+    {
+        let sel = CssSelector::parse(css)?;
+        Ok(self.find(move |n| sel.matches(n)))
+    }
+}
+
+/// CSS selector query methods.
+impl Document {
+    /// Parse the given CSS selector list and return an iterator over all
+    /// matching nodes of this `Document`.
+    ///
+    /// See [`NodeRef::select_css`] for details.
+    pub fn select_css<'a>(&'a self, css: &str)
+        -> Result<Selector<'a, impl FnMut(&NodeRef<'a>) -> bool + 'a>, CssError>
+    {
+        self.document_node_ref().select_css(css)
+    }
+}
+
+/// The `SelectorImpl` for `Document`.
+#[derive(Clone, Debug)]
+#[doc(hidden)]
+pub struct Simple;
+
+impl selectors::SelectorImpl for Simple {
+    type ExtraMatchingData = ();
+    type AttrValue = String;
+    type Identifier = LocalName;
+    type ClassName = LocalName;
+    type PartName = LocalName;
+    type LocalName = LocalName;
+    type NamespaceUrl = Namespace;
+    type NamespacePrefix = LocalName;
+    type BorrowedNamespaceUrl = Namespace;
+    type BorrowedLocalName = LocalName;
+    type NonTSPseudoClass = PseudoClass;
+    type PseudoElement = PseudoElement;
+}
+
+struct SimpleParser;
+
+impl<'i> selectors::Parser<'i> for SimpleParser {
+    type Impl = Simple;
+    type Error = SelectorParseErrorKind<'i>;
+
+    fn parse_non_ts_pseudo_class(
+        &self,
+        location: SourceLocation,
+        name: CowRcStr<'i>)
+        -> Result<PseudoClass, ParseError<'i, SelectorParseErrorKind<'i>>>
+    {
+        use PseudoClass::*;
+        let pc = match name.to_ascii_lowercase().as_str() {
+            "any-link" => AnyLink,
+            "link"     => Link,
+            "visited"  => Visited,
+            "active"   => Active,
+            "focus"    => Focus,
+            "hover"    => Hover,
+            "enabled"  => Enabled,
+            "disabled" => Disabled,
+            "checked"  => Checked,
+            _ => {
+                return Err(location.new_custom_error(
+                    SelectorParseErrorKind::UnsupportedPseudoClassOrElement(
+                        name
+                    )
+                ));
+            }
+        };
+        Ok(pc)
+    }
+}
+
+/// Supported non tree-structural pseudo-classes.
+///
+/// Dynamic user action states such as `:hover` are parsed, but never match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[doc(hidden)]
+pub enum PseudoClass {
+    AnyLink,
+    Link,
+    Visited,
+    Active,
+    Focus,
+    Hover,
+    Enabled,
+    Disabled,
+    Checked,
+}
+
+impl selectors::parser::NonTSPseudoClass for PseudoClass {
+    type Impl = Simple;
+
+    fn is_active_or_hover(&self) -> bool {
+        match self {
+            PseudoClass::Active | PseudoClass::Hover => true,
+            _ => false,
+        }
+    }
+
+    fn is_user_action_state(&self) -> bool {
+        match self {
+            PseudoClass::Active | PseudoClass::Hover |
+            PseudoClass::Focus => true,
+            _ => false,
+        }
+    }
+
+    fn has_zero_specificity(&self) -> bool {
+        false
+    }
+}
+
+impl ToCss for PseudoClass {
+    fn to_css<W>(&self, dest: &mut W) -> fmt::Result
+        where W: fmt::Write
+    {
+        dest.write_str(match self {
+            PseudoClass::AnyLink  => ":any-link",
+            PseudoClass::Link     => ":link",
+            PseudoClass::Visited  => ":visited",
+            PseudoClass::Active   => ":active",
+            PseudoClass::Focus    => ":focus",
+            PseudoClass::Hover    => ":hover",
+            PseudoClass::Enabled  => ":enabled",
+            PseudoClass::Disabled => ":disabled",
+            PseudoClass::Checked  => ":checked",
+        })
+    }
+}
+
+/// Pseudo-elements, none of which are supported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[doc(hidden)]
+pub enum PseudoElement {}
+
+impl selectors::parser::PseudoElement for PseudoElement {
+    type Impl = Simple;
+}
+
+impl ToCss for PseudoElement {
+    fn to_css<W>(&self, _dest: &mut W) -> fmt::Result
+        where W: fmt::Write
+    {
+        match *self {}
+    }
+}
+
+// The definition of whitespace per CSS Selectors Level 3 § 4.
+const SELECTOR_WHITESPACE: &[char] = &[' ', '\t', '\n', '\r', '\x0C'];
+
+impl<'a> NodeRef<'a> {
+    fn parent_elem(&self) -> Option<NodeRef<'a>> {
+        self.parent().filter(|p| p.as_element().is_some())
+    }
+
+    fn is_html_link(&self) -> bool {
+        if let Some(elm) = self.as_element() {
+            elm.name.ns == ns::HTML &&
+                (elm.is_elem(t::A) ||
+                 elm.is_elem(t::AREA) ||
+                 elm.is_elem(t::LINK)) &&
+                elm.attr(a::HREF).is_some()
+        } else {
+            false
+        }
+    }
+
+    fn is_html_form_control(&self) -> bool {
+        if let Some(elm) = self.as_element() {
+            elm.name.ns == ns::HTML && (
+                elm.is_elem(t::BUTTON) ||
+                elm.is_elem(t::INPUT) ||
+                elm.is_elem(t::SELECT) ||
+                elm.is_elem(t::TEXTAREA) ||
+                elm.is_elem(t::OPTGROUP) ||
+                elm.is_elem(t::OPTION) ||
+                elm.is_elem(t::FIELDSET))
+        } else {
+            false
+        }
+    }
+}
+
+impl<'a> selectors::Element for NodeRef<'a> {
+    type Impl = Simple;
+
+    fn opaque(&self) -> OpaqueElement {
+        OpaqueElement::new::<crate::dom::Node>(self)
+    }
+
+    fn parent_element(&self) -> Option<Self> {
+        self.parent_elem()
+    }
+
+    fn parent_node_is_shadow_root(&self) -> bool {
+        false
+    }
+
+    fn containing_shadow_host(&self) -> Option<Self> {
+        None
+    }
+
+    fn is_pseudo_element(&self) -> bool {
+        false
+    }
+
+    fn prev_sibling_element(&self) -> Option<Self> {
+        let mut prev = self.prev_sibling();
+        while let Some(p) = prev {
+            if p.as_element().is_some() {
+                return Some(p);
+            }
+            prev = p.prev_sibling();
+        }
+        None
+    }
+
+    fn next_sibling_element(&self) -> Option<Self> {
+        let mut next = self.next_sibling();
+        while let Some(n) = next {
+            if n.as_element().is_some() {
+                return Some(n);
+            }
+            next = n.next_sibling();
+        }
+        None
+    }
+
+    fn is_html_element_in_html_document(&self) -> bool {
+        self.as_element().map_or(false, |e| e.name.ns == ns::HTML)
+    }
+
+    fn has_local_name(&self, name: &LocalName) -> bool {
+        self.as_element().map_or(false, |e| e.name.local == *name)
+    }
+
+    fn has_namespace(&self, ns: &Namespace) -> bool {
+        self.as_element().map_or(false, |e| e.name.ns == *ns)
+    }
+
+    fn is_same_type(&self, other: &Self) -> bool {
+        match (self.as_element(), other.as_element()) {
+            (Some(e), Some(o)) => e.name.ns == o.name.ns &&
+                e.name.local == o.name.local,
+            _ => false,
+        }
+    }
+
+    fn attr_matches(
+        &self,
+        ns: &NamespaceConstraint<&Namespace>,
+        local_name: &LocalName,
+        operation: &AttrSelectorOperation<&String>)
+        -> bool
+    {
+        let elm = match self.as_element() {
+            Some(e) => e,
+            None => return false,
+        };
+        elm.attrs.iter().any(|attr| {
+            attr.name.local == *local_name &&
+                match ns {
+                    NamespaceConstraint::Any => true,
+                    NamespaceConstraint::Specific(ns) => attr.name.ns == **ns,
+                } &&
+                operation.eval_str(&attr.value)
+        })
+    }
+
+    fn match_non_ts_pseudo_class<F>(
+        &self,
+        pc: &PseudoClass,
+        _context: &mut MatchingContext<'_, Simple>,
+        _flags_setter: &mut F)
+        -> bool
+        where F: FnMut(&Self, ElementSelectorFlags)
+    {
+        match pc {
+            PseudoClass::AnyLink | PseudoClass::Link => self.is_html_link(),
+            PseudoClass::Enabled => {
+                self.is_html_form_control() &&
+                    self.attr("disabled").is_none()
+            }
+            PseudoClass::Disabled => {
+                self.is_html_form_control() &&
+                    self.attr("disabled").is_some()
+            }
+            PseudoClass::Checked => {
+                (self.is_elem(t::INPUT) && self.attr("checked").is_some()) ||
+                    (self.is_elem(t::OPTION) && self.attr("selected").is_some())
+            }
+            PseudoClass::Visited |
+            PseudoClass::Active |
+            PseudoClass::Focus |
+            PseudoClass::Hover => false,
+        }
+    }
+
+    fn match_pseudo_element(
+        &self,
+        pe: &PseudoElement,
+        _context: &mut MatchingContext<'_, Simple>)
+        -> bool
+    {
+        match *pe {}
+    }
+
+    fn is_link(&self) -> bool {
+        self.is_html_link()
+    }
+
+    fn is_html_slot_element(&self) -> bool {
+        false
+    }
+
+    fn has_id(&self, id: &LocalName, case_sensitivity: CaseSensitivity)
+        -> bool
+    {
+        self.attr(a::ID).map_or(false, |v| {
+            case_sensitivity.eq(id.as_bytes(), v.as_bytes())
+        })
+    }
+
+    fn has_class(&self, name: &LocalName, case_sensitivity: CaseSensitivity)
+        -> bool
+    {
+        let name = name.as_bytes();
+        !name.is_empty() && self.attr(a::CLASS).map_or(false, |v| {
+            v.split(SELECTOR_WHITESPACE)
+                .any(|c| case_sensitivity.eq(c.as_bytes(), name))
+        })
+    }
+
+    fn exported_part(&self, _name: &LocalName) -> Option<LocalName> {
+        None
+    }
+
+    fn imported_part(&self, _name: &LocalName) -> Option<LocalName> {
+        None
+    }
+
+    fn is_part(&self, _name: &LocalName) -> bool {
+        false
+    }
+
+    fn is_empty(&self) -> bool {
+        self.children().all(|c| match &c.data {
+            NodeData::Elem(_) => false,
+            NodeData::Text(t) => t.is_empty(),
+            _ => true,
+        })
+    }
+
+    fn is_root(&self) -> bool {
+        self.parent().map_or(false, |p| {
+            if let NodeData::Document = p.data { true } else { false }
+        })
+    }
+}
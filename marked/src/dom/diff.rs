@@ -0,0 +1,677 @@
+//! Structural diff of two `Document`s, as a tree edit script.
+//!
+//! Nodes of the old and new documents are first matched: top-down by
+//! identical sub-trees and then by kind and element name within each pair
+//! of matched parents, then anywhere for identical (and non-trivial)
+//! sub-trees which have moved, and finally bottom-up, for elements whose
+//! children have mostly moved together. The resulting [`EditScript`] then
+//! consists of the [`Edit`]s needed to transform the old document into the
+//! new, which may also be applied as a patch.
+//!
+//! ```
+//! use marked::{diff, html};
+//!
+//! let old = html::parse_utf8_fragment(b"<p>one</p><p class=a>two</p>");
+//! let new = html::parse_utf8_fragment(b"<p class=b>two</p><p>one!</p>");
+//! let script = diff::diff(&old, &new);
+//! for edit in script.edits() {
+//!     println!("{:?}", edit);
+//! }
+//!
+//! let mut patched = old.bulk_clone();
+//! script.apply(&mut patched);
+//! assert_eq!(new.to_string(), patched.to_string());
+//! ```
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::dom::{Document, Node, NodeData, NodeId, NodeRef};
+
+/// A single tree edit of an [`EditScript`].
+///
+/// Positions are given as the `parent` and child `index` of the node in the
+/// new `Document`.
+#[derive(Clone, Copy, Debug)]
+pub enum Edit<'a> {
+    /// Insert a copy of the `node` from the new document, at `index` of the
+    /// new `parent`. All descendants of the node are also copied, except
+    /// those matched to old nodes, which are placed via `Move` edits.
+    Insert {
+        node: NodeRef<'a>,
+        parent: NodeRef<'a>,
+        index: usize,
+    },
+
+    /// Delete the `node` (and any of its descendants not otherwise moved) of
+    /// the old document.
+    Delete {
+        node: NodeRef<'a>,
+    },
+
+    /// Move the `node` of the old document (with its descendants), matched
+    /// to node `to` of the new document, to `index` of the new `parent`.
+    Move {
+        node: NodeRef<'a>,
+        to: NodeRef<'a>,
+        parent: NodeRef<'a>,
+        index: usize,
+    },
+
+    /// Change the content of a matched text, comment, document type or
+    /// processing instruction node, from `old` to `new`.
+    Text {
+        old: NodeRef<'a>,
+        new: NodeRef<'a>,
+    },
+
+    /// Change the attributes (or template contents) of a matched element,
+    /// from `old` to `new`.
+    Attrs {
+        old: NodeRef<'a>,
+        new: NodeRef<'a>,
+    },
+}
+
+/// An ordered list of [`Edit`]s transforming an old `Document` into a new
+/// `Document`, as returned by [`diff`].
+#[derive(Debug)]
+pub struct EditScript<'a> {
+    new: &'a Document,
+    edits: Vec<Edit<'a>>,
+    // Matched old NodeId, by new NodeId
+    matches: Vec<Option<NodeId>>,
+}
+
+impl<'a> EditScript<'a> {
+    /// Return all edits, in order.
+    pub fn edits(&self) -> &[Edit<'a>] {
+        &self.edits
+    }
+
+    /// Return the number of edits.
+    pub fn len(&self) -> usize {
+        self.edits.len()
+    }
+
+    /// Return true if there are no edits, i.e. the documents are equivalent.
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Apply the edits as a patch to the given `Document`, which must have
+    /// the same `NodeId`s as the old document, e.g. via
+    /// [`Document::bulk_clone`]. To patch the old document itself, use
+    /// [`patch`].
+    ///
+    /// Deleted nodes are unlinked, and new nodes are pushed, such that all
+    /// other `NodeId`s are preserved. Use [`Document::compact`] to free the
+    /// deleted nodes.
+    pub fn apply(&self, doc: &mut Document) {
+        let ops = self.ops();
+        apply_ops(doc, self.new, &ops, &self.matches);
+    }
+
+    fn ops(&self) -> Vec<Op> {
+        self.edits.iter().map(|edit| match *edit {
+            Edit::Insert { node, .. } => Op::Insert(node.id()),
+            Edit::Delete { node } => Op::Delete(node.id()),
+            Edit::Move { node, to, .. } => Op::Move(node.id(), to.id()),
+            Edit::Text { old, new } | Edit::Attrs { old, new } => {
+                Op::Replace(old.id(), new.id())
+            }
+        }).collect()
+    }
+}
+
+/// Compute the [`EditScript`] transforming the old `Document` into the new.
+pub fn diff<'a>(old: &'a Document, new: &'a Document) -> EditScript<'a> {
+    let mut matcher = Matcher::new(old, new);
+    matcher.top_down();
+    matcher.moved();
+    matcher.bottom_up();
+    matcher.script()
+}
+
+/// Patch the old `Document` in place, as per [`diff`] and
+/// [`EditScript::apply`], such that it is equivalent to the new
+/// `Document`.
+pub fn patch(old: &mut Document, new: &Document) {
+    let (ops, matches) = {
+        let script = diff(old, new);
+        (script.ops(), script.matches)
+    };
+    apply_ops(old, new, &ops, &matches);
+}
+
+// An edit by NodeId(s), for apply.
+enum Op {
+    Insert(NodeId),
+    Delete(NodeId),
+    Move(NodeId, NodeId),
+    Replace(NodeId, NodeId),
+}
+
+#[inline]
+fn ix(id: NodeId) -> usize {
+    id.0.get() as usize
+}
+
+fn apply_ops(
+    doc: &mut Document,
+    new: &Document,
+    ops: &[Op],
+    matches: &[Option<NodeId>])
+{
+    // Working (old) NodeId, by new NodeId
+    let mut map = matches.to_vec();
+    for op in ops {
+        match *op {
+            Op::Insert(n) => {
+                let w = doc.push_node(Node::new(new[n].data.clone()));
+                doc.clone_template_contents(w, new, n);
+                map[ix(n)] = Some(w);
+                place(doc, new, &map, n, w);
+                copy_unmatched(doc, new, &mut map, n, w);
+            }
+            Op::Delete(o) => {
+                doc.unlink(o);
+            }
+            Op::Move(o, n) => {
+                place(doc, new, &map, n, o);
+            }
+            Op::Replace(o, n) => {
+                doc[o].data = new[n].data.clone();
+                doc.set_template_contents(o, None);
+                doc.clone_template_contents(o, new, n);
+            }
+        }
+    }
+}
+
+// Place working node w at the position of n in the new document: after the
+// working node of its previous sibling, or otherwise as the first child.
+fn place(
+    doc: &mut Document,
+    new: &Document,
+    map: &[Option<NodeId>],
+    n: NodeId,
+    w: NodeId)
+{
+    let np = new[n].parent.expect("placed node parent");
+    let wp = map[ix(np)].expect("placed node parent mapped");
+    let next = match new[n].prev_sibling {
+        Some(prev) => {
+            let wprev = map[ix(prev)].expect("prior sibling mapped");
+            doc[wprev].next_sibling
+        }
+        None => doc[wp].first_child,
+    };
+    match next {
+        Some(next) if next == w => {}
+        Some(next) => doc.insert_before(next, w),
+        None => doc.append(wp, w),
+    }
+}
+
+// Append copies of all unmatched descendants of n to w.
+fn copy_unmatched(
+    doc: &mut Document,
+    new: &Document,
+    map: &mut [Option<NodeId>],
+    n: NodeId,
+    w: NodeId)
+{
+    let mut stack = vec![(n, w)];
+    while let Some((n, w)) = stack.pop() {
+        for c in new.children(n) {
+            if map[ix(c)].is_none() {
+                let cw = doc.push_node(Node::new(new[c].data.clone()));
+                doc.clone_template_contents(cw, new, c);
+                doc.append(w, cw);
+                map[ix(c)] = Some(cw);
+                stack.push((c, cw));
+            }
+        }
+    }
+}
+
+// Node matching state and phases.
+struct Matcher<'a> {
+    old: &'a Document,
+    new: &'a Document,
+    old_sums: Vec<Summary>,
+    new_sums: Vec<Summary>,
+    o2n: Vec<Option<NodeId>>,
+    n2o: Vec<Option<NodeId>>,
+}
+
+// Sub-tree content hash and node count, by NodeId.
+#[derive(Clone, Copy, Default)]
+struct Summary {
+    hash: u64,
+    size: u32,
+}
+
+// Beyond this many child pairs, align children greedily.
+const MAX_LCS_CELLS: usize = 0x4_0000;
+
+impl<'a> Matcher<'a> {
+    fn new(old: &'a Document, new: &'a Document) -> Matcher<'a> {
+        Matcher {
+            old,
+            new,
+            old_sums: summarize(old),
+            new_sums: summarize(new),
+            o2n: vec![None; old.nodes.len()],
+            n2o: vec![None; new.nodes.len()],
+        }
+    }
+
+    fn set_match(&mut self, o: NodeId, n: NodeId) {
+        self.o2n[ix(o)] = Some(n);
+        self.n2o[ix(n)] = Some(o);
+    }
+
+    // Match all nodes of identical sub-trees.
+    fn match_tree(&mut self, o: NodeId, n: NodeId) {
+        let old = self.old;
+        let new = self.new;
+        for (o, n) in old.descendants(o).zip(new.descendants(n)) {
+            self.set_match(o, n);
+        }
+    }
+
+    // Align the children of matched nodes, recursively, first by identical
+    // sub-trees, then by label (kind and element name).
+    fn align(&mut self, o: NodeId, n: NodeId) {
+        let (old, new) = (self.old, self.new);
+        let mut stack = vec![(o, n)];
+        while let Some((o, n)) = stack.pop() {
+            let oc = old.children(o)
+                .filter(|&c| self.o2n[ix(c)].is_none())
+                .collect::<Vec<_>>();
+            let nc = new.children(n)
+                .filter(|&c| self.n2o[ix(c)].is_none())
+                .collect::<Vec<_>>();
+            let (os, ns) = (&self.old_sums, &self.new_sums);
+            let pairs = lcs(&oc, &nc, |a, b| os[ix(a)].hash == ns[ix(b)].hash);
+            for (a, b) in pairs {
+                self.match_tree(a, b);
+            }
+
+            let oc = oc.into_iter()
+                .filter(|&c| self.o2n[ix(c)].is_none())
+                .collect::<Vec<_>>();
+            let nc = nc.into_iter()
+                .filter(|&c| self.n2o[ix(c)].is_none())
+                .collect::<Vec<_>>();
+            let pairs = lcs(&oc, &nc, |a, b| same_label(&old[a], &new[b]));
+            for (a, b) in pairs {
+                self.set_match(a, b);
+                stack.push((a, b));
+            }
+        }
+    }
+
+    fn top_down(&mut self) {
+        let root = Document::DOCUMENT_NODE_ID;
+        self.set_match(root, root);
+        self.align(root, root);
+    }
+
+    // Match remaining, non-trivial identical sub-trees, anywhere.
+    fn moved(&mut self) {
+        let (old, new) = (self.old, self.new);
+        let mut by_hash: HashMap<u64, Vec<NodeId>> = HashMap::new();
+        for o in old.nodes() {
+            if self.o2n[ix(o)].is_none() &&
+                is_significant(&old[o], self.old_sums[ix(o)])
+            {
+                by_hash.entry(self.old_sums[ix(o)].hash)
+                    .or_default()
+                    .push(o);
+            }
+        }
+        for n in new.nodes() {
+            if self.n2o[ix(n)].is_some() ||
+                !is_significant(&new[n], self.new_sums[ix(n)])
+            {
+                continue;
+            }
+            let cands = match by_hash.get_mut(&self.new_sums[ix(n)].hash) {
+                Some(c) => c,
+                None => continue,
+            };
+            let o2n = &self.o2n;
+            let found = cands.iter().position(|&o| {
+                old.descendants(o).all(|d| o2n[ix(d)].is_none())
+            });
+            if let Some(i) = found {
+                let o = cands.remove(i);
+                self.match_tree(o, n);
+            }
+        }
+    }
+
+    // Match remaining elements via the old parents of their matched
+    // children, and align the children of each such pair.
+    fn bottom_up(&mut self) {
+        let (old, new) = (self.old, self.new);
+        let ids = new.nodes().collect::<Vec<_>>();
+        for &n in ids.iter().rev() {
+            if self.n2o[ix(n)].is_some() || new[n].as_element().is_none() {
+                continue;
+            }
+            let mut counts: Vec<(NodeId, usize)> = Vec::new();
+            for c in new.children(n) {
+                let p = self.n2o[ix(c)].and_then(|o| old[o].parent);
+                if let Some(p) = p {
+                    if self.o2n[ix(p)].is_none() &&
+                        same_label(&old[p], &new[n])
+                    {
+                        match counts.iter_mut().find(|(id, _)| *id == p) {
+                            Some(e) => e.1 += 1,
+                            None => counts.push((p, 1)),
+                        }
+                    }
+                }
+            }
+            let best = counts.iter()
+                .max_by_key(|(_, count)| *count)
+                .map(|&(p, _)| p);
+            if let Some(o) = best {
+                self.set_match(o, n);
+                self.align(o, n);
+            }
+        }
+    }
+
+    // Generate the edit script from the matching.
+    fn script(self) -> EditScript<'a> {
+        let (old, new) = (self.old, self.new);
+        let oref = |id| NodeRef::new(old, id);
+        let nref = |id| NodeRef::new(new, id);
+        let mut edits = Vec::new();
+
+        // Breadth-first, such that each parent is placed before its
+        // children, and each sibling before the next.
+        let mut queue = std::collections::VecDeque::new();
+        queue.push_back(Document::DOCUMENT_NODE_ID);
+        while let Some(np) = queue.pop_front() {
+            let op = self.n2o[ix(np)];
+            let kept = self.kept(op, np);
+            for (index, n) in new.children(np).enumerate() {
+                match self.n2o[ix(n)] {
+                    Some(o) => {
+                        if !kept.contains(&n) {
+                            edits.push(Edit::Move {
+                                node: oref(o),
+                                to: nref(n),
+                                parent: nref(np),
+                                index,
+                            });
+                        }
+                        match (&old[o].data, &new[n].data) {
+                            (NodeData::Elem(_), NodeData::Elem(_)) => {
+                                if !data_eq(old, o, new, n) {
+                                    edits.push(Edit::Attrs {
+                                        old: oref(o),
+                                        new: nref(n),
+                                    });
+                                }
+                            }
+                            _ => {
+                                if !data_eq(old, o, new, n) {
+                                    edits.push(Edit::Text {
+                                        old: oref(o),
+                                        new: nref(n),
+                                    });
+                                }
+                            }
+                        }
+                    }
+                    None if op.is_some() => {
+                        edits.push(Edit::Insert {
+                            node: nref(n),
+                            parent: nref(np),
+                            index,
+                        });
+                    }
+                    None => {}
+                }
+                queue.push_back(n);
+            }
+        }
+
+        for o in old.nodes() {
+            if self.o2n[ix(o)].is_none() {
+                let parent = old[o].parent.expect("non-root parent");
+                if self.o2n[ix(parent)].is_some() {
+                    edits.push(Edit::Delete { node: oref(o) });
+                }
+            }
+        }
+
+        EditScript { new, edits, matches: self.n2o }
+    }
+
+    // Return the new children of np to be kept in place, as the longest
+    // subsequence of those matched to children of op, with the same
+    // relative order.
+    fn kept(&self, op: Option<NodeId>, np: NodeId) -> Vec<NodeId> {
+        let op = match op {
+            Some(op) => op,
+            None => return Vec::new(),
+        };
+        let old = self.old;
+        let positions = old.children(op)
+            .enumerate()
+            .map(|(i, o)| (ix(o), i))
+            .collect::<HashMap<_, _>>();
+        let seq = self.new.children(np)
+            .filter_map(|n| {
+                self.n2o[ix(n)]
+                    .and_then(|o| positions.get(&ix(o)))
+                    .map(|&i| (n, i))
+            })
+            .collect::<Vec<_>>();
+        longest_increasing(&seq)
+    }
+}
+
+// Compute the content hash and size of each sub-tree of doc.
+fn summarize(doc: &Document) -> Vec<Summary> {
+    let mut sums = vec![Summary::default(); doc.nodes.len()];
+    let ids = doc.nodes().collect::<Vec<_>>();
+    // Reverse pre-order visits all children before their parent
+    for &id in ids.iter().rev() {
+        let mut hasher = DefaultHasher::new();
+        hash_data(doc, id, &mut hasher);
+        let mut size = 1;
+        for c in doc.children(id) {
+            sums[ix(c)].hash.hash(&mut hasher);
+            size += sums[ix(c)].size;
+        }
+        sums[ix(id)] = Summary { hash: hasher.finish(), size };
+    }
+    sums
+}
+
+fn hash_data(doc: &Document, id: NodeId, hasher: &mut DefaultHasher) {
+    match &doc[id].data {
+        NodeData::Hole => 0u8.hash(hasher),
+        NodeData::Document => 1u8.hash(hasher),
+        NodeData::DocType(dt) => {
+            2u8.hash(hasher);
+            dt.name.as_ref().hash(hasher);
+            dt.public_id.as_ref().hash(hasher);
+            dt.system_id.as_ref().hash(hasher);
+        }
+        NodeData::Text(t) => {
+            3u8.hash(hasher);
+            t.as_ref().hash(hasher);
+        }
+        NodeData::Comment(t) => {
+            4u8.hash(hasher);
+            t.as_ref().hash(hasher);
+        }
+        NodeData::Elem(e) => {
+            5u8.hash(hasher);
+            e.name.hash(hasher);
+            for attr in &e.attrs {
+                attr.name.hash(hasher);
+                attr.value.as_ref().hash(hasher);
+            }
+            if let Some(contents) = doc.template_contents(id) {
+                let sums = summarize(contents);
+                sums[ix(Document::DOCUMENT_NODE_ID)].hash.hash(hasher);
+            }
+        }
+        NodeData::Pi(pi) => {
+            6u8.hash(hasher);
+            pi.target.as_ref().hash(hasher);
+            pi.data.as_ref().hash(hasher);
+        }
+    }
+}
+
+// Return true if the node data of o in old and n in new is equal, including
+// any template contents.
+fn data_eq(old: &Document, o: NodeId, new: &Document, n: NodeId) -> bool {
+    match (&old[o].data, &new[n].data) {
+        (NodeData::Elem(a), NodeData::Elem(b)) => {
+            a.name == b.name &&
+                a.attrs == b.attrs &&
+                match (old.template_contents(o), new.template_contents(n)) {
+                    (Some(a), Some(b)) => {
+                        a.document_node_ref()
+                            .structurally_eq(&b.document_node_ref())
+                    }
+                    (None, None) => true,
+                    _ => false,
+                }
+        }
+        (NodeData::Text(a), NodeData::Text(b)) |
+        (NodeData::Comment(a), NodeData::Comment(b)) => a == b,
+        (NodeData::DocType(a), NodeData::DocType(b)) => {
+            a.name == b.name &&
+                a.public_id == b.public_id &&
+                a.system_id == b.system_id
+        }
+        (NodeData::Pi(a), NodeData::Pi(b)) => {
+            a.target == b.target && a.data == b.data
+        }
+        (NodeData::Document, NodeData::Document) |
+        (NodeData::Hole, NodeData::Hole) => true,
+        _ => false,
+    }
+}
+
+// Return true if the nodes are of the same kind, and for elements, name.
+fn same_label(a: &Node, b: &Node) -> bool {
+    match (&a.data, &b.data) {
+        (NodeData::Elem(a), NodeData::Elem(b)) => a.name == b.name,
+        (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+    }
+}
+
+// Return true if a sub-tree is large enough to be matched anywhere.
+fn is_significant(node: &Node, sum: Summary) -> bool {
+    sum.size > 1 || match node.data {
+        NodeData::Text(ref t) => !t.trim().is_empty(),
+        NodeData::Elem(_) | NodeData::Comment(_) => true,
+        _ => false,
+    }
+}
+
+// Return the index pairs of a longest common subsequence of a and b, by
+// the eq predicate, or a greedy approximation for long sequences.
+fn lcs<F>(a: &[NodeId], b: &[NodeId], eq: F) -> Vec<(NodeId, NodeId)>
+    where F: Fn(NodeId, NodeId) -> bool
+{
+    let mut pairs = Vec::new();
+
+    // Common prefix and suffix
+    let mut start = 0;
+    while start < a.len() && start < b.len() && eq(a[start], b[start]) {
+        pairs.push((a[start], b[start]));
+        start += 1;
+    }
+    let mut suffix = Vec::new();
+    let (mut ae, mut be) = (a.len(), b.len());
+    while ae > start && be > start && eq(a[ae - 1], b[be - 1]) {
+        ae -= 1;
+        be -= 1;
+        suffix.push((a[ae], b[be]));
+    }
+    let (a, b) = (&a[start..ae], &b[start..be]);
+
+    if a.len() * b.len() > MAX_LCS_CELLS {
+        let mut j = 0;
+        for &x in a {
+            if let Some(k) = b[j..].iter().position(|&y| eq(x, y)) {
+                pairs.push((x, b[j + k]));
+                j += k + 1;
+            }
+        }
+    } else if !a.is_empty() && !b.is_empty() {
+        // lens[i][j]: LCS length of a[i..] and b[j..]
+        let w = b.len() + 1;
+        let mut lens = vec![0u32; (a.len() + 1) * w];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lens[i * w + j] = if eq(a[i], b[j]) {
+                    lens[(i + 1) * w + j + 1] + 1
+                } else {
+                    lens[(i + 1) * w + j].max(lens[i * w + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() && j < b.len() {
+            if eq(a[i], b[j]) {
+                pairs.push((a[i], b[j]));
+                i += 1;
+                j += 1;
+            } else if lens[(i + 1) * w + j] >= lens[i * w + j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+    }
+
+    pairs.extend(suffix.into_iter().rev());
+    pairs
+}
+
+// Return the items of a longest subsequence of seq with increasing
+// positions.
+fn longest_increasing(seq: &[(NodeId, usize)]) -> Vec<NodeId> {
+    // tails[k]: index in seq of the smallest tail of length k+1
+    let mut tails: Vec<usize> = Vec::new();
+    let mut prev: Vec<Option<usize>> = vec![None; seq.len()];
+    for (i, &(_, pos)) in seq.iter().enumerate() {
+        let k = match tails.binary_search_by(|&t| seq[t].1.cmp(&pos)) {
+            Ok(k) | Err(k) => k,
+        };
+        if k > 0 {
+            prev[i] = Some(tails[k - 1]);
+        }
+        if k == tails.len() {
+            tails.push(i);
+        } else {
+            tails[k] = i;
+        }
+    }
+    let mut out = Vec::with_capacity(tails.len());
+    let mut next = tails.last().cloned();
+    while let Some(i) = next {
+        out.push(seq[i].0);
+        next = prev[i];
+    }
+    out.reverse();
+    out
+}
@@ -387,7 +387,7 @@ pub fn xmp_to_pre(_p: NodeRef<'_>, data: &mut NodeData) -> Action {
     Action::Continue
 }
 
-fn is_block(node: NodeRef<'_>) -> bool {
+pub(crate) fn is_block(node: NodeRef<'_>) -> bool {
     if let Some(elm) = node.as_element() {
         if let Some(tmeta) = TAG_META.get(&elm.name.local) {
             return !tmeta.is_inline();
@@ -411,7 +411,7 @@ fn is_preformatted(e: &Element) -> bool {
     e.is_elem(t::PRE) || e.is_elem(t::XMP) || e.is_elem(t::PLAINTEXT)
 }
 
-fn is_preform_node(n: NodeRef<'_>) -> bool {
+pub(crate) fn is_preform_node(n: NodeRef<'_>) -> bool {
     n.is_elem(t::PRE) || n.is_elem(t::XMP) || n.is_elem(t::PLAINTEXT)
 }
 
@@ -10,42 +10,72 @@
 //! Support for html5 parsing to `Document`.
 
 use std::borrow::Cow;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::default::Default;
 use std::io;
+use std::num::NonZeroU32;
 
 use encoding_rs as enc;
 
 use html5ever::{
     parse_document, parse_fragment,
-    ExpandedName, QualName, Parser, ParseOpts
+    ExpandedName, QualName
 };
 use html5ever::interface::tree_builder::{
-    ElementFlags, NodeOrText, QuirksMode, TreeSink
+    ElementFlags, NodeOrText, TreeSink
 };
 use html5ever::tendril::{StrTendril, TendrilSink};
-use log::{debug, info, trace};
-use tendril::{fmt as form, Tendril};
+use log::debug;
 
 use crate::{
-    Attribute, Decoder, Document, DocumentType, Element, EncodingHint,
-    Node, NodeData, NodeId, ProcessingInstruction, SharedEncodingHint,
-    BOM_CONF, HTML_META_CONF, INITIAL_BUFFER_SIZE,
+    Attribute, Document, DocumentType, Element, EncodingHint, HintSource,
+    LimitError, Node, NodeData, NodeId, ParseLimits, ParseReport,
+    ProcessingInstruction,
+    QuirksMode, SharedEncodingHint, SourcePos, SourceSpan, HTML_META_CONF,
 };
+use crate::decode::{media_type::parse_media_type, read_buffered};
+#[cfg(feature = "async")]
+use crate::decode::read_buffered_async;
+use crate::dom::limits::{Limiter, SharedLimitError};
 
+mod errors;
 mod meta;
+mod options;
+mod push;
+
+pub use self::errors::{
+    ParseError, ParseErrors, SharedParseErrors,
+    DEFAULT_MAX_ERRORS
+};
 
 pub use self::meta::{
     a, ns, t,
     TagMeta, TAG_META
 };
 
+pub use self::options::HtmlParseOptions;
+pub use self::push::HtmlPushParser;
+
 /// Parse HTML document from UTF-8 bytes in RAM.
 pub fn parse_utf8(bytes: &[u8]) -> Document {
-    let sink = Sink::default();
-    parse_document(sink, Default::default())
+    parse_utf8_with_options(bytes, &HtmlParseOptions::default())
+        .expect("no limits")
+}
+
+/// Parse HTML document from UTF-8 bytes in RAM, with the given options.
+///
+/// Returns a [`LimitError`] only if `HtmlParseOptions::limits` are given and
+/// exceeded (without `ParseLimits::truncate`).
+pub fn parse_utf8_with_options(bytes: &[u8], opts: &HtmlParseOptions)
+    -> Result<Document, LimitError>
+{
+    let exceeded = SharedLimitError::default();
+    let doc = parse_document(
+        opts.sink(utf8_hint(), false, &exceeded),
+        opts.parse_opts())
         .from_utf8()
-        .one(bytes)
+        .one(bytes);
+    opts.check(doc, &exceeded)
 }
 
 /// Parse an HTML fragement from UTF-8 bytes in RAM.
@@ -54,21 +84,79 @@ pub fn parse_utf8(bytes: &[u8]) -> Document {
 /// contain a single, block level (e.g. not [`TagMeta::is_inline`]) element, a
 /// root `<div>` element is included as parent.
 pub fn parse_utf8_fragment(bytes: &[u8]) -> Document {
-    let sink = Sink::default();
+    parse_utf8_fragment_with_options(bytes, &HtmlParseOptions::default())
+        .expect("no limits")
+}
 
-    let mut doc = parse_fragment(
-        sink,
-        Default::default(),
+/// Parse an HTML fragement from UTF-8 bytes in RAM, with the given options.
+///
+/// The fragment root is as per [`parse_utf8_fragment`], unless limits are
+/// exceeded with `ParseLimits::truncate`, in which case the `Document` may
+/// be empty. Returns a [`LimitError`] as per [`parse_utf8_with_options`].
+pub fn parse_utf8_fragment_with_options(
+    bytes: &[u8],
+    opts: &HtmlParseOptions)
+    -> Result<Document, LimitError>
+{
+    let exceeded = SharedLimitError::default();
+    let doc = parse_fragment(
+        opts.sink(utf8_hint(), false, &exceeded),
+        opts.parse_opts(),
         QualName::new(None, ns::HTML, t::DIV),
         vec![])
         .from_utf8()
         .one(bytes);
 
-    // Note that the above context name, doesn't really get used. A matching
-    // element is pushed but never linked, so unless we replace the doc (deep
-    // clone, etc.) then it will contain this cruft.
+    opts.check(doc, &exceeded).map(fragment_root)
+}
+
+/// Parse and return an HTML fragment, reading from the given stream of bytes
+/// until end, processing incrementally.
+///
+/// Decoding is as per [`parse_buffered`], including restart on a
+/// compelling BOM (or detected) encoding hint, but note that any `<meta>`
+/// charset in the fragment is not considered. The fragment root is as per
+/// [`parse_utf8_fragment`].
+pub fn parse_buffered_fragment<R>(hint: SharedEncodingHint, r: &mut R)
+    -> Result<Document, io::Error>
+    where R: io::Read
+{
+    parse_buffered_fragment_with_options(hint, r, &HtmlParseOptions::default())
+}
+
+/// Parse and return an HTML fragment as per [`parse_buffered_fragment`], with
+/// the given options.
+pub fn parse_buffered_fragment_with_options<R>(
+    hint: SharedEncodingHint,
+    r: &mut R,
+    opts: &HtmlParseOptions)
+    -> Result<Document, io::Error>
+    where R: io::Read
+{
+    let exceeded = SharedLimitError::default();
+    let (doc, _) = read_buffered(&hint, r, |_| {
+        parse_fragment(
+            opts.sink(hint.clone(), false, &exceeded),
+            opts.parse_opts(),
+            QualName::new(None, ns::HTML, t::DIV),
+            vec![])
+    })?;
+    debug!("Final encoding errors {}", hint.borrow().errors());
+    opts.check(doc, &exceeded)
+        .map(fragment_root)
+        .map_err(limit_io_error)
+}
+
+// Ensure a single root element for a fragment parsed in a `<div>` context.
+fn fragment_root(mut doc: Document) -> Document {
+    // Note that the `<div>` context element doesn't really get used. A
+    // matching element is pushed but never linked, so unless we replace the
+    // doc (deep clone, etc.) then it will contain this cruft.
 
-    let root_id = doc.root_element().expect("a root");
+    let root_id = match doc.root_element() {
+        Some(id) => id,
+        None => return doc, // only if truncated by limits
+    };
     debug_assert!(doc[root_id].is_elem(t::HTML));
 
     // If the root has a single element child, which is not an inline
@@ -94,6 +182,58 @@ pub fn parse_utf8_fragment(bytes: &[u8]) -> Document {
     doc
 }
 
+/// Parse an HTML fragment from UTF-8 bytes in RAM, in the given context
+/// element, e.g. `<tbody>` for a fragment of table rows.
+///
+/// This applies the HTML5 fragment parsing algorithm for the context, as
+/// would be used when setting the `innerHTML` of such an element. The
+/// returned `Document` contains the parsed nodes as direct children of the
+/// document node, which may not form a single root element.
+///
+/// ```
+/// use marked::{html::{self, t, ns}, QualName};
+///
+/// let context = QualName::new(None, ns::HTML, t::TBODY);
+/// let doc = html::parse_fragment_in(context, b"<tr><td>1</td></tr>");
+/// assert_eq!("<tr><td>1</td></tr>", doc.to_string());
+/// ```
+pub fn parse_fragment_in(context: QualName, bytes: &[u8]) -> Document {
+    parse_fragment_in_with_options(context, bytes, &HtmlParseOptions::default())
+        .expect("no limits")
+}
+
+/// Parse an HTML fragment from UTF-8 bytes in RAM, in the given context
+/// element, as per [`parse_fragment_in`], with the given options.
+///
+/// Returns a [`LimitError`] as per [`parse_utf8_with_options`].
+pub fn parse_fragment_in_with_options(
+    context: QualName,
+    bytes: &[u8],
+    opts: &HtmlParseOptions)
+    -> Result<Document, LimitError>
+{
+    let exceeded = SharedLimitError::default();
+    let doc = parse_fragment(
+        opts.sink(utf8_hint(), false, &exceeded),
+        opts.parse_opts(),
+        context,
+        vec![])
+        .from_utf8()
+        .one(bytes);
+    opts.check(doc, &exceeded).map(fold_fragment_root)
+}
+
+// Fold the synthetic `<html>` root of an html5ever parsed fragment, and
+// compact to drop the unlinked context element.
+fn fold_fragment_root(mut doc: Document) -> Document {
+    if let Some(root_id) = doc.root_element() {
+        debug_assert!(doc[root_id].is_elem(t::HTML));
+        doc.fold(root_id);
+    }
+    doc.compact();
+    doc
+}
+
 /// Parse and return an HTML `Document`, reading from the given stream of bytes
 /// until end, processing incrementally.
 ///
@@ -102,129 +242,129 @@ pub fn parse_utf8_fragment(bytes: &[u8]) -> Document {
 /// bytes of the stream are buffered and if a compelling alternative encoding
 /// hint is found via a leading Byte-Order-Mark (BOM) or in the documents
 /// `<head>`, the parse will be restarted from the beginning with that encoding
-/// and continuing until the end.
+/// and continuing until the end. With the _detect_ feature, the encoding of
+/// the buffered bytes is also statistically detected and added as a hint with
+/// [`DETECT_CONF`](crate::DETECT_CONF).
 pub fn parse_buffered<R>(hint: SharedEncodingHint, r: &mut R)
     -> Result<Document, io::Error>
     where R: io::Read
 {
-    let enc = hint.borrow().top().expect("EnodingHint default encoding required");
-
-    let parser_sink: Parser<Sink> = parse_document(
-        Sink::new(hint.clone(), true),
-        ParseOpts::default()
-    );
-
-    // Decoders are "Sink adaptors" that also impl TendrilSink.
-    // The decoder is consumed to finish the parse.
-    let mut decoder = Some(Decoder::new(enc, parser_sink));
-
-    // Read up to _SIZE bytes, processing as we go and finishing if end is
-    // reached in that size.
-    let mut buff = Tendril::<form::Bytes>::new();
-    unsafe {
-        buff.push_uninitialized(INITIAL_BUFFER_SIZE);
-    }
-    let mut i = 0;
-    let mut finished = None;
-    loop {
-        match r.read(&mut buff[i as usize..]) {
-            Ok(0) => {
-                trace!("read 0 bytes (end len {})", i);
-                finished = Some(decoder.take().unwrap().finish());
-                break;
-            }
-            Ok(n) => {
-                let n = n as u32;
-                trace!("read {} bytes (len {})", n, i + n);
-
-                // One time, leading Byte-order-mark (BOM) detection for UTF-16
-                // little/big endian, or UTF-8, after reading initial 3 bytes.
-                // This is part of the `decode` algorithm of the Encoding
-                // Standard which is not implemented by either encoding_rs or
-                // html5ever. html5ever will ignore a BOM character so we need
-                // not remove it before processing.  If the new hint is
-                // compelling, then break early to reprocess with a new
-                // decoder.
-                if i < 3 && (i + n) >= 3 {
-                    if let Some(enc) = bom_enc(&buff) {
-                        if hint.borrow_mut().add_hint(enc, BOM_CONF) {
-                            i += n;
-                            break;
-                        }
-                    }
-                }
-
-                decoder.as_mut().unwrap().process(buff.subtendril(i, n));
-                i += n;
-                if i == INITIAL_BUFFER_SIZE || hint.borrow().changed().is_some() {
-                    break;
-                }
-            }
-            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
-            Err(e) => return Err(e)
-        }
-    } // repeat on interrupt or short read.
-
-    // Avoid any uninitialized trailing bytes
-    buff.pop_back(INITIAL_BUFFER_SIZE - i);
+    parse_buffered_with_options(hint, r, &HtmlParseOptions::default())
+}
 
-    let (changed, errors) = {
-        let hint = hint.borrow();
-        trace!("revised hint: {:?}", hint);
-        (hint.changed(), hint.errors())
-    };
+/// Parse and return an HTML `Document` as per [`parse_buffered`], reading
+/// from the given asynchronous stream (_async_ feature).
+///
+/// This yields to the executor between each chunk read and processed.
+/// Note that the returned future is not `Send`, as the `SharedEncodingHint`
+/// and parser state are not, so with a multi-threaded runtime it should be
+/// run via `tokio::task::LocalSet` or `block_on`.
+#[cfg(feature = "async")]
+pub async fn parse_buffered_async<R>(hint: SharedEncodingHint, r: &mut R)
+    -> Result<Document, io::Error>
+    where R: tokio::io::AsyncRead + Unpin
+{
+    let opts = HtmlParseOptions::default();
+    parse_buffered_async_with_options(hint, r, &opts).await
+}
 
-    if let Some(enc) = changed {
-        info!(
-            "Reparsing with enc {}, buffered: {}, prior enc errors: {}",
-            enc.name(), buff.len(), errors
-        );
-        hint.borrow_mut().clear_errors();
-        finished = None;
-
-        // Replace decoder and re-process, consuming the original tendril
-        // buffer, which was previously cloned.
-        let parser_sink = parse_document(
-            Sink::new(hint.clone(), false),
-            ParseOpts::default()
-        );
-        decoder = Some(Decoder::new(enc, parser_sink));
-        decoder.as_mut().unwrap().process(buff);
-    }
+/// Parse and return an HTML `Document` as per [`parse_buffered_async`], with
+/// the given options (_async_ feature).
+#[cfg(feature = "async")]
+pub async fn parse_buffered_async_with_options<R>(
+    hint: SharedEncodingHint,
+    r: &mut R,
+    opts: &HtmlParseOptions)
+    -> Result<Document, io::Error>
+    where R: tokio::io::AsyncRead + Unpin
+{
+    let exceeded = SharedLimitError::default();
+    let (doc, _) = read_buffered_async(&hint, r, |enc_check| {
+        parse_document(
+            opts.sink(hint.clone(), enc_check, &exceeded),
+            opts.parse_opts())
+    }).await?;
+    debug!("Final encoding errors {}", hint.borrow().errors());
+    opts.check(doc, &exceeded).map_err(limit_io_error)
+}
 
-    // If (still) finished, return that Document, else read and process to end.
-    let res = if let Some(d) = finished {
-        Ok(d)
-    } else {
-        decoder.take().unwrap().read_to_end(r)
-    };
-    if res.is_ok() {
-        debug!("Final encoding errors {}", hint.borrow().errors());
-    }
-    res
+/// Parse and return an HTML `Document` as per [`parse_buffered`], with the
+/// given options.
+///
+/// If `HtmlParseOptions::limits` are exceeded (and `ParseLimits::truncate` is
+/// not set), the [`LimitError`] is returned as an `io::Error` of kind
+/// `InvalidData`.
+pub fn parse_buffered_with_options<R>(
+    hint: SharedEncodingHint,
+    r: &mut R,
+    opts: &HtmlParseOptions)
+    -> Result<Document, io::Error>
+    where R: io::Read
+{
+    let exceeded = SharedLimitError::default();
+    let (doc, _) = read_buffered(&hint, r, |enc_check| {
+        parse_document(
+            opts.sink(hint.clone(), enc_check, &exceeded),
+            opts.parse_opts())
+    })?;
+    debug!("Final encoding errors {}", hint.borrow().errors());
+    opts.check(doc, &exceeded).map_err(limit_io_error)
 }
 
-// Return encoding for any Byte-Order-Mark found at start of buff.
-fn bom_enc(buff: &Tendril::<form::Bytes>) -> Option<&'static enc::Encoding>
+/// Parse and return an HTML `Document` as per [`parse_buffered`], with the
+/// given options, along with a [`ParseReport`] of the final encoding, how it
+/// was decided, and any decoding and parse errors.
+///
+/// Parse errors are collected to `HtmlParseOptions::errors` if set, or
+/// otherwise up to [`DEFAULT_MAX_ERRORS`] are kept. Any exceeded limit is
+/// returned as per [`parse_buffered_with_options`].
+pub fn parse_buffered_with_report<R>(
+    hint: SharedEncodingHint,
+    r: &mut R,
+    opts: &HtmlParseOptions)
+    -> Result<(Document, ParseReport), io::Error>
+    where R: io::Read
 {
-    match (buff[0], buff[1], buff[2]) {
-        (0xFE, 0xFF,    _) => Some(enc::UTF_16BE),
-        (0xFF, 0xFE,    _) => Some(enc::UTF_16LE),
-        (0xEF, 0xBB, 0xBF) => Some(enc::UTF_8),
-        _ => None
-    }
+    let mut opts = opts.clone();
+    let errors = opts.errors
+        .get_or_insert_with(|| ParseErrors::shared(DEFAULT_MAX_ERRORS))
+        .clone();
+    let exceeded = SharedLimitError::default();
+    let (doc, prior_errors) = read_buffered(&hint, r, |enc_check| {
+        parse_document(
+            opts.sink(hint.clone(), enc_check, &exceeded),
+            opts.parse_opts())
+    })?;
+    let doc = opts.check(doc, &exceeded).map_err(limit_io_error)?;
+    let errors = errors.borrow().clone();
+    let report = ParseReport::new(&hint.borrow(), prior_errors, errors);
+    debug!("{:?}", report);
+    Ok((doc, report))
 }
 
 /// A `TreeSink` implementation for parsing html to a
 /// [`Document`](crate::Document) tree.
 pub struct Sink {
     document: Document,
-    #[allow(unused)]
-    quirks_mode: QuirksMode,
     enc_hint: SharedEncodingHint,
     enc_check: bool,
+    errors: Option<SharedParseErrors>,
+    line: u64,
+    spans: bool,
+    limiter: Option<Limiter>,
+    // Names of elements dropped due to limits, by handle
+    dropped: Vec<QualName>,
+    // Template elements and the unlinked roots of their contents, moved to
+    // a separate fragment on finish
+    templates: BTreeMap<NodeId, NodeId>,
 }
 
+// Handles for nodes dropped due to limits are allocated downward from the top
+// of the `NodeId` index space, reserved via `ParseLimits::MAX_NODES`. One
+// handle is used for each distinct element name, and the top handle for all
+// other nodes.
+const MAX_DROPPED_NAMES: usize = 0x1_0000;
+
 impl Sink {
     /// Construct new sink with shared `EncodingHint`.
     ///
@@ -234,14 +374,111 @@ impl Sink {
 
         Sink {
             document: Document::new(),
-            quirks_mode: QuirksMode::NoQuirks,
             enc_hint,
             enc_check,
+            errors: None,
+            line: 1,
+            spans: false,
+            limiter: None,
+            dropped: Vec::new(),
+            templates: BTreeMap::new(),
+        }
+    }
+
+    // Enforce limits, recording any exceeded limit to the shared error.
+    fn limit(mut self, limits: &ParseLimits, exceeded: SharedLimitError)
+        -> Sink
+    {
+        self.limiter = Some(Limiter::new(limits, exceeded));
+        self
+    }
+
+    // Set the initial quirks mode of the document, matching that given to
+    // the parser via `TreeBuilderOpts`.
+    fn initial_quirks_mode(mut self, mode: QuirksMode) -> Sink {
+        self.document.set_quirks_mode(mode);
+        self
+    }
+
+    /// Collect non-fatal parse errors, with line numbers, to the given shared
+    /// `ParseErrors`, instead of only logging them.
+    pub fn collect_errors(mut self, errors: SharedParseErrors) -> Sink {
+        self.errors = Some(errors);
+        self
+    }
+
+    /// Record the [`SourceSpan`] (by line only) of each node in the
+    /// `Document`.
+    pub fn record_spans(mut self) -> Sink {
+        self.spans = true;
+        self
+    }
+
+    // Move the contents of each template element to a separate fragment
+    // `Document`, innermost (last created) first.
+    fn move_templates(&mut self) {
+        let doc = &mut self.document;
+        for (&id, &root) in self.templates.iter().rev() {
+            let mut frag = Document::new();
+            let children = doc.children(root).collect::<Vec<_>>();
+            for child in children {
+                frag.append_move(Document::DOCUMENT_NODE_ID, doc, child);
+            }
+            doc.set_template_contents(id, Some(frag));
         }
     }
 
     fn new_node(&mut self, data: NodeData) -> NodeId {
-        self.document.push_node(Node::new(data))
+        if let Some(ref limiter) = self.limiter {
+            if !limiter.allow_node(&self.document, self.line) {
+                let name = match data {
+                    NodeData::Elem(e) => Some(e.name),
+                    _ => None,
+                };
+                return self.dropped_node(name);
+            }
+        }
+        let id = self.document.push_node(Node::new(data));
+        if self.spans {
+            let span = SourceSpan::at(SourcePos::at_line(self.line));
+            self.document.set_source_span(id, span);
+        }
+        id
+    }
+
+    // Return a handle for a node dropped due to limits.
+    fn dropped_node(&mut self, name: Option<QualName>) -> NodeId {
+        if self.dropped.is_empty() {
+            // Placeholder for non-elements, at the top handle
+            self.dropped.push(QualName::new(None, ns::HTML, t::DIV));
+        }
+        let index = name.map_or(0, |name| {
+            match self.dropped.iter().skip(1).position(|n| *n == name) {
+                Some(i) => i + 1,
+                None if self.dropped.len() < MAX_DROPPED_NAMES => {
+                    self.dropped.push(name);
+                    self.dropped.len() - 1
+                }
+                None => 0,
+            }
+        });
+        let id = std::u32::MAX - index as u32;
+        super::NodeId(NonZeroU32::new(id).unwrap())
+    }
+
+    fn is_dropped(&self, id: NodeId) -> bool {
+        id.0.get() > self.document.len()
+    }
+
+    // Return true if a child may be added to parent.
+    fn allow_child(&self, parent: NodeId) -> bool {
+        if self.is_dropped(parent) {
+            return false;
+        }
+        match self.limiter {
+            Some(ref limiter) => limiter.allow_child(parent, self.line),
+            None => true,
+        }
     }
 
     fn append_common<P, A>(
@@ -253,18 +490,33 @@ impl Sink {
               A: FnOnce(&mut Document, NodeId)
     {
         let new_node = match child {
-            NodeOrText::AppendText(text) => {
+            NodeOrText::AppendText(mut text) => {
+                if let Some(ref mut limiter) = self.limiter {
+                    let len = limiter.allow_text(&text, self.line);
+                    if len == 0 {
+                        return;
+                    }
+                    text.pop_back(text.len32() - len as u32);
+                }
+
                 // Append to an existing Text node if we have one.
                 if let Some(id) = previous(&mut self.document) {
                     let node = &mut self.document[id];
                     if let NodeData::Text(t) = &mut node.data {
                         t.push_tendril(&text);
+                        if self.spans {
+                            let end = SourcePos::at_line(self.line);
+                            self.document.set_source_end(id, end);
+                        }
                         return;
                     }
                 }
                 self.new_node(NodeData::Text(text))
             }
             NodeOrText::AppendNode(node) => {
+                if self.is_dropped(node) {
+                    return;
+                }
                 if self.enc_check && self.document[node].is_elem(t::BODY) {
                     debug!("body appended, checking meta charsets now");
                     self.enc_check = false;
@@ -274,7 +526,27 @@ impl Sink {
             }
         };
 
-        append(&mut self.document, new_node);
+        if !self.is_dropped(new_node) {
+            append(&mut self.document, new_node);
+            self.linked(new_node);
+        }
+    }
+
+    // Record the depth of a node as just linked, for limits.
+    fn linked(&mut self, id: NodeId) {
+        if let Some(ref mut limiter) = self.limiter {
+            if let Some(parent) = self.document[id].parent {
+                limiter.linked(id, parent);
+            }
+        }
+    }
+
+    // Truncate text of a comment or processing instruction to limits.
+    fn limit_text(&mut self, text: &mut StrTendril) {
+        if let Some(ref mut limiter) = self.limiter {
+            let len = limiter.allow_text(text, self.line);
+            text.pop_back(text.len32() - len as u32);
+        }
     }
 
     fn check_meta_charsets(&mut self) {
@@ -292,7 +564,7 @@ impl Sink {
                 } else if let Some(a) = m.attr(a::HTTP_EQUIV) {
                     if a.as_ref().trim().eq_ignore_ascii_case("Content-Type") {
                         if let Some(a) = m.attr(a::CONTENT) {
-                            if let Ok(m) = a.as_ref().trim().parse::<mime::Mime>() {
+                            if let Some(m) = parse_media_type(a) {
                                 if let Some(cs) = m.get_param(mime::CHARSET) {
                                     metas += 1;
                                     let cs = cs.as_str().trim().as_bytes();
@@ -316,7 +588,7 @@ impl Sink {
             let mut hints = self.enc_hint.borrow_mut();
             for cs in charsets {
                 if hints.could_read_from(cs) {
-                    hints.add_hint(cs, conf);
+                    hints.add_hint_from(cs, conf, HintSource::Meta);
                 } else {
                     debug!("Ignoring impossible hint: {}", cs.name());
                 }
@@ -327,15 +599,49 @@ impl Sink {
 
 impl Default for Sink {
     fn default() -> Self {
-        Sink::new(EncodingHint::shared_default(enc::UTF_8), false)
+        Sink::new(utf8_hint(), false)
     }
 }
 
+// Return an exceeded limit as an `io::Error` of kind `InvalidData`.
+fn limit_io_error(e: LimitError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+// Return a new hint for parsing UTF-8 bytes in RAM.
+fn utf8_hint() -> SharedEncodingHint {
+    EncodingHint::shared_default(enc::UTF_8)
+}
+
 impl TreeSink for Sink {
     type Handle = NodeId;
     type Output = Document;
 
-    fn finish(self) -> Document {
+    fn finish(mut self) -> Document {
+        self.move_templates();
+        if self.spans {
+            // Elements aren't always popped, so also extend the end of each
+            // to include the end of its descendants, in reverse tree order.
+            let doc = &mut self.document;
+            let ids: Vec<NodeId> = doc.nodes().collect();
+            for id in ids.into_iter().rev() {
+                if let (Some(span), Some(parent)) =
+                    (doc.source_span(id), doc[id].parent)
+                {
+                    if let Some(mut pspan) = doc.source_span(parent) {
+                        if pspan.end.line < span.end.line {
+                            pspan.end = span.end;
+                            doc.set_source_span(parent, pspan);
+                        }
+                    }
+                }
+            }
+        }
+        let exceeded = self.limiter.as_ref().map_or(false, |l| l.exceeded());
+        if exceeded || !self.templates.is_empty() {
+            // Drop any nodes left unlinked due to limits or templates.
+            self.document.compact();
+        }
         self.document
     }
 
@@ -346,15 +652,22 @@ impl TreeSink for Sink {
             self.enc_hint.borrow_mut().increment_error();
         } else {
             debug!("other parser error: {}", err);
+            if let Some(ref errors) = self.errors {
+                errors.borrow_mut().push(self.line, err);
+            }
         }
     }
 
+    fn set_current_line(&mut self, line: u64) {
+        self.line = line;
+    }
+
     fn get_document(&mut self) -> NodeId {
         Document::DOCUMENT_NODE_ID
     }
 
     fn set_quirks_mode(&mut self, mode: QuirksMode) {
-        self.quirks_mode = mode;
+        self.document.set_quirks_mode(mode);
     }
 
     fn same_node(&self, x: &NodeId, y: &NodeId) -> bool {
@@ -362,6 +675,10 @@ impl TreeSink for Sink {
     }
 
     fn elem_name<'a>(&'a self, &target: &'a NodeId) -> ExpandedName<'a> {
+        if self.is_dropped(target) {
+            let index = (std::u32::MAX - target.0.get()) as usize;
+            return self.dropped[index].expanded();
+        }
         self.document[target]
             .as_element()
             .expect("not an element")
@@ -370,30 +687,50 @@ impl TreeSink for Sink {
     }
 
     fn get_template_contents(&mut self, &target: &NodeId) -> NodeId {
-        target
+        if self.is_dropped(target) {
+            return target;
+        }
+        let document = &mut self.document;
+        *self.templates
+            .entry(target)
+            .or_insert_with(|| document.push_fragment_root())
     }
 
     fn create_element(
         &mut self,
         name: QualName,
-        attrs: Vec<Attribute>,
+        mut attrs: Vec<Attribute>,
         _flags: ElementFlags)
         -> NodeId
     {
-        self.new_node(NodeData::Elem(Element { name, attrs, _priv: () }))
+        if let Some(ref limiter) = self.limiter {
+            limiter.limit_attrs(&mut attrs, self.line);
+        }
+        self.new_node(NodeData::Elem(Element {
+            name,
+            attrs,
+            _priv: ()
+        }))
     }
 
-    fn create_comment(&mut self, text: StrTendril) -> NodeId {
+    fn create_comment(&mut self, mut text: StrTendril) -> NodeId {
+        self.limit_text(&mut text);
         self.new_node(NodeData::Comment(text))
     }
 
-    fn create_pi(&mut self, _target: StrTendril, data: StrTendril)
+    fn create_pi(&mut self, target: StrTendril, mut data: StrTendril)
         -> NodeId
     {
-        self.new_node(NodeData::Pi(ProcessingInstruction { data, _priv: () }))
+        self.limit_text(&mut data);
+        self.new_node(NodeData::Pi(
+            ProcessingInstruction { target, data, _priv: () }
+        ))
     }
 
     fn append(&mut self, &parent: &NodeId, child: NodeOrText<NodeId>) {
+        if !self.allow_child(parent) {
+            return;
+        }
         self.append_common(
             child,
             |document| document[parent].last_child,
@@ -406,6 +743,14 @@ impl TreeSink for Sink {
         &sibling: &NodeId,
         child: NodeOrText<NodeId>)
     {
+        if self.is_dropped(sibling) {
+            return;
+        }
+        if let Some(parent) = self.document[sibling].parent {
+            if !self.allow_child(parent) {
+                return;
+            }
+        }
         self.append_common(
             child,
             |document| document[sibling].prev_sibling,
@@ -419,7 +764,9 @@ impl TreeSink for Sink {
         prev_element: &NodeId,
         child: NodeOrText<NodeId>)
     {
-        if self.document[*element].parent.is_some() {
+        if !self.is_dropped(*element) &&
+            self.document[*element].parent.is_some()
+        {
             self.append_before_sibling(element, child)
         } else {
             self.append(prev_element, child)
@@ -429,13 +776,15 @@ impl TreeSink for Sink {
     fn append_doctype_to_document(
         &mut self,
         name: StrTendril,
-        _p_id: StrTendril,
-        _s_id: StrTendril)
+        public_id: StrTendril,
+        system_id: StrTendril)
     {
         let node = self.new_node(NodeData::DocType(
-            DocumentType { name, _priv: () }
+            DocumentType { name, public_id, system_id, _priv: () }
         ));
-        self.document.append(Document::DOCUMENT_NODE_ID, node)
+        if !self.is_dropped(node) {
+            self.document.append(Document::DOCUMENT_NODE_ID, node)
+        }
     }
 
     fn add_attrs_if_missing(
@@ -447,6 +796,9 @@ impl TreeSink for Sink {
         // html and body node attributes, but it definitely needs to be
         // implemented.
 
+        if self.is_dropped(target) {
+            return;
+        }
         let node = &mut self.document[target];
         let element = if let NodeData::Elem(e) = &mut node.data {
             e
@@ -464,13 +816,27 @@ impl TreeSink for Sink {
                 .into_iter()
                 .filter(|attr| !existing_names.contains(&attr.name)),
         );
+        if let Some(ref limiter) = self.limiter {
+            limiter.limit_attrs(&mut element.attrs, self.line);
+        }
+    }
+
+    fn pop(&mut self, &node: &NodeId) {
+        if self.spans {
+            self.document.set_source_end(node, SourcePos::at_line(self.line));
+        }
     }
 
     fn remove_from_parent(&mut self, &target: &NodeId) {
-        self.document.unlink_only(target)
+        if !self.is_dropped(target) {
+            self.document.unlink_only(target)
+        }
     }
 
     fn reparent_children(&mut self, &node: &NodeId, &new_parent: &NodeId) {
+        if self.is_dropped(node) || self.is_dropped(new_parent) {
+            return;
+        }
         let mut next_child = self.document[node].first_child;
         while let Some(child) = next_child {
             debug_assert_eq!(self.document[child].parent, Some(node));
@@ -478,6 +844,7 @@ impl TreeSink for Sink {
             // detaches the association.
             next_child = self.document[child].next_sibling;
             self.document.append(new_parent, child);
+            self.linked(child);
         }
     }
 }
@@ -0,0 +1,90 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// Default maximum number of errors kept by [`ParseErrors`], as used by
+/// [`parse_buffered_with_report`](super::parse_buffered_with_report).
+pub const DEFAULT_MAX_ERRORS: usize = 100;
+
+/// A non-fatal parse error, as reported by html5ever.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    line: u64,
+    message: Cow<'static, str>,
+}
+
+impl ParseError {
+    /// Return the 1-based line number of the input at which the error was
+    /// reported.
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    /// Return the error message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.line, self.message)
+    }
+}
+
+/// Non-fatal parse errors collected by a [`Sink`](super::Sink), up to a
+/// maximum count.
+#[derive(Clone, Debug)]
+pub struct ParseErrors {
+    errors: Vec<ParseError>,
+    count: usize,
+    max: usize,
+}
+
+/// `ParseErrors` that can be shared with a [`Sink`](super::Sink), by
+/// reference on the same thread, and internally mutated.
+pub type SharedParseErrors = Rc<RefCell<ParseErrors>>;
+
+impl ParseErrors {
+    /// Construct new, empty `ParseErrors`, which will keep up to the given
+    /// maximum number of errors.
+    pub fn new(max: usize) -> ParseErrors {
+        ParseErrors { errors: Vec::new(), count: 0, max }
+    }
+
+    /// Construct new, empty `ParseErrors` as per [`ParseErrors::new`],
+    /// wrapped for sharing.
+    pub fn shared(max: usize) -> SharedParseErrors {
+        Rc::new(RefCell::new(ParseErrors::new(max)))
+    }
+
+    /// Return the errors kept, in the order reported.
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// Return the total count of errors reported, including any beyond the
+    /// maximum which were not kept.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Return true if errors were reported beyond the maximum, and not kept.
+    pub fn truncated(&self) -> bool {
+        self.count > self.errors.len()
+    }
+
+    /// Remove all errors and reset the count.
+    pub fn clear(&mut self) {
+        self.errors.clear();
+        self.count = 0;
+    }
+
+    pub(crate) fn push(&mut self, line: u64, message: Cow<'static, str>) {
+        self.count += 1;
+        if self.errors.len() < self.max {
+            self.errors.push(ParseError { line, message });
+        }
+    }
+}
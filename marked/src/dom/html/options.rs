@@ -0,0 +1,138 @@
+use html5ever::interface::QuirksMode;
+use html5ever::tokenizer::TokenizerOpts;
+use html5ever::tree_builder::TreeBuilderOpts;
+use html5ever::ParseOpts;
+
+use crate::{Document, LimitError, ParseLimits, SharedEncodingHint};
+use crate::dom::limits::{check_limits, SharedLimitError};
+
+use super::{SharedParseErrors, Sink};
+
+/// Options for HTML parsing, as accepted by the `html::parse_*_with_options`
+/// functions, [`parse_buffered_with_report`](super::parse_buffered_with_report)
+/// and [`HtmlPushParser::with_options`](super::HtmlPushParser::with_options).
+///
+/// Use struct update syntax to set specific options:
+///
+/// ```
+/// use marked::html::{self, HtmlParseOptions, ParseErrors};
+///
+/// let errors = ParseErrors::shared(10);
+/// let opts = HtmlParseOptions {
+///     errors: Some(errors.clone()),
+///     ..HtmlParseOptions::default()
+/// };
+/// html::parse_utf8_with_options(b"<!DOCTYPE html><p>1</i>", &opts).unwrap();
+/// assert_eq!(1, errors.borrow().count());
+/// ```
+#[derive(Clone, Debug)]
+pub struct HtmlParseOptions {
+    /// If true, parse as if scripting is enabled, where the content of
+    /// `<noscript>` elements is raw text. If false, `<noscript>` content is
+    /// parsed as markup, as is preferable for text extraction. Default: true
+    pub scripting_enabled: bool,
+
+    /// If true, report parse errors with more detail (e.g. the offending
+    /// token), at some performance cost. Default: false
+    pub exact_errors: bool,
+
+    /// If true, any `<!DOCTYPE>` is not included in the `Document`.
+    /// Default: false
+    pub drop_doctype: bool,
+
+    /// If true, parse as the `srcdoc` document of an `<iframe>`, which is
+    /// never in quirks mode. Default: false
+    pub iframe_srcdoc: bool,
+
+    /// The initial quirks mode, which may be changed by the `<!DOCTYPE>` (or
+    /// its absence). For fragments, this is the quirks mode of the context
+    /// document. Default: `QuirksMode::NoQuirks`
+    pub quirks_mode: QuirksMode,
+
+    /// If set, non-fatal parse errors are collected, with line numbers, to
+    /// the given shared [`ParseErrors`](super::ParseErrors), instead of only
+    /// being logged. Any errors of a prior parse are cleared at the start of
+    /// each parse. Default: None
+    pub errors: Option<SharedParseErrors>,
+
+    /// If true, record the [`SourceSpan`](crate::SourceSpan) (by line only) of
+    /// each node, as available via
+    /// [`NodeRef::source_span`](crate::NodeRef::source_span). Default: false
+    pub spans: bool,
+
+    /// If set, the [`ParseLimits`] to enforce. If a limit is exceeded (and
+    /// `ParseLimits::truncate` is not set), the parse fails with a
+    /// [`LimitError`]. Default: None
+    pub limits: Option<ParseLimits>,
+}
+
+impl Default for HtmlParseOptions {
+    fn default() -> HtmlParseOptions {
+        HtmlParseOptions {
+            scripting_enabled: true,
+            exact_errors: false,
+            drop_doctype: false,
+            iframe_srcdoc: false,
+            quirks_mode: QuirksMode::NoQuirks,
+            errors: None,
+            spans: false,
+            limits: None,
+        }
+    }
+}
+
+impl HtmlParseOptions {
+    // Return a new sink configured per these options, recording any exceeded
+    // limit to the given shared error.
+    pub(crate) fn sink(
+        &self,
+        hint: SharedEncodingHint,
+        enc_check: bool,
+        exceeded: &SharedLimitError)
+        -> Sink
+    {
+        let mut sink = Sink::new(hint, enc_check)
+            .initial_quirks_mode(self.quirks_mode);
+        if let Some(ref errors) = self.errors {
+            // Errors of any prior (e.g. restarted) parse are discarded
+            errors.borrow_mut().clear();
+            sink = sink.collect_errors(errors.clone());
+        }
+        if self.spans {
+            sink = sink.record_spans();
+        }
+        if let Some(ref limits) = self.limits {
+            // Any limit exceeded by a prior, restarted parse is discarded
+            exceeded.set(None);
+            sink = sink.limit(limits, exceeded.clone());
+        }
+        sink
+    }
+
+    // Return the parsed document, or any exceeded limit error.
+    pub(crate) fn check(&self, doc: Document, exceeded: &SharedLimitError)
+        -> Result<Document, LimitError>
+    {
+        match self.limits {
+            Some(ref limits) => check_limits(doc, limits, exceeded),
+            None => Ok(doc),
+        }
+    }
+
+    pub(crate) fn parse_opts(&self) -> ParseOpts {
+        ParseOpts {
+            tokenizer: TokenizerOpts {
+                exact_errors: self.exact_errors,
+                ..Default::default()
+            },
+            tree_builder: TreeBuilderOpts {
+                exact_errors: self.exact_errors,
+                scripting_enabled: self.scripting_enabled,
+                iframe_srcdoc: self.iframe_srcdoc,
+                drop_doctype: self.drop_doctype,
+                quirks_mode: self.quirks_mode,
+                ..Default::default()
+            },
+        }
+    }
+}
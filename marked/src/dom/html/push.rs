@@ -0,0 +1,86 @@
+use html5ever::driver::Parser;
+use html5ever::parse_document;
+use log::debug;
+use tendril::{Tendril, TendrilSink};
+
+use crate::decode::BufferedDecoder;
+use crate::dom::limits::SharedLimitError;
+use crate::{Document, LimitError, SharedEncodingHint};
+
+use super::{HtmlParseOptions, Sink};
+
+type NewParser = Box<dyn Fn(bool) -> Parser<Sink>>;
+
+/// An incremental HTML parser to which bytes are pushed, e.g. as received in
+/// chunks from the network, instead of pulled from an `io::Read`.
+///
+/// Decoding is as per [`parse_buffered`](super::parse_buffered), including
+/// buffering of the initial [`INITIAL_BUFFER_SIZE`](crate::INITIAL_BUFFER_SIZE)
+/// bytes, and restarting the parse from the beginning on a compelling BOM,
+/// `<meta>` charset (or detected) encoding hint.
+///
+/// ```
+/// use marked::{html::HtmlPushParser, EncodingHint};
+///
+/// let eh = EncodingHint::shared_default(encoding_rs::UTF_8);
+/// let mut parser = HtmlPushParser::new(eh);
+/// parser.feed(b"<p>Hello, ");
+/// parser.feed(b"world</p>");
+/// let doc = parser.finish().unwrap();
+/// assert_eq!(
+///     "<html><head></head><body><p>Hello, world</p></body></html>",
+///     doc.to_string()
+/// );
+/// ```
+pub struct HtmlPushParser {
+    hint: SharedEncodingHint,
+    opts: HtmlParseOptions,
+    exceeded: SharedLimitError,
+    decoder: BufferedDecoder<Parser<Sink>, NewParser>,
+}
+
+impl HtmlPushParser {
+    /// Construct a new parser with the given [`SharedEncodingHint`], which
+    /// must have a top (e.g. default) encoding.
+    pub fn new(hint: SharedEncodingHint) -> HtmlPushParser {
+        HtmlPushParser::with_options(hint, &HtmlParseOptions::default())
+    }
+
+    /// Construct a new parser as per [`HtmlPushParser::new`], with the given
+    /// options.
+    pub fn with_options(hint: SharedEncodingHint, opts: &HtmlParseOptions)
+        -> HtmlPushParser
+    {
+        let exceeded = SharedLimitError::default();
+        let sink_opts = opts.clone();
+        let sink_exceeded = exceeded.clone();
+        let sink_hint = hint.clone();
+        let new_parser: NewParser = Box::new(move |enc_check| {
+            parse_document(
+                sink_opts.sink(sink_hint.clone(), enc_check, &sink_exceeded),
+                sink_opts.parse_opts()
+            )
+        });
+        HtmlPushParser {
+            hint: hint.clone(),
+            opts: opts.clone(),
+            exceeded,
+            decoder: BufferedDecoder::new(hint, new_parser),
+        }
+    }
+
+    /// Push the next chunk of bytes of the document to the parser.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.decoder.process(Tendril::from_slice(bytes));
+    }
+
+    /// Finish parsing at the end of the document, and return it.
+    ///
+    /// Returns a [`LimitError`] only if `HtmlParseOptions::limits` are given
+    /// and exceeded (without `ParseLimits::truncate`).
+    pub fn finish(self) -> Result<Document, LimitError> {
+        let (doc, _) = self.decoder.finish();
+        debug!("Final encoding errors {}", self.hint.borrow().errors());
+        self.opts.check(doc, &self.exceeded)
+    }
+}
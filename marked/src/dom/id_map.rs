@@ -0,0 +1,42 @@
+use std::num::NonZeroU32;
+
+use crate::dom::NodeId;
+
+/// A mapping of original to new `NodeId`s, as returned by
+/// [`Document::compact_with_map`](crate::Document::compact_with_map).
+///
+/// This may be used to translate any `NodeId`s retained outside of the
+/// `Document` (e.g. in indexes or annotations) after it is renumbered.
+#[derive(Clone, Debug, Default)]
+pub struct NodeIdMap {
+    // Indexed by original NodeId, including padding at index 0.
+    ids: Vec<Option<NodeId>>,
+}
+
+impl NodeIdMap {
+    pub(crate) fn with_len(len: usize) -> NodeIdMap {
+        NodeIdMap { ids: vec![None; len] }
+    }
+
+    pub(crate) fn insert(&mut self, old: NodeId, new: NodeId) {
+        self.ids[old.0.get() as usize] = Some(new);
+    }
+
+    /// Return the new `NodeId` for the given original `NodeId`, or `None` if
+    /// the node was removed (no longer referenced from the document node) or
+    /// the ID is out of range.
+    pub fn get(&self, old: NodeId) -> Option<NodeId> {
+        self.ids.get(old.0.get() as usize).and_then(|new| *new)
+    }
+
+    /// Return an iterator over all (original, new) `NodeId` pairs of
+    /// retained nodes, in original `NodeId` order.
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, NodeId)> + '_ {
+        self.ids.iter().enumerate().filter_map(|(i, new)| {
+            new.map(|new| {
+                let old = NonZeroU32::new(i as u32).unwrap();
+                (NodeId(old), new)
+            })
+        })
+    }
+}
@@ -0,0 +1,272 @@
+use std::cell::Cell;
+use std::error::Error as StdError;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::dom::{Attribute, Document, NodeId};
+
+/// Limits on the resources used by a parse, for untrusted input.
+///
+/// Once a limit is exceeded, further nodes, attributes or text beyond the
+/// limit are dropped, so the resulting `Document` is truncated. If
+/// `truncate` is false, the parse then fails with a [`LimitError`] for the
+/// first limit exceeded, otherwise the truncated `Document` is returned.
+/// Limits are given to the parser via the `limits` field of
+/// [`HtmlParseOptions`](crate::html::HtmlParseOptions) or (with the _xml_
+/// feature) `xml::XmlParseOptions`.
+///
+/// The default limits are effectively unlimited, other than the maximum
+/// number of nodes of a `Document`. Use struct update syntax to set
+/// specific limits:
+///
+/// ```
+/// use marked::{html::{self, HtmlParseOptions}, ParseLimits};
+///
+/// let limits = ParseLimits { max_depth: 3, ..ParseLimits::default() };
+/// let opts = HtmlParseOptions { limits: Some(limits), ..Default::default() };
+/// let res = html::parse_utf8_with_options(b"<div><div>x</div></div>", &opts);
+/// assert!(res.is_err());
+/// ```
+#[derive(Clone, Debug)]
+pub struct ParseLimits {
+    /// Maximum number of nodes, as per [`Document::len`], including the
+    /// document node. Default: [`ParseLimits::MAX_NODES`]
+    pub max_nodes: u32,
+
+    /// Maximum depth of an element or other node, where children of the
+    /// document node are at depth 1. Default: `u32::MAX`
+    pub max_depth: u32,
+
+    /// Maximum number of attributes per element. Default: `usize::MAX`
+    pub max_attributes: usize,
+
+    /// Maximum length in bytes of each attribute value. Default:
+    /// `usize::MAX`
+    pub max_attr_value_len: usize,
+
+    /// Maximum total bytes of text, comment and processing instruction
+    /// content. Default: `usize::MAX`
+    pub max_text_bytes: usize,
+
+    /// If true, return the truncated `Document` instead of a [`LimitError`].
+    /// Default: false
+    pub truncate: bool,
+}
+
+impl ParseLimits {
+    /// The largest supported `max_nodes`, leaving some of the `u32` index
+    /// space unused for parser book-keeping.
+    pub const MAX_NODES: u32 = std::u32::MAX - 0x1_0000;
+}
+
+impl Default for ParseLimits {
+    fn default() -> ParseLimits {
+        ParseLimits {
+            max_nodes: ParseLimits::MAX_NODES,
+            max_depth: std::u32::MAX,
+            max_attributes: std::usize::MAX,
+            max_attr_value_len: std::usize::MAX,
+            max_text_bytes: std::usize::MAX,
+            truncate: false,
+        }
+    }
+}
+
+/// A specific limit of [`ParseLimits`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    /// `ParseLimits::max_nodes`
+    Nodes,
+    /// `ParseLimits::max_depth`
+    Depth,
+    /// `ParseLimits::max_attributes`
+    Attributes,
+    /// `ParseLimits::max_attr_value_len`
+    AttrValueLen,
+    /// `ParseLimits::max_text_bytes`
+    TextBytes,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Nodes => "max_nodes",
+            Limit::Depth => "max_depth",
+            Limit::Attributes => "max_attributes",
+            Limit::AttrValueLen => "max_attr_value_len",
+            Limit::TextBytes => "max_text_bytes",
+        })
+    }
+}
+
+/// Error returned when a [`ParseLimits`] limit is exceeded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LimitError {
+    limit: Limit,
+    max: usize,
+    line: u64,
+}
+
+impl LimitError {
+    /// Return the limit which was (first) exceeded.
+    pub fn limit(&self) -> Limit {
+        self.limit
+    }
+
+    /// Return the configured maximum of the exceeded limit.
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Return the 1-based line number of the input at which the limit was
+    /// exceeded.
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} of {} exceeded", self.line, self.limit, self.max)
+    }
+}
+
+impl StdError for LimitError {}
+
+// The first `LimitError` of a parse, shared with its sink.
+pub(crate) type SharedLimitError = Rc<Cell<Option<LimitError>>>;
+
+// Enforces `ParseLimits` for a parser, recording the first limit exceeded.
+pub(crate) struct Limiter {
+    limits: ParseLimits,
+    text_bytes: usize,
+    // Depth of each node by `NodeId` index, as recorded via `linked`, only
+    // if `max_depth` is limited. Unrecorded nodes are at depth 0.
+    depths: Vec<u32>,
+    exceeded: SharedLimitError,
+}
+
+impl Limiter {
+    pub(crate) fn new(limits: &ParseLimits, exceeded: SharedLimitError)
+        -> Limiter
+    {
+        let mut limits = limits.clone();
+        limits.max_nodes = limits.max_nodes.min(ParseLimits::MAX_NODES);
+        Limiter { limits, text_bytes: 0, depths: Vec::new(), exceeded }
+    }
+
+    fn exceed(&self, limit: Limit, max: usize, line: u64) {
+        if self.exceeded.get().is_none() {
+            self.exceeded.set(Some(LimitError { limit, max, line }));
+        }
+    }
+
+    // Return true if a node may be added to doc.
+    pub(crate) fn allow_node(&self, doc: &Document, line: u64) -> bool {
+        if doc.len() < self.limits.max_nodes {
+            true
+        } else {
+            self.exceed(Limit::Nodes, self.limits.max_nodes as usize, line);
+            false
+        }
+    }
+
+    // Return true if a node may be added as a child of parent.
+    pub(crate) fn allow_child(&self, parent: NodeId, line: u64) -> bool {
+        let max = self.limits.max_depth;
+        if self.depth(parent) < max {
+            true
+        } else {
+            self.exceed(Limit::Depth, max as usize, line);
+            false
+        }
+    }
+
+    // Record the depth of node id, as just linked as a child of parent.
+    pub(crate) fn linked(&mut self, id: NodeId, parent: NodeId) {
+        if self.limits.max_depth == std::u32::MAX {
+            return;
+        }
+        let depth = self.depth(parent) + 1;
+        let i = id.0.get() as usize;
+        if self.depths.len() <= i {
+            self.depths.resize(i + 1, 0);
+        }
+        self.depths[i] = depth;
+    }
+
+    fn depth(&self, id: NodeId) -> u32 {
+        self.depths.get(id.0.get() as usize).cloned().unwrap_or(0)
+    }
+
+    // Truncate the number of attributes and the length of their values.
+    pub(crate) fn limit_attrs(&self, attrs: &mut Vec<Attribute>, line: u64) {
+        let max = self.limits.max_attributes;
+        if attrs.len() > max {
+            self.exceed(Limit::Attributes, max, line);
+            attrs.truncate(max);
+        }
+        let max = self.limits.max_attr_value_len;
+        for attr in attrs.iter_mut() {
+            if attr.value.len() > max {
+                self.exceed(Limit::AttrValueLen, max, line);
+                let end = floor_char_boundary(&attr.value, max);
+                attr.value.pop_back(attr.value.len32() - end as u32);
+            }
+        }
+    }
+
+    // Count the given text, returning its length in bytes which may be kept,
+    // without splitting a char.
+    pub(crate) fn allow_text(&mut self, text: &str, line: u64) -> usize {
+        let max = self.limits.max_text_bytes;
+        let allowed = max - self.text_bytes;
+        if text.len() <= allowed {
+            self.text_bytes += text.len();
+            text.len()
+        } else {
+            self.exceed(Limit::TextBytes, max, line);
+            let end = floor_char_boundary(text, allowed);
+            self.text_bytes += end;
+            end
+        }
+    }
+
+    // Return true if any limit has been exceeded.
+    pub(crate) fn exceeded(&self) -> bool {
+        self.exceeded.get().is_some()
+    }
+
+    // Return true if a limit has been exceeded and, not truncating, the
+    // parse should stop.
+    #[cfg(feature = "xml")]
+    pub(crate) fn stop(&self) -> bool {
+        !self.limits.truncate && self.exceeded()
+    }
+}
+
+// Return the document, or the first exceeded limit error, unless truncating.
+pub(crate) fn check_limits(
+    doc: Document,
+    limits: &ParseLimits,
+    exceeded: &SharedLimitError)
+    -> Result<Document, LimitError>
+{
+    match exceeded.get() {
+        Some(e) if !limits.truncate => Err(e),
+        _ => Ok(doc),
+    }
+}
+
+// Return the largest index not greater than i which is on a char boundary of
+// s.
+fn floor_char_boundary(s: &str, i: usize) -> usize {
+    if i >= s.len() {
+        return s.len();
+    }
+    let mut i = i;
+    while !s.is_char_boundary(i) {
+        i -= 1;
+    }
+    i
+}
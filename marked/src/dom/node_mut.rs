@@ -0,0 +1,192 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::dom::{
+    Document, Element, LocalName, Node, NodeData, NodeId, NodeRef,
+    StrTendril,
+};
+
+/// A `Node` within `Document` lifetime mutable reference.
+///
+/// This provides chainable methods for editing the tree around the node,
+/// with the same linkage invariants as the `Document` methods. Nodes to be
+/// added are given as new, unlinked `Node`s, e.g. via [`Node::new_elem`] or
+/// [`Node::new_text`].
+///
+/// ```
+/// use marked::{Document, Element, Node};
+///
+/// let mut doc = Document::new();
+/// let div = doc.append_child(
+///     Document::DOCUMENT_NODE_ID,
+///     Node::new_elem(Element::new("div")));
+/// doc.node_mut(div)
+///     .set_attr("class", "greeting")
+///     .append(Node::new_text("world"))
+///     .prepend(Node::new_text("hello "))
+///     .wrap_in(Element::new("section"));
+/// assert_eq!(
+///     r#"<section><div class="greeting">hello world</div></section>"#,
+///     doc.to_string()
+/// );
+/// ```
+pub struct NodeMut<'a> {
+    doc: &'a mut Document,
+    id: NodeId,
+}
+
+impl<'a> NodeMut<'a> {
+    /// Constructor.
+    #[inline]
+    pub fn new(doc: &'a mut Document, id: NodeId) -> Self {
+        NodeMut { doc, id }
+    }
+
+    /// Return the associated `NodeId`.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Return a read-only `NodeRef` for this node.
+    pub fn as_ref(&self) -> NodeRef<'_> {
+        NodeRef::new(self.doc, self.id)
+    }
+
+    /// Append node as the new last child of this node.
+    ///
+    /// Debug-asserts that this node is an element or the document node.
+    pub fn append(&mut self, node: Node) -> &mut Self {
+        self.doc.append_child(self.id, node);
+        self
+    }
+
+    /// Prepend node as the new first child of this node.
+    ///
+    /// Debug-asserts that this node is an element or the document node.
+    pub fn prepend(&mut self, node: Node) -> &mut Self {
+        self.doc.prepend_child(self.id, node);
+        self
+    }
+
+    /// Insert node as the previous sibling of this node.
+    ///
+    /// Panics if this node has no parent.
+    pub fn insert_before(&mut self, node: Node) -> &mut Self {
+        self.doc.insert_before_sibling(self.id, node);
+        self
+    }
+
+    /// Insert node as the next sibling of this node.
+    ///
+    /// Panics if this node has no parent.
+    pub fn insert_after(&mut self, node: Node) -> &mut Self {
+        if let Some(next) = self.doc[self.id].next_sibling {
+            self.doc.insert_before_sibling(next, node);
+        } else {
+            let parent = self.doc[self.id].parent
+                .expect("insert_after node has no parent");
+            self.doc.append_child(parent, node);
+        }
+        self
+    }
+
+    /// Replace this node (and its descendants) with the given node, which is
+    /// then referenced by self.
+    ///
+    /// The replaced node is [unlinked][Document::unlink]. Panics if this
+    /// node has no parent.
+    pub fn replace_with(&mut self, node: Node) -> &mut Self {
+        let id = self.doc.insert_before_sibling(self.id, node);
+        self.doc.unlink(self.id);
+        self.id = id;
+        self
+    }
+
+    /// Wrap this node in the given element, inserted in its place, with this
+    /// node as its only child.
+    ///
+    /// Panics if this node has no parent.
+    pub fn wrap_in(&mut self, element: Element) -> &mut Self {
+        self.doc.wrap(self.id, element);
+        self
+    }
+
+    /// Replace this node with its children, returning its original
+    /// `NodeData`.
+    ///
+    /// This is equivalent to [`Document::fold`].
+    pub fn unwrap(self) -> NodeData {
+        self.doc.fold(self.id)
+    }
+
+    /// Remove this node (and its descendants) from the `Document`, returning
+    /// its original `NodeData`.
+    ///
+    /// This is equivalent to [`Document::unlink`]. To obtain the entire
+    /// sub-tree, use [`Document::detach`] instead.
+    pub fn remove(self) -> NodeData {
+        self.doc.unlink(self.id)
+    }
+
+    /// Set attribute on this element node, as per [`Element::set_attr`].
+    ///
+    /// Panics if this is not an element node.
+    pub fn set_attr<LN, V>(&mut self, lname: LN, value: V) -> &mut Self
+        where LN: Into<LocalName>, V: Into<StrTendril>
+    {
+        self.doc[self.id]
+            .as_element_mut()
+            .expect("set_attr on a non-element node")
+            .set_attr(lname, value);
+        self
+    }
+
+    /// Set the text of this node.
+    ///
+    /// If this is a text node, its text is replaced. If this is an element
+    /// or the document node, all children are [unlinked][Document::unlink]
+    /// and replaced with a single text node. Panics for all other node
+    /// types.
+    pub fn set_text<T>(&mut self, text: T) -> &mut Self
+        where T: Into<StrTendril>
+    {
+        match self.doc[self.id].data {
+            NodeData::Text(ref mut t) => {
+                *t = text.into();
+            }
+            NodeData::Document | NodeData::Elem(_) => {
+                let children = self.doc.children(self.id)
+                    .collect::<Vec<_>>();
+                for child in children {
+                    self.doc.unlink(child);
+                }
+                self.doc.append_child(self.id, Node::new_text(text));
+            }
+            ref data => panic!("set_text on {:?}", data),
+        }
+        self
+    }
+}
+
+impl<'a> Deref for NodeMut<'a> {
+    type Target = Node;
+
+    #[inline]
+    fn deref(&self) -> &Node {
+        &self.doc[self.id]
+    }
+}
+
+impl<'a> DerefMut for NodeMut<'a> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Node {
+        &mut self.doc[self.id]
+    }
+}
+
+/// `NodeMut` convenence accessor methods.
+impl Document {
+    /// Return a `NodeMut` for editing the tree around the specified node.
+    pub fn node_mut(&mut self, id: NodeId) -> NodeMut<'_> {
+        NodeMut::new(self, id)
+    }
+}
@@ -9,8 +9,8 @@ use crate::dom::{Document, Node, NodeId, StrTendril, NodeStack1};
 /// This provides convenient but necessarily read-only access.
 #[derive(Copy, Clone)]
 pub struct NodeRef<'a>{
-    doc: &'a Document,
-    id: NodeId
+    pub(crate) doc: &'a Document,
+    pub(crate) id: NodeId
 }
 
 impl<'a> NodeRef<'a> {
@@ -0,0 +1,390 @@
+use std::io;
+use std::io::Write;
+use std::mem;
+
+use html5ever::serialize::{
+    HtmlSerializer, Serialize, SerializeOpts, Serializer,
+    TraversalScope::*
+};
+
+use crate::dom::{
+    filter::{is_block, is_preform_node},
+    html::t,
+    Document, NodeData, NodeRef,
+};
+
+/// Options for [`Document::serialize_pretty`] and
+/// [`NodeRef::serialize_pretty`].
+#[derive(Clone, Debug)]
+pub struct PrettyOptions {
+    /// Number of spaces per indentation level. Default: 2
+    pub indent: usize,
+
+    /// Maximum line width (in chars) beyond which inline content is
+    /// wrapped. Lines are only broken where white-space already exists, and
+    /// never in preformatted content, so some lines may still exceed this
+    /// width. Default: 80
+    pub max_width: usize,
+
+    /// If true, also place the children of inline elements on separate,
+    /// indented lines, as is done for block elements.  Note that this adds
+    /// white-space between inline elements and text, which may change how
+    /// the HTML is rendered. Default: false
+    pub indent_inline: bool,
+}
+
+impl Default for PrettyOptions {
+    fn default() -> PrettyOptions {
+        PrettyOptions { indent: 2, max_width: 80, indent_inline: false }
+    }
+}
+
+/// Pretty serialize convenience method.
+impl Document {
+    /// Serialize the contents of the document node and descendants in HTML
+    /// syntax to the given stream, with indentation and line wrapping per the
+    /// given options.
+    ///
+    /// White-space is only added or changed where it is insignificant to
+    /// HTML rendering: between block elements, and as collapsible runs
+    /// within inline content. Preformatted elements (e.g. `<pre>`,
+    /// `<textarea>`) and raw text elements (e.g. `<script>`) are written
+    /// as-is.
+    pub fn serialize_pretty<W>(&self, writer: &mut W, opts: &PrettyOptions)
+        -> io::Result<()>
+        where W: Write
+    {
+        Pretty::new(writer, opts).write_node(self.document_node_ref(), 0)?;
+        writer.write_all(b"\n")
+    }
+}
+
+/// Pretty serialize convenience method.
+impl<'a> NodeRef<'a> {
+    /// Serialize the referenced node and its descendants in HTML syntax to the
+    /// given stream, with indentation and line wrapping per the given options.
+    ///
+    /// See [`Document::serialize_pretty`].
+    pub fn serialize_pretty<W>(&self, writer: &mut W, opts: &PrettyOptions)
+        -> io::Result<()>
+        where W: Write
+    {
+        let node = *self;
+        let mut pretty = Pretty::new(writer, opts);
+        if node.node_and_ancestors().any(is_preserved) {
+            pretty.write_verbatim(node)?;
+        } else {
+            pretty.write_node(node, 0)?;
+        }
+        writer.write_all(b"\n")
+    }
+}
+
+// A layout token of inline content
+enum Token {
+    // Serialized markup or text, which can't be broken
+    Word(String),
+
+    // Collapsible white-space, which can be output as a single space or a
+    // line break.
+    Space,
+}
+
+struct Pretty<'w, 'o, W: Write> {
+    out: &'w mut W,
+    opts: &'o PrettyOptions,
+    scratch: HtmlSerializer<Vec<u8>>,
+    col: usize,
+    started: bool,
+}
+
+impl<'w, 'o, W: Write> Pretty<'w, 'o, W> {
+    fn new(out: &'w mut W, opts: &'o PrettyOptions) -> Self {
+        let scratch = HtmlSerializer::new(
+            Vec::new(),
+            SerializeOpts {
+                traversal_scope: ChildrenOnly(None),
+                ..Default::default()
+            }
+        );
+        Pretty { out, opts, scratch, col: 0, started: false }
+    }
+
+    // Write the given node, and descendants, starting on a new line at the
+    // given depth.
+    fn write_node(&mut self, node: NodeRef<'_>, depth: usize)
+        -> io::Result<()>
+    {
+        match node.data {
+            NodeData::Document => self.write_children(node, depth),
+            NodeData::Elem(ref elm) => {
+                self.new_line(depth)?;
+                if is_preserved(node) {
+                    return self.write_verbatim(node);
+                }
+                self.scratch.start_elem(
+                    elm.name.clone(),
+                    elm.attrs.iter().map(|a| (&a.name, a.value.as_ref()))
+                )?;
+                let stag = self.take();
+                self.write(&stag)?;
+
+                let has_content = node.children().any(|c| !is_ws(c));
+                if has_content {
+                    if node.children().any(|c| self.is_block_child(c)) {
+                        self.write_children(node, depth + 1)?;
+                        self.new_line(depth)?;
+                    } else {
+                        let mut tokens = Vec::new();
+                        for child in node.children() {
+                            self.tokens(child, &mut tokens)?;
+                        }
+                        trim(&mut tokens);
+                        let etag_len = elm.name.local.len() + 3;
+                        if self.col + width(&tokens) + etag_len
+                            <= self.opts.max_width
+                        {
+                            self.write_flow(&tokens, usize::max_value())?;
+                        } else {
+                            self.new_line(depth + 1)?;
+                            self.write_flow(&tokens, depth + 1)?;
+                            self.new_line(depth)?;
+                        }
+                    }
+                }
+
+                self.scratch.end_elem(elm.name.clone())?;
+                let etag = self.take();
+                self.write(&etag)
+            }
+            NodeData::Hole => panic!("Hole in Document"),
+            _ => {
+                self.new_line(depth)?;
+                self.write_verbatim(node)
+            }
+        }
+    }
+
+    // Write children of node, each block on a new line, and each run of
+    // inline content as a wrapped paragraph.
+    fn write_children(&mut self, node: NodeRef<'_>, depth: usize)
+        -> io::Result<()>
+    {
+        let mut tokens = Vec::new();
+        for child in node.children() {
+            let inline = match child.data {
+                NodeData::Text(_) => true,
+                NodeData::Elem(_) => !self.is_block_child(child),
+                NodeData::Comment(_) | NodeData::Pi(_) => {
+                    tokens.iter().any(|t| if let Token::Word(_) = t {
+                        true
+                    } else {
+                        false
+                    })
+                }
+                _ => false,
+            };
+            if inline {
+                self.tokens(child, &mut tokens)?;
+            } else {
+                self.write_paragraph(&mut tokens, depth)?;
+                self.write_node(child, depth)?;
+            }
+        }
+        self.write_paragraph(&mut tokens, depth)
+    }
+
+    // Write (and clear) any inline tokens starting on a new line.
+    fn write_paragraph(&mut self, tokens: &mut Vec<Token>, depth: usize)
+        -> io::Result<()>
+    {
+        trim(tokens);
+        if !tokens.is_empty() {
+            self.new_line(depth)?;
+            self.write_flow(tokens, depth)?;
+            tokens.clear();
+        }
+        Ok(())
+    }
+
+    // Write inline tokens, breaking lines at spaces which would otherwise
+    // exceed max_width, indenting continuation lines to depth.
+    fn write_flow(&mut self, tokens: &[Token], depth: usize)
+        -> io::Result<()>
+    {
+        for (i, token) in tokens.iter().enumerate() {
+            match token {
+                Token::Word(w) => self.write(w)?,
+                Token::Space => {
+                    let next = width_to_space(&tokens[i + 1..]);
+                    if  depth != usize::max_value() &&
+                        self.col > depth * self.opts.indent &&
+                        self.col + 1 + next > self.opts.max_width
+                    {
+                        self.new_line(depth)?;
+                    } else {
+                        self.write(" ")?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Push inline tokens for node and its descendants.
+    fn tokens(&mut self, node: NodeRef<'_>, tokens: &mut Vec<Token>)
+        -> io::Result<()>
+    {
+        match node.data {
+            NodeData::Text(ref t) => {
+                if t.starts_with(is_html_ws) {
+                    push_space(tokens);
+                }
+                for (i, word) in t.split(is_html_ws)
+                    .filter(|w| !w.is_empty())
+                    .enumerate()
+                {
+                    if i > 0 {
+                        push_space(tokens);
+                    }
+                    self.scratch.write_text(word)?;
+                    tokens.push(Token::Word(self.take()));
+                }
+                if t.ends_with(is_html_ws) {
+                    push_space(tokens);
+                }
+            }
+            NodeData::Elem(ref elm) if !is_preserved(node) => {
+                self.scratch.start_elem(
+                    elm.name.clone(),
+                    elm.attrs.iter().map(|a| (&a.name, a.value.as_ref()))
+                )?;
+                tokens.push(Token::Word(self.take()));
+                for child in node.children() {
+                    self.tokens(child, tokens)?;
+                }
+                self.scratch.end_elem(elm.name.clone())?;
+                let etag = self.take();
+                if !etag.is_empty() {
+                    tokens.push(Token::Word(etag));
+                }
+            }
+            _ => {
+                Serialize::serialize(&node, &mut self.scratch, IncludeNode)?;
+                tokens.push(Token::Word(self.take()));
+            }
+        }
+        Ok(())
+    }
+
+    // Write the node and descendants without any layout changes.
+    fn write_verbatim(&mut self, node: NodeRef<'_>) -> io::Result<()> {
+        Serialize::serialize(&node, &mut self.scratch, IncludeNode)?;
+        let s = self.take();
+        self.write(&s)
+    }
+
+    fn is_block_child(&self, node: NodeRef<'_>) -> bool {
+        if self.opts.indent_inline {
+            node.as_element().is_some()
+        } else {
+            is_block(node)
+        }
+    }
+
+    fn new_line(&mut self, depth: usize) -> io::Result<()> {
+        if self.started {
+            self.out.write_all(b"\n")?;
+        }
+        let indent = depth * self.opts.indent;
+        for _ in 0..indent {
+            self.out.write_all(b" ")?;
+        }
+        self.col = indent;
+        self.started = true;
+        Ok(())
+    }
+
+    fn write(&mut self, s: &str) -> io::Result<()> {
+        self.out.write_all(s.as_bytes())?;
+        if let Some(i) = s.rfind('\n') {
+            self.col = s[i+1..].chars().count();
+        } else {
+            self.col += s.chars().count();
+        }
+        self.started = true;
+        Ok(())
+    }
+
+    // Take what was written to the scratch serializer.
+    fn take(&mut self) -> String {
+        let buf = mem::replace(&mut self.scratch.writer, Vec::new());
+        String::from_utf8(buf).expect("serialized UTF-8")
+    }
+}
+
+fn push_space(tokens: &mut Vec<Token>) {
+    if let Some(Token::Space) = tokens.last() {
+        return;
+    }
+    tokens.push(Token::Space);
+}
+
+fn trim(tokens: &mut Vec<Token>) {
+    if let Some(Token::Space) = tokens.last() {
+        tokens.pop();
+    }
+    if let Some(Token::Space) = tokens.first() {
+        tokens.remove(0);
+    }
+}
+
+fn width(tokens: &[Token]) -> usize {
+    tokens.iter().map(|t| match t {
+        Token::Word(w) => word_width(w),
+        Token::Space => 1,
+    }).sum()
+}
+
+// Width of tokens up to the next space.
+fn width_to_space(tokens: &[Token]) -> usize {
+    let mut w = 0;
+    for t in tokens {
+        match t {
+            Token::Word(word) => w += word_width(word),
+            Token::Space => break,
+        }
+    }
+    w
+}
+
+// Width of the first line of a word, which for preserved content may include
+// line breaks.
+fn word_width(word: &str) -> usize {
+    word.split('\n').next().unwrap().chars().count()
+}
+
+// Elements for which content is written as-is: preformatted or raw text.
+fn is_preserved(node: NodeRef<'_>) -> bool {
+    /**/is_preform_node(node) ||
+        node.is_elem(t::LISTING) ||
+        node.is_elem(t::TEXTAREA) ||
+        node.is_elem(t::SCRIPT) ||
+        node.is_elem(t::STYLE) ||
+        node.is_elem(t::NOSCRIPT) ||
+        node.is_elem(t::IFRAME) ||
+        node.is_elem(t::NOFRAMES)
+}
+
+// HTML (ASCII) white-space, which collapses in rendering.
+fn is_html_ws(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\n' || c == '\x0C' || c == '\r'
+}
+
+fn is_ws(node: NodeRef<'_>) -> bool {
+    if let Some(t) = node.as_text() {
+        t.chars().all(is_html_ws)
+    } else {
+        false
+    }
+}
@@ -0,0 +1,453 @@
+//! Serde `Serialize` and `Deserialize` implementations (_serde_ feature).
+//!
+//! A `Document` is represented flat, as its vector of nodes with their
+//! `NodeId` links, so as to be restored exactly, including any nodes not
+//! (or no longer) referenced from the document node. The links are checked
+//! on deserialization. All fields are serialized, in a fixed order, even when
+//! empty, as required of non-self-describing formats such as _bincode_.
+//!
+//! A `NodeRef` is alternatively represented as a compact nested tree, for
+//! inspection (see [`NodeRef::to_json`]).
+
+use std::num::NonZeroU32;
+use std::ops::Deref;
+
+use html5ever::Prefix;
+use serde_crate::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_crate::ser::{SerializeMap, SerializeSeq, SerializeStruct};
+
+use crate::dom::{
+    Attribute, Document, DocumentType, Element, LocalName, Namespace, Node,
+    NodeData, NodeId, NodeRef, ProcessingInstruction, QualName, QuirksMode,
+    SourcePos, SourceSpan, StrTendril,
+};
+
+impl Serialize for NodeId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_u32(self.0.get())
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeId {
+    fn deserialize<D>(deserializer: D) -> Result<NodeId, D::Error>
+        where D: Deserializer<'de>
+    {
+        let id = u32::deserialize(deserializer)?;
+        NonZeroU32::new(id)
+            .map(NodeId)
+            .ok_or_else(|| de::Error::custom("invalid NodeId 0"))
+    }
+}
+
+// (De-)serialize any atom or tendril as a string.
+mod as_str {
+    use super::*;
+
+    pub(super) fn serialize<T, S>(v: &T, serializer: S)
+        -> Result<S::Ok, S::Error>
+        where T: Deref<Target = str>, S: Serializer
+    {
+        serializer.serialize_str(v)
+    }
+
+    pub(super) fn deserialize<'de, T, D>(deserializer: D)
+        -> Result<T, D::Error>
+        where T: From<String>, D: Deserializer<'de>
+    {
+        String::deserialize(deserializer).map(T::from)
+    }
+}
+
+// (De-)serialize an optional prefix as an optional string.
+mod opt_prefix {
+    use super::*;
+
+    pub(super) fn serialize<S>(v: &Option<Prefix>, serializer: S)
+        -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        match v {
+            Some(p) => serializer.serialize_some(&**p),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D>(deserializer: D)
+        -> Result<Option<Prefix>, D::Error>
+        where D: Deserializer<'de>
+    {
+        Ok(Option::<String>::deserialize(deserializer)?.map(Prefix::from))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde_crate", remote = "QualName")]
+struct QualNameDef {
+    #[serde(with = "opt_prefix")]
+    prefix: Option<Prefix>,
+    #[serde(with = "as_str")]
+    ns: Namespace,
+    #[serde(with = "as_str")]
+    local: LocalName,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde_crate", remote = "Attribute")]
+struct AttributeDef {
+    #[serde(with = "QualNameDef")]
+    name: QualName,
+    #[serde(with = "as_str")]
+    value: StrTendril,
+}
+
+struct AttrRef<'a>(&'a Attribute);
+
+impl Serialize for AttrRef<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        AttributeDef::serialize(self.0, serializer)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "serde_crate")]
+struct AttrOwned(#[serde(with = "AttributeDef")] Attribute);
+
+mod attrs {
+    use super::*;
+
+    pub(super) fn serialize<S>(v: &[Attribute], serializer: S)
+        -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_seq(v.iter().map(AttrRef))
+    }
+
+    pub(super) fn deserialize<'de, D>(deserializer: D)
+        -> Result<Vec<Attribute>, D::Error>
+        where D: Deserializer<'de>
+    {
+        let attrs = Vec::<AttrOwned>::deserialize(deserializer)?;
+        Ok(attrs.into_iter().map(|a| a.0).collect())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde_crate", remote = "Element")]
+struct ElementDef {
+    #[serde(with = "QualNameDef")]
+    name: QualName,
+    #[serde(with = "attrs")]
+    attrs: Vec<Attribute>,
+    #[serde(skip)]
+    _priv: (),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde_crate", remote = "DocumentType")]
+struct DocumentTypeDef {
+    #[serde(with = "as_str")]
+    name: StrTendril,
+    #[serde(with = "as_str")]
+    public_id: StrTendril,
+    #[serde(with = "as_str")]
+    system_id: StrTendril,
+    #[serde(skip)]
+    _priv: (),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde_crate", remote = "ProcessingInstruction")]
+struct ProcessingInstructionDef {
+    #[serde(with = "as_str")]
+    target: StrTendril,
+    #[serde(with = "as_str")]
+    data: StrTendril,
+    #[serde(skip)]
+    _priv: (),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde_crate", remote = "NodeData")]
+enum NodeDataDef {
+    Hole,
+    Document,
+    DocType(#[serde(with = "DocumentTypeDef")] DocumentType),
+    Text(#[serde(with = "as_str")] StrTendril),
+    Comment(#[serde(with = "as_str")] StrTendril),
+    Elem(#[serde(with = "ElementDef")] Element),
+    Pi(#[serde(with = "ProcessingInstructionDef")] ProcessingInstruction),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde_crate", remote = "Node")]
+struct NodeDef {
+    #[serde(with = "NodeDataDef")]
+    data: NodeData,
+    parent: Option<NodeId>,
+    prev_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde_crate", remote = "QuirksMode")]
+enum QuirksModeDef {
+    Quirks,
+    LimitedQuirks,
+    NoQuirks,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde_crate", remote = "SourcePos")]
+struct SourcePosDef {
+    line: u32,
+    column: Option<u32>,
+    offset: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+struct Span(
+    #[serde(with = "SourcePosDef")] SourcePos,
+    #[serde(with = "SourcePosDef")] SourcePos,
+);
+
+// Implement the public traits via the remote definitions above.
+macro_rules! via_remote {
+    ($($typ:ident => $def:ident),*) => {$(
+        impl Serialize for $typ {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+                where S: Serializer
+            {
+                $def::serialize(self, serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $typ {
+            fn deserialize<D>(deserializer: D) -> Result<$typ, D::Error>
+                where D: Deserializer<'de>
+            {
+                $def::deserialize(deserializer)
+            }
+        }
+    )*};
+}
+
+via_remote!(
+    Node => NodeDef,
+    NodeData => NodeDataDef,
+    Element => ElementDef,
+    DocumentType => DocumentTypeDef,
+    ProcessingInstruction => ProcessingInstructionDef
+);
+
+struct QuirksModeRef(QuirksMode);
+
+impl Serialize for QuirksModeRef {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        QuirksModeDef::serialize(&self.0, serializer)
+    }
+}
+
+struct Spans<'a>(&'a [Option<SourceSpan>]);
+
+impl Serialize for Spans<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        // Skip the padding index 0, as with nodes
+        let spans = self.0.iter().skip(1)
+            .map(|s| s.map(|s| Span(s.start, s.end)));
+        serializer.collect_seq(spans)
+    }
+}
+
+impl Serialize for Document {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut state = serializer.serialize_struct("Document", 4)?;
+        let quirks_mode = QuirksModeRef(self.quirks_mode);
+        state.serialize_field("quirks_mode", &quirks_mode)?;
+        // Skip the padding node at index 0, so that NodeId 1 is first
+        state.serialize_field("nodes", &self.nodes[1..])?;
+        // Empty if no spans were recorded
+        state.serialize_field("spans", &Spans(&self.spans))?;
+        // As (template NodeId, contents) pairs
+        let templates = self.templates.iter().collect::<Vec<_>>();
+        state.serialize_field("templates", &templates)?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "serde_crate")]
+struct DocumentRepr {
+    #[serde(with = "QuirksModeDef")]
+    quirks_mode: QuirksMode,
+    nodes: Vec<Node>,
+    spans: Vec<Option<Span>>,
+    templates: Vec<(NodeId, Document)>,
+}
+
+impl<'de> Deserialize<'de> for Document {
+    fn deserialize<D>(deserializer: D) -> Result<Document, D::Error>
+        where D: Deserializer<'de>
+    {
+        let repr = DocumentRepr::deserialize(deserializer)?;
+
+        let mut nodes = Vec::with_capacity(repr.nodes.len() + 1);
+        nodes.push(Node::new(NodeData::Hole)); // Index 0: Padding
+        nodes.extend(repr.nodes);
+
+        let mut spans = Vec::new();
+        if !repr.spans.is_empty() {
+            spans.reserve(repr.spans.len() + 1);
+            spans.push(None);
+            spans.extend(repr.spans.into_iter().map(|s| {
+                s.map(|Span(start, end)| SourceSpan { start, end })
+            }));
+        }
+
+        let templates = repr.templates.into_iter().collect();
+
+        let doc = Document {
+            nodes,
+            spans,
+            templates,
+            quirks_mode: repr.quirks_mode,
+        };
+        doc.check_links().map_err(de::Error::custom)?;
+        Ok(doc)
+    }
+}
+
+/// Compact nested representation, for inspection.
+///
+/// The document node is represented as the sequence of its children, text as
+/// a string and elements as a map of `tag`, `attrs` (as a map), `children`
+/// and template `content`, where not empty. Other nodes are represented as a
+/// map with a single entry by kind, e.g. `{"comment": "text"}`.
+impl Serialize for NodeRef<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        match self.data {
+            NodeData::Hole => serializer.serialize_unit(),
+            NodeData::Document => Children(*self).serialize(serializer),
+            NodeData::DocType(ref dt) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("doctype", &*dt.name)?;
+                map.end()
+            }
+            NodeData::Text(ref t) => serializer.serialize_str(t),
+            NodeData::Comment(ref t) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("comment", &**t)?;
+                map.end()
+            }
+            NodeData::Pi(ref pi) => {
+                let mut map = serializer.serialize_map(Some(2))?;
+                map.serialize_entry("pi", &*pi.target)?;
+                map.serialize_entry("data", &*pi.data)?;
+                map.end()
+            }
+            NodeData::Elem(ref elem) => {
+                let mut map = serializer.serialize_map(None)?;
+                map.serialize_entry("tag", &NameRef(&elem.name))?;
+                if !elem.attrs.is_empty() {
+                    map.serialize_entry("attrs", &AttrsMap(&elem.attrs))?;
+                }
+                if self.first_child.is_some() {
+                    map.serialize_entry("children", &Children(*self))?;
+                }
+                if let Some(contents) = self.template_contents() {
+                    let contents = contents.document_node_ref();
+                    map.serialize_entry("content", &contents)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+// A qualified name as "prefix:local" or "local".
+struct NameRef<'a>(&'a QualName);
+
+impl Serialize for NameRef<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        match self.0.prefix {
+            Some(ref p) => {
+                let local = &self.0.local;
+                serializer.collect_str(&format_args!("{}:{}", p, local))
+            }
+            None => serializer.serialize_str(&self.0.local),
+        }
+    }
+}
+
+struct AttrsMap<'a>(&'a [Attribute]);
+
+impl Serialize for AttrsMap<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for attr in self.0 {
+            map.serialize_entry(&NameRef(&attr.name), &*attr.value)?;
+        }
+        map.end()
+    }
+}
+
+struct Children<'a>(NodeRef<'a>);
+
+impl Serialize for Children<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut seq = serializer.serialize_seq(None)?;
+        for child in self.0.children() {
+            seq.serialize_element(&child)?;
+        }
+        seq.end()
+    }
+}
+
+/// Compact nested JSON methods (_serde_ feature).
+impl<'a> NodeRef<'a> {
+    /// Return the compact nested JSON representation of this node and its
+    /// descendants, for inspection.
+    ///
+    /// ```
+    /// use marked::html;
+    ///
+    /// let doc = html::parse_utf8_fragment(b"<p class=x>one<br>two</p>");
+    /// assert_eq!(
+    ///     r#"[{"tag":"p","attrs":{"class":"x"},"#.to_owned() +
+    ///     r#""children":["one",{"tag":"br"},"two"]}]"#,
+    ///     doc.to_json()
+    /// );
+    /// ```
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("NodeRef JSON serialization")
+    }
+}
+
+/// Compact nested JSON methods (_serde_ feature).
+impl Document {
+    /// Return the compact nested JSON representation of the document node
+    /// and all its descendants, as per [`NodeRef::to_json`].
+    pub fn to_json(&self) -> String {
+        self.document_node_ref().to_json()
+    }
+}
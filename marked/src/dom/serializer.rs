@@ -76,7 +76,7 @@ impl<'a> Serialize for NodeRef<'a> {
                 serializer.write_comment(&t)
             }
             (IncludeNode, Pi(ref pi)) => {
-                serializer.write_processing_instruction(&"", &pi.data)
+                serializer.write_processing_instruction(&pi.target, &pi.data)
             }
         }
     }
@@ -0,0 +1,452 @@
+//! A compact, versioned binary snapshot format for `Document`.
+//!
+//! The format consists of a header, with magic bytes and the format
+//! version, followed by the document body:
+//!
+//! * The quirks mode (byte).
+//! * The count of nodes, followed by each node of the `Vec<Node>` arena, in
+//!   order: a kind byte, the 5 parent/sibling/child links (0 for `None`),
+//!   and any kind specific data.
+//! * A flag byte for source spans, and if 1, an optional span for each node.
+//! * The count of HTML template contents, followed by each as its template
+//!   `NodeId` and a nested document body, in `NodeId` order.
+//!
+//! Integers are written as unsigned LEB128 variable length. Text is written
+//! as a length prefix and UTF-8 bytes. Element and attribute name components
+//! (namespace, prefix and local name atoms) are interned: each is written as
+//! an index to a table built while writing, and on first use, is followed by
+//! its text.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::num::NonZeroU32;
+
+use html5ever::Prefix;
+
+use crate::dom::{
+    Attribute, Document, DocumentType, Element, LocalName, Namespace, Node,
+    NodeData, NodeId, ProcessingInstruction, QualName, QuirksMode, SourcePos,
+    SourceSpan, StrTendril,
+};
+
+const MAGIC: &[u8; 4] = b"MKD\x00";
+
+// Node kinds
+const HOLE: u8 = 0;
+const DOCUMENT: u8 = 1;
+const DOC_TYPE: u8 = 2;
+const TEXT: u8 = 3;
+const COMMENT: u8 = 4;
+const ELEM: u8 = 5;
+const PI: u8 = 6;
+
+/// Binary snapshot methods.
+impl Document {
+    /// The current (and highest readable) snapshot format version.
+    pub const SNAPSHOT_VERSION: u16 = 1;
+
+    /// Write a compact binary snapshot of this `Document`, including all
+    /// nodes (referenced or not), source spans and quirks mode, for later
+    /// exact restoration via [`Document::read_snapshot`].
+    pub fn write_snapshot<W>(&self, out: &mut W) -> io::Result<()>
+        where W: Write
+    {
+        out.write_all(MAGIC)?;
+        out.write_all(&Document::SNAPSHOT_VERSION.to_le_bytes())?;
+        let mut w = SnapshotWriter { out, names: HashMap::new() };
+        w.document(self)
+    }
+
+    /// Read a `Document` snapshot as written by
+    /// [`Document::write_snapshot`].
+    ///
+    /// Fails with `io::ErrorKind::InvalidData` if the input isn't a snapshot,
+    /// is of a later, unsupported format version, or is otherwise invalid,
+    /// including inconsistent node links.
+    pub fn read_snapshot<R>(input: &mut R) -> io::Result<Document>
+        where R: Read
+    {
+        let mut header = [0u8; 6];
+        input.read_exact(&mut header)?;
+        if &header[0..4] != MAGIC {
+            return Err(invalid("not a Document snapshot"));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version == 0 || version > Document::SNAPSHOT_VERSION {
+            return Err(invalid(format!(
+                "unsupported snapshot version {}", version)));
+        }
+        let mut r = SnapshotReader { input, names: Vec::new(), depth: 0 };
+        r.document()
+    }
+}
+
+fn invalid<E>(error: E) -> io::Error
+    where E: Into<Box<dyn std::error::Error + Send + Sync>>
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+struct SnapshotWriter<'a, 'd, W> {
+    out: &'a mut W,
+    names: HashMap<&'d str, u32>,
+}
+
+impl<'a, 'd, W> SnapshotWriter<'a, 'd, W>
+    where W: Write
+{
+    fn document(&mut self, doc: &'d Document) -> io::Result<()> {
+        self.byte(match doc.quirks_mode {
+            QuirksMode::NoQuirks => 0,
+            QuirksMode::LimitedQuirks => 1,
+            QuirksMode::Quirks => 2,
+        })?;
+        // Skip the padding node at index 0
+        let nodes = &doc.nodes[1..];
+        self.uint(nodes.len() as u64)?;
+        for node in nodes {
+            self.node(node)?;
+        }
+        if doc.spans.is_empty() {
+            self.byte(0)?;
+        } else {
+            self.byte(1)?;
+            for i in 1..doc.nodes.len() {
+                match doc.spans.get(i).and_then(|s| *s) {
+                    Some(span) => {
+                        self.byte(1)?;
+                        self.pos(&span.start)?;
+                        self.pos(&span.end)?;
+                    }
+                    None => self.byte(0)?,
+                }
+            }
+        }
+        self.uint(doc.templates.len() as u64)?;
+        for (id, contents) in &doc.templates {
+            self.uint(id.0.get().into())?;
+            self.document(contents)?;
+        }
+        Ok(())
+    }
+
+    fn node(&mut self, node: &'d Node) -> io::Result<()> {
+        let kind = match node.data {
+            NodeData::Hole => HOLE,
+            NodeData::Document => DOCUMENT,
+            NodeData::DocType(_) => DOC_TYPE,
+            NodeData::Text(_) => TEXT,
+            NodeData::Comment(_) => COMMENT,
+            NodeData::Elem(_) => ELEM,
+            NodeData::Pi(_) => PI,
+        };
+        self.byte(kind)?;
+        for &link in &[
+            node.parent,
+            node.prev_sibling,
+            node.next_sibling,
+            node.first_child,
+            node.last_child,
+        ] {
+            self.uint(link.map_or(0, |id| id.0.get().into()))?;
+        }
+        match node.data {
+            NodeData::Hole | NodeData::Document => Ok(()),
+            NodeData::DocType(ref dt) => {
+                self.text(&dt.name)?;
+                self.text(&dt.public_id)?;
+                self.text(&dt.system_id)
+            }
+            NodeData::Text(ref t) | NodeData::Comment(ref t) => self.text(t),
+            NodeData::Pi(ref pi) => {
+                self.text(&pi.target)?;
+                self.text(&pi.data)
+            }
+            NodeData::Elem(ref elem) => self.element(elem),
+        }
+    }
+
+    fn element(&mut self, elem: &'d Element) -> io::Result<()> {
+        self.qual_name(&elem.name)?;
+        self.uint(elem.attrs.len() as u64)?;
+        for attr in &elem.attrs {
+            self.qual_name(&attr.name)?;
+            self.text(&attr.value)?;
+        }
+        Ok(())
+    }
+
+    fn qual_name(&mut self, name: &'d QualName) -> io::Result<()> {
+        match name.prefix {
+            Some(ref p) => {
+                self.byte(1)?;
+                self.name(p)?;
+            }
+            None => self.byte(0)?,
+        }
+        self.name(&name.ns)?;
+        self.name(&name.local)
+    }
+
+    // Write an interned name index, and on first use, its text.
+    fn name(&mut self, name: &'d str) -> io::Result<()> {
+        let next = self.names.len() as u32;
+        let index = *self.names.entry(name).or_insert(next);
+        self.uint(index.into())?;
+        if index == next {
+            self.text(name)?;
+        }
+        Ok(())
+    }
+
+    fn pos(&mut self, pos: &SourcePos) -> io::Result<()> {
+        self.uint(pos.line.into())?;
+        self.uint(pos.column.map_or(0, |c| u64::from(c) + 1))?;
+        self.uint(pos.offset.map_or(0, |o| o as u64 + 1))
+    }
+
+    fn text(&mut self, text: &str) -> io::Result<()> {
+        self.uint(text.len() as u64)?;
+        self.out.write_all(text.as_bytes())
+    }
+
+    fn byte(&mut self, b: u8) -> io::Result<()> {
+        self.out.write_all(&[b])
+    }
+
+    // Write unsigned LEB128.
+    fn uint(&mut self, mut v: u64) -> io::Result<()> {
+        let mut buf = [0u8; 10];
+        let mut i = 0;
+        loop {
+            let b = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                buf[i] = b;
+                i += 1;
+                break;
+            }
+            buf[i] = b | 0x80;
+            i += 1;
+        }
+        self.out.write_all(&buf[..i])
+    }
+}
+
+struct SnapshotReader<'a, R> {
+    input: &'a mut R,
+    names: Vec<String>,
+    depth: u32,
+}
+
+// Bound on initial allocations, independent of untrusted counts.
+const MAX_RESERVE: usize = 0x1_0000;
+
+// Maximum nesting of template contents documents.
+const MAX_DEPTH: u32 = 256;
+
+impl<'a, R> SnapshotReader<'a, R>
+    where R: Read
+{
+    fn document(&mut self) -> io::Result<Document> {
+        let quirks_mode = match self.byte()? {
+            0 => QuirksMode::NoQuirks,
+            1 => QuirksMode::LimitedQuirks,
+            2 => QuirksMode::Quirks,
+            b => return Err(invalid(format!("invalid quirks mode {}", b))),
+        };
+        let count = self.len()?;
+        if count >= std::u32::MAX as usize {
+            return Err(invalid("node count overflow"));
+        }
+        let mut nodes = Vec::with_capacity(count.min(MAX_RESERVE) + 1);
+        nodes.push(Node::new(NodeData::Hole)); // Index 0: Padding
+        for _ in 0..count {
+            nodes.push(self.node()?);
+        }
+
+        let mut spans = Vec::new();
+        match self.byte()? {
+            0 => {}
+            1 => {
+                spans.reserve(nodes.len().min(MAX_RESERVE));
+                spans.push(None);
+                for _ in 0..count {
+                    spans.push(match self.byte()? {
+                        0 => None,
+                        1 => {
+                            let start = self.pos()?;
+                            let end = self.pos()?;
+                            Some(SourceSpan { start, end })
+                        }
+                        b => {
+                            return Err(invalid(
+                                format!("invalid span flag {}", b)));
+                        }
+                    });
+                }
+            }
+            b => return Err(invalid(format!("invalid spans flag {}", b))),
+        }
+
+        let count = self.len()?;
+        let mut templates = BTreeMap::new();
+        for _ in 0..count {
+            let id = self.node_id()?
+                .ok_or_else(|| invalid("invalid template NodeId 0"))?;
+            if self.depth == MAX_DEPTH {
+                return Err(invalid("template contents nested too deep"));
+            }
+            self.depth += 1;
+            let contents = self.document()?;
+            self.depth -= 1;
+            templates.insert(id, contents);
+        }
+
+        let doc = Document { nodes, spans, templates, quirks_mode };
+        doc.check_links().map_err(invalid)?;
+        Ok(doc)
+    }
+
+    fn node(&mut self) -> io::Result<Node> {
+        let kind = self.byte()?;
+        let mut links = [None; 5];
+        for link in links.iter_mut() {
+            *link = self.node_id()?;
+        }
+        let data = match kind {
+            HOLE => NodeData::Hole,
+            DOCUMENT => NodeData::Document,
+            DOC_TYPE => NodeData::DocType(DocumentType {
+                name: self.text()?,
+                public_id: self.text()?,
+                system_id: self.text()?,
+                _priv: ()
+            }),
+            TEXT => NodeData::Text(self.text()?),
+            COMMENT => NodeData::Comment(self.text()?),
+            PI => NodeData::Pi(ProcessingInstruction {
+                target: self.text()?,
+                data: self.text()?,
+                _priv: ()
+            }),
+            ELEM => NodeData::Elem(self.element()?),
+            k => return Err(invalid(format!("invalid node kind {}", k))),
+        };
+        Ok(Node {
+            data,
+            parent: links[0],
+            prev_sibling: links[1],
+            next_sibling: links[2],
+            first_child: links[3],
+            last_child: links[4],
+        })
+    }
+
+    fn element(&mut self) -> io::Result<Element> {
+        let name = self.qual_name()?;
+        let count = self.len()?;
+        let mut attrs = Vec::with_capacity(count.min(MAX_RESERVE));
+        for _ in 0..count {
+            let name = self.qual_name()?;
+            let value = self.text()?;
+            attrs.push(Attribute { name, value });
+        }
+        Ok(Element { name, attrs, _priv: () })
+    }
+
+    fn qual_name(&mut self) -> io::Result<QualName> {
+        let prefix = match self.byte()? {
+            0 => None,
+            1 => Some(Prefix::from(self.name()?)),
+            b => return Err(invalid(format!("invalid prefix flag {}", b))),
+        };
+        let ns = Namespace::from(self.name()?);
+        let local = LocalName::from(self.name()?);
+        Ok(QualName { prefix, ns, local })
+    }
+
+    // Read an interned name index, and on first use, its text.
+    fn name(&mut self) -> io::Result<&str> {
+        let index = self.len()?;
+        if index == self.names.len() {
+            let name = self.string()?;
+            self.names.push(name);
+        }
+        self.names.get(index)
+            .map(|n| n.as_str())
+            .ok_or_else(|| invalid(format!("invalid name index {}", index)))
+    }
+
+    fn node_id(&mut self) -> io::Result<Option<NodeId>> {
+        let id = self.uint()?;
+        if id == 0 {
+            Ok(None)
+        } else {
+            let id: u32 = id.try_into()
+                .map_err(|_| invalid("NodeId overflow"))?;
+            Ok(NonZeroU32::new(id).map(NodeId))
+        }
+    }
+
+    fn pos(&mut self) -> io::Result<SourcePos> {
+        let line = self.uint()?.try_into()
+            .map_err(|_| invalid("line overflow"))?;
+        let column = match self.uint()? {
+            0 => None,
+            c => Some((c - 1).try_into()
+                .map_err(|_| invalid("column overflow"))?),
+        };
+        let offset = match self.uint()? {
+            0 => None,
+            o => Some((o - 1).try_into()
+                .map_err(|_| invalid("offset overflow"))?),
+        };
+        Ok(SourcePos { line, column, offset })
+    }
+
+    fn text(&mut self) -> io::Result<StrTendril> {
+        self.string().map(StrTendril::from)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.len()?;
+        let mut buf = Vec::with_capacity(len.min(MAX_RESERVE));
+        (&mut self.input).take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        String::from_utf8(buf).map_err(invalid)
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        self.uint()?.try_into().map_err(|_| invalid("length overflow"))
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut b = [0u8];
+        self.input.read_exact(&mut b)?;
+        Ok(b[0])
+    }
+
+    // Read unsigned LEB128.
+    fn uint(&mut self) -> io::Result<u64> {
+        let mut v = 0u64;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            if shift == 63 && b > 1 {
+                return Err(invalid("integer overflow"));
+            }
+            v |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+            shift += 7;
+            if shift > 63 {
+                return Err(invalid("integer overflow"));
+            }
+        }
+    }
+}
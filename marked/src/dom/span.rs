@@ -0,0 +1,129 @@
+use crate::dom::{Document, NodeId, NodeRef};
+
+/// A position in the source text of a parsed `Document`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourcePos {
+    /// The 1-based line number.
+    pub line: u32,
+
+    /// The 1-based column number, in chars, if known.
+    pub column: Option<u32>,
+
+    /// The byte offset from the start of the (UTF-8) source, if known.
+    pub offset: Option<usize>,
+}
+
+/// The start and end positions of a `Node` in the source text of a parsed
+/// `Document`.
+///
+/// For elements, the span starts with the start tag and ends after any end
+/// tag. The HTML parser (_html5ever_) only reports line numbers, so for HTML
+/// the `column` and `offset` are `None`, and the lines are those of the
+/// parser's progress when the node was created and completed, which may be
+/// later than the markup itself, e.g. with a multi-line start tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceSpan {
+    /// The position at the start of the node.
+    pub start: SourcePos,
+
+    /// The position at the end of the node.
+    pub end: SourcePos,
+}
+
+impl SourcePos {
+    pub(crate) fn at_line(line: u64) -> SourcePos {
+        SourcePos { line: line as u32, column: None, offset: None }
+    }
+}
+
+impl SourceSpan {
+    pub(crate) fn at(pos: SourcePos) -> SourceSpan {
+        SourceSpan { start: pos, end: pos }
+    }
+}
+
+/// Source position methods.
+impl Document {
+    /// Return the source span recorded for the specified node, if any.
+    ///
+    /// Spans are only recorded when requested of the parser, e.g. via
+    /// `HtmlParseOptions::spans`, and are retained by
+    /// [`Document::compact`], [`Document::deep_clone`] and
+    /// [`Document::detach`]. Nodes otherwise added have no span.
+    pub fn source_span(&self, id: NodeId) -> Option<SourceSpan> {
+        self.spans.get(id.0.get() as usize).cloned().unwrap_or(None)
+    }
+
+    pub(crate) fn set_source_span(&mut self, id: NodeId, span: SourceSpan) {
+        let i = id.0.get() as usize;
+        if self.spans.len() <= i {
+            self.spans.resize(i + 1, None);
+        }
+        self.spans[i] = Some(span);
+    }
+
+    pub(crate) fn set_source_end(&mut self, id: NodeId, end: SourcePos) {
+        if let Some(Some(span)) = self.spans.get_mut(id.0.get() as usize) {
+            span.end = end;
+        }
+    }
+
+    // Copy any span of oid in odoc to id in self.
+    pub(crate) fn copy_source_span(
+        &mut self,
+        id: NodeId,
+        odoc: &Document,
+        oid: NodeId)
+    {
+        if let Some(span) = odoc.source_span(oid) {
+            self.set_source_span(id, span);
+        }
+    }
+}
+
+/// Source position methods.
+impl<'a> NodeRef<'a> {
+    /// Return the source span recorded for this node, if any.
+    ///
+    /// See [`Document::source_span`].
+    pub fn source_span(&self) -> Option<SourceSpan> {
+        self.doc.source_span(self.id)
+    }
+}
+
+// Index of line start byte offsets, for converting a 0-based line and char
+// column to a `SourcePos` with byte offset.
+#[cfg(feature = "xml")]
+pub(crate) struct LineIndex<'s> {
+    source: &'s str,
+    starts: Vec<usize>,
+}
+
+#[cfg(feature = "xml")]
+impl<'s> LineIndex<'s> {
+    pub(crate) fn new(source: &'s str) -> LineIndex<'s> {
+        let mut starts = vec![0];
+        starts.extend(
+            source.bytes()
+                .enumerate()
+                .filter(|&(_, b)| b == b'\n')
+                .map(|(i, _)| i + 1)
+        );
+        LineIndex { source, starts }
+    }
+
+    pub(crate) fn pos(&self, row: u64, column: u64) -> SourcePos {
+        let row = row as usize;
+        let offset = self.starts.get(row).map(|&start| {
+            let line = &self.source[start..];
+            start + line.char_indices()
+                .nth(column as usize)
+                .map_or(line.len(), |(i, _)| i)
+        });
+        SourcePos {
+            line: row as u32 + 1,
+            column: Some(column as u32 + 1),
+            offset,
+        }
+    }
+}
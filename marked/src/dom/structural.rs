@@ -0,0 +1,343 @@
+use std::borrow::Cow;
+
+use crate::dom::{Document, NodeData, NodeId, NodeRef};
+
+/// Options for [`NodeRef::structurally_eq_with`] and
+/// [`NodeRef::content_hash_with`].
+///
+/// Regardless of options, comparison is independent of `NodeId`s (arena
+/// layout), `Hole` nodes are ignored, and adjacent text nodes are compared
+/// as if concatenated. Element and attribute names are compared by
+/// namespace and local name, without any prefix.
+#[derive(Clone, Debug, Default)]
+pub struct CompareOptions {
+    /// If true, compare the attributes of elements as unordered sets.
+    /// Default: false
+    pub ignore_attr_order: bool,
+
+    /// If true, ignore comment nodes. Default: false
+    pub ignore_comments: bool,
+
+    /// If true, ignore text that is empty or only white-space (after
+    /// concatenating adjacent text nodes). Default: false
+    pub ignore_whitespace_text: bool,
+}
+
+/// Structural equality and content hash methods.
+impl<'a> NodeRef<'a> {
+    /// Return true if this node and its descendants are structurally equal to
+    /// the other node and its descendants, in the same or another `Document`.
+    ///
+    /// This is equivalent to `structurally_eq_with` and default
+    /// `CompareOptions`. Note that `NodeRef`'s `PartialEq` is instead by
+    /// identity.
+    ///
+    /// ```
+    /// use marked::{html, CompareOptions};
+    ///
+    /// let a = html::parse_utf8_fragment(b"<p id=1 class=x>one<!--c--></p>");
+    /// let b = html::parse_utf8_fragment(b"<p class=x id=1>one</p>");
+    /// let (a, b) = (a.document_node_ref(), b.document_node_ref());
+    /// assert!(!a.structurally_eq(&b));
+    ///
+    /// let opts = CompareOptions {
+    ///     ignore_attr_order: true,
+    ///     ignore_comments: true,
+    ///     ..CompareOptions::default()
+    /// };
+    /// assert!(a.structurally_eq_with(&b, &opts));
+    /// assert_eq!(a.content_hash_with(&opts), b.content_hash_with(&opts));
+    /// ```
+    pub fn structurally_eq(&self, other: &NodeRef<'_>) -> bool {
+        self.structurally_eq_with(other, &CompareOptions::default())
+    }
+
+    /// Return true if this node and its descendants are structurally equal to
+    /// the other node and its descendants, per the given options.
+    ///
+    /// This compares node kinds, element names and attributes, text and other
+    /// node content, and the order of children. Any template contents are
+    /// also compared.
+    pub fn structurally_eq_with(
+        &self,
+        other: &NodeRef<'_>,
+        opts: &CompareOptions)
+        -> bool
+    {
+        let mut a = Tokens::new(*self, opts);
+        let mut b = Tokens::new(*other, opts);
+        loop {
+            match (a.next(), b.next()) {
+                (None, None) => return true,
+                (Some(ta), Some(tb)) => {
+                    if ta != tb {
+                        return false;
+                    }
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    /// Return a stable 64-bit hash of the content of this node and its
+    /// descendants.
+    ///
+    /// This is equivalent to `content_hash_with` and default
+    /// `CompareOptions`.
+    pub fn content_hash(&self) -> u64 {
+        self.content_hash_with(&CompareOptions::default())
+    }
+
+    /// Return a stable 64-bit hash of the content of this node and its
+    /// descendants, per the given options.
+    ///
+    /// Nodes which are [structurally equal][NodeRef::structurally_eq_with],
+    /// with the same options, have the same hash. The hash is computed
+    /// (via FNV-1a) over a canonical form of the content, such that it is
+    /// the same across processes and platforms, and may be persisted, for
+    /// example to detect duplicate documents.
+    pub fn content_hash_with(&self, opts: &CompareOptions) -> u64 {
+        let mut hasher = Fnv64::new();
+        for token in Tokens::new(*self, opts) {
+            token.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+}
+
+// A canonical content token, in tree order.
+#[derive(PartialEq)]
+enum Token<'a> {
+    Document,
+    // Name, public and system identifiers
+    DocType(&'a str, &'a str, &'a str),
+    Text(Cow<'a, str>),
+    Comment(&'a str),
+    // Element namespace, local name, and attributes as (namespace, local
+    // name, value)
+    Start(&'a str, &'a str, Vec<(&'a str, &'a str, &'a str)>),
+    Pi(&'a str, &'a str),
+    // Start of an element's template contents, after its children
+    Contents,
+    // End of the children of the last Document, Start or Contents
+    End,
+}
+
+impl Token<'_> {
+    fn hash(&self, h: &mut Fnv64) {
+        match self {
+            Token::Document => h.write_u8(1),
+            Token::DocType(name, public_id, system_id) => {
+                h.write_u8(2);
+                h.write_str(name);
+                h.write_str(public_id);
+                h.write_str(system_id);
+            }
+            Token::Text(t) => {
+                h.write_u8(3);
+                h.write_str(t);
+            }
+            Token::Comment(t) => {
+                h.write_u8(4);
+                h.write_str(t);
+            }
+            Token::Start(ns, local, attrs) => {
+                h.write_u8(5);
+                h.write_str(ns);
+                h.write_str(local);
+                h.write_len(attrs.len());
+                for (ns, local, value) in attrs {
+                    h.write_str(ns);
+                    h.write_str(local);
+                    h.write_str(value);
+                }
+            }
+            Token::Pi(target, data) => {
+                h.write_u8(6);
+                h.write_str(target);
+                h.write_str(data);
+            }
+            Token::Contents => h.write_u8(7),
+            Token::End => h.write_u8(8),
+        }
+    }
+}
+
+// A sibling list being visited.
+struct Frame<'a> {
+    doc: &'a Document,
+    next: Option<NodeId>,
+    // Template contents to visit after the siblings
+    contents: Option<&'a Document>,
+    // True for the initial node, without siblings or End
+    root: bool,
+}
+
+// Iterator over the canonical tokens of a sub-tree, using an explicit stack
+// for arbitrarily deep trees.
+struct Tokens<'a, 'o> {
+    opts: &'o CompareOptions,
+    stack: Vec<Frame<'a>>,
+}
+
+impl<'a, 'o> Tokens<'a, 'o> {
+    fn new(node: NodeRef<'a>, opts: &'o CompareOptions) -> Self {
+        let frame = Frame {
+            doc: node.doc,
+            next: Some(node.id),
+            contents: None,
+            root: true,
+        };
+        Tokens { opts, stack: vec![frame] }
+    }
+
+    // Push a frame for the children of the given node.
+    fn push_children(
+        &mut self,
+        doc: &'a Document,
+        id: NodeId,
+        contents: Option<&'a Document>)
+    {
+        let next = doc[id].first_child;
+        self.stack.push(Frame { doc, next, contents, root: false });
+    }
+
+    // Concatenate text from adjacent text nodes, starting with the current
+    // text node of the top frame, and advancing it. Holes and any ignored
+    // comments in between are skipped.
+    fn take_text(&mut self) -> Cow<'a, str> {
+        let opts = self.opts;
+        let frame = self.stack.last_mut().unwrap();
+        let doc = frame.doc;
+        let mut text: Option<Cow<'a, str>> = None;
+        while let Some(id) = frame.next {
+            match doc[id].data {
+                NodeData::Text(ref t) => {
+                    match text {
+                        Some(ref mut s) => s.to_mut().push_str(t),
+                        None => text = Some(Cow::Borrowed(t)),
+                    }
+                }
+                NodeData::Hole => {}
+                NodeData::Comment(_) if opts.ignore_comments => {}
+                _ => break,
+            }
+            frame.next = if frame.root { None } else { doc[id].next_sibling };
+        }
+        text.unwrap_or(Cow::Borrowed(""))
+    }
+}
+
+impl<'a, 'o> Iterator for Tokens<'a, 'o> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        loop {
+            let frame = self.stack.last_mut()?;
+            let doc = frame.doc;
+            let id = match frame.next {
+                Some(id) => id,
+                None => {
+                    if let Some(contents) = frame.contents.take() {
+                        *frame = Frame {
+                            doc: contents,
+                            next: contents[Document::DOCUMENT_NODE_ID]
+                                .first_child,
+                            contents: None,
+                            root: false,
+                        };
+                        return Some(Token::Contents);
+                    }
+                    let root = frame.root;
+                    self.stack.pop();
+                    if root {
+                        return None;
+                    }
+                    return Some(Token::End);
+                }
+            };
+
+            if let NodeData::Text(_) = doc[id].data {
+                let text = self.take_text();
+                if self.opts.ignore_whitespace_text &&
+                    text.trim().is_empty()
+                {
+                    continue;
+                }
+                return Some(Token::Text(text));
+            }
+
+            frame.next = if frame.root { None } else { doc[id].next_sibling };
+            match doc[id].data {
+                NodeData::Hole | NodeData::Text(_) => {}
+                NodeData::Document => {
+                    self.push_children(doc, id, None);
+                    return Some(Token::Document);
+                }
+                NodeData::DocType(ref dt) => {
+                    return Some(Token::DocType(
+                        &dt.name,
+                        &dt.public_id,
+                        &dt.system_id
+                    ));
+                }
+                NodeData::Comment(ref t) => {
+                    if !self.opts.ignore_comments {
+                        return Some(Token::Comment(t));
+                    }
+                }
+                NodeData::Elem(ref elm) => {
+                    let mut attrs = elm.attrs.iter()
+                        .map(|a| (&*a.name.ns, &*a.name.local, &*a.value))
+                        .collect::<Vec<_>>();
+                    if self.opts.ignore_attr_order {
+                        attrs.sort();
+                    }
+                    self.push_children(doc, id, doc.template_contents(id));
+                    return Some(Token::Start(
+                        &elm.name.ns,
+                        &elm.name.local,
+                        attrs
+                    ));
+                }
+                NodeData::Pi(ref pi) => {
+                    return Some(Token::Pi(&pi.target, &pi.data));
+                }
+            }
+        }
+    }
+}
+
+// FNV-1a 64-bit, which unlike `DefaultHasher` is specified and stable.
+struct Fnv64(u64);
+
+impl Fnv64 {
+    fn new() -> Self {
+        Fnv64(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u8(&mut self, b: u8) {
+        self.write(&[b]);
+    }
+
+    fn write_len(&mut self, len: usize) {
+        self.write(&(len as u64).to_le_bytes());
+    }
+
+    // Length prefixed, such that adjacent strings are unambiguous.
+    fn write_str(&mut self, s: &str) {
+        self.write_len(s.len());
+        self.write(s.as_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,74 @@
+use crate::dom::{Document, NodeId, NodeRef};
+
+/// HTML template contents methods.
+impl Document {
+    /// Return the contents of the specified HTML `<template>` element, if
+    /// any, as a separate fragment `Document`.
+    ///
+    /// The contents are inert, and aren't children of the element, so they
+    /// are not included in traversal, filtering or serialization of the
+    /// containing `Document`. Use the `Display` implementation of the
+    /// returned `Document` to serialize the contents. Contents are retained
+    /// by [`Document::compact`], [`Document::deep_clone`] and
+    /// [`Document::detach`].
+    pub fn template_contents(&self, id: NodeId) -> Option<&Document> {
+        self.templates.get(&id)
+    }
+
+    /// Return a mutable reference to any template contents, as per
+    /// [`Document::template_contents`].
+    pub fn template_contents_mut(&mut self, id: NodeId)
+        -> Option<&mut Document>
+    {
+        self.templates.get_mut(&id)
+    }
+
+    /// Set (or with `None`, remove) the template contents of the specified
+    /// node, returning any prior contents.
+    pub fn set_template_contents(
+        &mut self,
+        id: NodeId,
+        contents: Option<Document>)
+        -> Option<Document>
+    {
+        match contents {
+            Some(contents) => self.templates.insert(id, contents),
+            None => self.templates.remove(&id),
+        }
+    }
+
+    // Move any template contents of oid in odoc to id in self.
+    pub(crate) fn move_template_contents(
+        &mut self,
+        id: NodeId,
+        odoc: &mut Document,
+        oid: NodeId)
+    {
+        if let Some(contents) = odoc.templates.remove(&oid) {
+            self.templates.insert(id, contents);
+        }
+    }
+
+    // Clone any template contents of oid in odoc to id in self.
+    pub(crate) fn clone_template_contents(
+        &mut self,
+        id: NodeId,
+        odoc: &Document,
+        oid: NodeId)
+    {
+        if let Some(contents) = odoc.templates.get(&oid) {
+            let contents = contents.deep_clone(Document::DOCUMENT_NODE_ID);
+            self.templates.insert(id, contents);
+        }
+    }
+}
+
+/// HTML template contents methods.
+impl<'a> NodeRef<'a> {
+    /// Return the template contents of this node, if any.
+    ///
+    /// See [`Document::template_contents`].
+    pub fn template_contents(&self) -> Option<&'a Document> {
+        self.doc.template_contents(self.id)
+    }
+}
@@ -2,16 +2,21 @@ use std::fs::File;
 use std::{io, io::Read};
 
 use crate::{
-    Attribute, Document, Element, Node, NodeData, NodeId, NodeRef,
-    QualName, StrTendril,
+    Attribute, CompareOptions, Document, Element, Node, NodeData, NodeId,
+    NodeRef, PrettyOptions, XmlOptions,
+    Limit, LimitError, ParseLimits, QualName, QuirksMode, StrTendril,
+    diff, diff::Edit,
     filter, filter::Action,
-    html, html::{a, t, TAG_META},
-    HTTP_CTYPE_CONF,
+    html, html::{a, ns, t, TAG_META},
+    HintSource, HTTP_CTYPE_CONF, HTML_META_CONF,
 };
 
 #[cfg(feature = "xml")]
 use crate::xml;
 
+#[cfg(feature = "detect")]
+use crate::DETECT_CONF;
+
 use crate::chain_filters;
 use crate::logger::ensure_logger;
 use crate::decode::EncodingHint;
@@ -298,6 +303,43 @@ fn test_fold_filter_breadth() {
     );
 }
 
+#[test]
+fn test_compact_with_map() {
+    ensure_logger();
+    let mut doc = html::parse_utf8_fragment(
+        b"<div><p>one</p><p>two</p><p>three</p></div>"
+    );
+    let rid = doc.root_element().unwrap();
+    let ps = doc.children(rid).collect::<Vec<_>>();
+    let texts = ps.iter()
+        .map(|&p| doc.children(p).next().unwrap())
+        .collect::<Vec<_>>();
+    let _det = doc.detach(ps[0]);
+    doc.unlink(texts[1]);
+    doc.append_child(ps[1], Node::new_text("2"));
+
+    let map = doc.compact_with_map();
+    assert_eq!("<div><p>2</p><p>three</p></div>", doc.to_string());
+    assert_eq!(
+        Some(Document::DOCUMENT_NODE_ID),
+        map.get(Document::DOCUMENT_NODE_ID)
+    );
+    assert_eq!(None, map.get(ps[0]));
+    assert_eq!(None, map.get(texts[0]));
+    assert_eq!(None, map.get(texts[1]));
+
+    let nid = map.get(texts[2]).unwrap();
+    assert_eq!("three", doc[nid].as_text().unwrap().as_ref());
+    let nid = map.get(ps[1]).unwrap();
+    assert_eq!("2", doc.text(nid).unwrap().as_ref());
+    assert_eq!(Some(doc.root_element().unwrap()), map.get(rid));
+
+    assert_eq!(doc.len() as usize, map.iter().count());
+    for (old, new) in map.iter() {
+        assert_eq!(Some(new), map.get(old));
+    }
+}
+
 #[test]
 fn test_remove_filter() {
     ensure_logger();
@@ -370,7 +412,7 @@ fn test_filter_chain_large_sample() {
     let eh = EncodingHint::shared_default(enc::UTF_8);
     let mut reader = sample_file("github-dekellum.html");
     let mut doc = html::parse_buffered(eh, &mut reader).unwrap();
-    assert_eq!(5500, doc.len());
+    assert_eq!(5483, doc.len());
     let pass_0 = chain_filters!(
         filter::detach_banned_elements,
         filter::detach_comments,
@@ -404,7 +446,7 @@ fn test_filter_chain_large_sample_breadth() {
     let eh = EncodingHint::shared_default(enc::UTF_8);
     let mut reader = sample_file("github-dekellum.html");
     let mut doc = html::parse_buffered(eh, &mut reader).unwrap();
-    assert_eq!(5500, doc.len());
+    assert_eq!(5483, doc.len());
     let pass_0 = chain_filters!(
         filter::detach_banned_elements,
         filter::detach_comments,
@@ -460,6 +502,202 @@ r####"
     );
 }
 
+#[test]
+#[cfg(feature = "xml")]
+fn test_serialize_xml_ns() {
+    ensure_logger();
+    let doc = xml::parse_utf8(
+        r#"<?xml version="1.0"?>
+           <doc xmlns="urn:d" xmlns:x="urn:x">
+             <x:item x:k="1" k="a&lt;&quot;b">c &amp; d</x:item>
+             <?target some data?>
+             <plain xmlns=""><y:e xmlns:y="urn:x"/></plain>
+           </doc>"#.as_bytes()
+    ).expect("parsed");
+    let mut out = Vec::new();
+    doc.serialize_xml(&mut out, &XmlOptions::default()).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <doc xmlns=\"urn:d\">\
+         <x:item xmlns:x=\"urn:x\" x:k=\"1\" k=\"a&lt;&quot;b\">\
+         c &amp; d</x:item>\
+         <?target some data?>\
+         <plain xmlns=\"\"><y:e xmlns:y=\"urn:x\"/></plain>\
+         </doc>",
+        out
+    );
+
+    // Round trip
+    let doc2 = xml::parse_utf8(out.as_bytes()).expect("parsed");
+    let mut out2 = Vec::new();
+    doc2.serialize_xml(&mut out2, &XmlOptions::default()).unwrap();
+    assert_eq!(out, String::from_utf8(out2).unwrap());
+}
+
+#[test]
+fn test_serialize_xhtml() {
+    ensure_logger();
+    let doc = html::parse_utf8(
+        "<!DOCTYPE html><html xmlns=\"http://www.w3.org/1999/xhtml\">\
+         <body><p a=\"1\" b=\"x\ty\">x<br>y <i></i></p><!-- a -- b -->\
+         <svg><a xlink:href=\"#f\"/></svg>\
+         <script>if (a < b && c) {}</script></body></html>".as_bytes()
+    );
+    let opts = XmlOptions { xhtml: true, xml_declaration: false };
+    let mut out = Vec::new();
+    doc.serialize_xml(&mut out, &opts).unwrap();
+    assert_eq!(
+        "<!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\"><head></head>\
+         <body><p a=\"1\" b=\"x&#9;y\">x<br />y <i></i></p><!-- a - - b -->\
+         <svg xmlns=\"http://www.w3.org/2000/svg\">\
+         <a xmlns:xlink=\"http://www.w3.org/1999/xlink\" xlink:href=\"#f\"/>\
+         </svg>\
+         <script>if (a &lt; b &amp;&amp; c) {}</script></body></html>",
+        String::from_utf8(out).unwrap()
+    );
+
+    let p = doc.document_node_ref().find(|n| n.is_elem(t::P)).unwrap();
+    let mut out = Vec::new();
+    p.serialize_xml(&mut out, &XmlOptions::default()).unwrap();
+    assert_eq!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <p xmlns=\"http://www.w3.org/1999/xhtml\" a=\"1\" b=\"x&#9;y\">\
+         x<br/>y <i/></p>",
+        String::from_utf8(out).unwrap()
+    );
+}
+
+#[test]
+#[cfg(feature = "xml")]
+fn test_serialize_xml_well_formed() {
+    ensure_logger();
+    let doc = html::parse_utf8(
+        "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\" \
+         \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd\">\
+         <p a<b=1 c=2><!--a---b-->x<o:p>y</o:p><!--c-<!---->".as_bytes()
+    );
+    let opts = XmlOptions { xhtml: true, xml_declaration: false };
+    let mut out = Vec::new();
+    doc.serialize_xml(&mut out, &opts).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(
+        "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\" \
+         \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd\">\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\"><head></head><body>\
+         <p c=\"2\"><!--a- - -b-->x<o_p>y</o_p><!--c-<!- - -->\
+         </p></body></html>",
+        out
+    );
+    xml::parse_utf8(out.as_bytes()).expect("well-formed");
+
+    // An attribute prefix already bound by an ancestor, to another namespace
+    let mut doc = xml::parse_utf8(
+        r#"<x:a xmlns:x="urn:a"><b/></x:a>"#.as_bytes()
+    ).expect("parsed");
+    let b = doc.nodes().find(|&id| {
+        doc[id].as_element().map_or(false, |e| &*e.name.local == "b")
+    }).unwrap();
+    doc[b].as_element_mut().unwrap().attrs.push(Attribute {
+        name: QualName::new(Some("x".into()), "urn:b".into(), "k".into()),
+        value: "v".into(),
+    });
+    let mut out = Vec::new();
+    doc.serialize_xml(&mut out, &opts).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(
+        "<x:a xmlns:x=\"urn:a\"><b xmlns:ns1=\"urn:b\" ns1:k=\"v\"/></x:a>",
+        out
+    );
+    xml::parse_utf8(out.as_bytes()).expect("well-formed");
+
+    // Reserved prefixes, with other namespaces
+    let qname = |prefix: &str, ns: &str, local: &str| {
+        QualName::new(Some(prefix.into()), ns.into(), local.into())
+    };
+    let mut doc = Document::new();
+    let mut elm = Element::new("a");
+    elm.name = qname("xml", "urn:a", "a");
+    elm.attrs.push(Attribute {
+        name: qname("xmlns", "urn:b", "k"),
+        value: "1".into(),
+    });
+    elm.attrs.push(Attribute {
+        name: qname("x", "http://www.w3.org/XML/1998/namespace", "lang"),
+        value: "en".into(),
+    });
+    elm.attrs.push(Attribute {
+        name: QualName::new(
+            None,
+            "http://www.w3.org/2000/xmlns/".into(),
+            "y".into()),
+        value: "http://www.w3.org/XML/1998/namespace".into(),
+    });
+    doc.append_child(Document::DOCUMENT_NODE_ID, Node::new_elem(elm));
+    let mut out = Vec::new();
+    doc.serialize_xml(&mut out, &opts).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(
+        "<ns1:a xmlns:ns1=\"urn:a\" xmlns:ns2=\"urn:b\" \
+         ns2:k=\"1\" xml:lang=\"en\"/>",
+        out
+    );
+    xml::parse_utf8(out.as_bytes()).expect("well-formed");
+}
+
+#[test]
+#[cfg(feature = "xml")]
+fn test_xml_lenient() {
+    ensure_logger();
+    let (doc, errors) = xml::parse_utf8_lenient(
+        "<?xml version=\"1.0\"?>\n\
+         <rss version=2.0>\n\
+         <channel><title>Fish & Chips &nbsp;&bogus; &lt;3</title>\n\
+         <item><link>http://x/?a=1&amp;b=2</link><p>one<br></item>\n\
+         <dc:creator>Me</dc:creator>\n\
+         </channel>".as_bytes()
+    );
+    for e in &errors {
+        debug!("recovered: {}", e);
+    }
+    assert_eq!(
+        "<rss version=\"2.0\"><channel>\
+         <title>Fish &amp; Chips &nbsp;&amp;bogus; &lt;3</title>\
+         <item><link>http://x/?a=1&amp;b=2</link><p>one<br></br></p></item>\
+         <dc:creator>Me</dc:creator></channel></rss>",
+        doc.to_string()
+    );
+    let messages: Vec<_> = errors.iter()
+        .map(|e| (e.line(), e.column(), e.message()))
+        .collect();
+    assert_eq!(
+        vec![
+            (2, 14, "attribute version value not quoted"),
+            (3, 22, "unescaped '&'"),
+            (3, 30, "undeclared entity &nbsp; (replaced as HTML)"),
+            (3, 36, "undeclared entity &bogus;"),
+            (4, 51, "element <br> not closed"),
+            (4, 51, "element <p> not closed"),
+            (5, 1, "namespace prefix dc not declared"),
+            (6, 11, "element <rss> not closed"),
+        ],
+        messages
+    );
+
+    // Same result as strict for well-formed input
+    let src = r#"<a xmlns:x="urn:x">foo <x:b x:c="1"><![CDATA[<c>]]></x:b>
+                 <?pi data?></a>"#;
+    let (doc, errors) = xml::parse_utf8_lenient(src.as_bytes());
+    assert!(errors.is_empty());
+    let strict = xml::parse_utf8(src.as_bytes()).unwrap();
+    assert_eq!(strict.to_string(), doc.to_string());
+
+    let (doc, errors) = xml::parse_utf8_lenient(b"<a>\xFF</a>");
+    assert_eq!("<a>\u{FFFD}</a>", doc.to_string());
+    assert_eq!((1, 4), (errors[0].line(), errors[0].column()));
+}
+
 #[test]
 fn test_empty_inline() {
     ensure_logger();
@@ -799,6 +1037,86 @@ fn test_append_deep_clone() {
     );
 }
 
+#[test]
+fn test_replace_with_deep_clone() {
+    ensure_logger();
+    let src = html::parse_utf8_fragment(
+        b"<div>x<a href=\"link\"><i>bar</i>s</a>y</div>"
+    );
+    let aref = src.root_element_ref().unwrap()
+        .find(|n| n.is_elem(t::A)).unwrap();
+    let a = src.deep_clone(aref.id());
+
+    let mut doc = html::parse_utf8_fragment(
+        b"<ul><li>1</li><li>2</li><li>3</li></ul>"
+    );
+    let root = doc.root_element().unwrap();
+    let lis = doc.children(root).collect::<Vec<_>>();
+    let data = doc.replace(lis[1], a);
+    assert!(data.is_elem(t::LI));
+    assert_eq!(
+        "<ul><li>1</li><a href=\"link\"><i>bar</i>s</a><li>3</li></ul>",
+        doc.to_string()
+    );
+
+    // Multiple top-level nodes are inserted in order.
+    let mut pair = src.deep_clone(aref.id());
+    pair.append_child(Document::DOCUMENT_NODE_ID, Node::new_text("!"));
+    doc.replace(lis[0], pair);
+    assert_eq!(
+        "<ul><a href=\"link\"><i>bar</i>s</a>!\
+         <a href=\"link\"><i>bar</i>s</a><li>3</li></ul>",
+        doc.to_string()
+    );
+
+    doc.compact();
+    assert_eq!(13, doc.len());
+}
+
+#[test]
+fn test_wrap() {
+    ensure_logger();
+    let mut doc = html::parse_utf8_fragment(
+        b"<div><p>1</p><p>2</p><p>3</p><p>4</p></div>"
+    );
+    let root = doc.root_element().unwrap();
+    let ps = doc.children(root).collect::<Vec<_>>();
+
+    let w = doc.wrap(ps[0], Element::new(t::SECTION));
+    assert_eq!(Some(w), doc[ps[0]].parent);
+    doc.wrap_range(ps[1], ps[2], Element::new(t::ARTICLE));
+    doc.wrap_range(ps[3], ps[3], Element::new(t::ASIDE));
+    assert_eq!(
+        "<div><section><p>1</p></section>\
+         <article><p>2</p><p>3</p></article>\
+         <aside><p>4</p></aside></div>",
+        doc.to_string()
+    );
+
+    let first = doc.prepend_child(root, Node::new_text("0"));
+    assert_eq!(Some(first), doc[root].first_child);
+    let p = doc.children(w).next().unwrap();
+    doc.fold(p);
+    let empty = doc.append_child(root, Node::new_elem(Element::new(t::P)));
+    doc.prepend_child(empty, Node::new_text("5"));
+    assert_eq!(
+        "<div>0<section>1</section>\
+         <article><p>2</p><p>3</p></article>\
+         <aside><p>4</p></aside><p>5</p></div>",
+        doc.to_string()
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_wrap_range_out_of_order() {
+    ensure_logger();
+    let mut doc = html::parse_utf8_fragment(b"<div><p>1</p><p>2</p></div>");
+    let root = doc.root_element().unwrap();
+    let ps = doc.children(root).collect::<Vec<_>>();
+    doc.wrap_range(ps[1], ps[0], Element::new(t::SECTION));
+}
+
 #[test]
 fn test_select_children() {
     ensure_logger();
@@ -855,6 +1173,318 @@ fn test_select() {
     assert_eq!(f1, vec!["1", "2", "3", "4"]);
 }
 
+#[test]
+#[cfg(feature = "css")]
+fn test_select_css() {
+    ensure_logger();
+    let doc = html::parse_utf8(
+        "<article>\
+           <p class=\"lead intro\">1 <a href=\"/one\">one</a></p>\
+           <p>2 <a href=\"/two\">two</a> <a>none</a></p>\
+           <div><p class=\"lead\"><a href=\"/three\">three</a></p></div>\
+         </article>"
+            .as_bytes()
+    );
+
+    let f1: Vec<_> = doc
+        .select_css("article > p.lead a[href]")
+        .expect("valid selector")
+        .map(|n| n.attr(a::HREF).unwrap().to_string())
+        .collect();
+    assert_eq!(f1, vec!["/one"]);
+
+    let root = doc.root_element_ref().expect("root");
+    let f2: Vec<_> = root
+        .select_css("p.lead a:any-link, p:not(.lead) > a:first-child")
+        .expect("valid selector")
+        .map(|n| n.text().unwrap().to_string())
+        .collect();
+    assert_eq!(f2, vec!["one", "two", "three"]);
+
+    let p = root.find_css("div p").expect("valid selector").expect("p");
+    assert_eq!("three", p.text().unwrap().as_ref());
+    assert!(root.find_css("div > a").expect("valid selector").is_none());
+}
+
+#[test]
+#[cfg(feature = "css")]
+fn test_select_css_compiled() {
+    use crate::css::CssSelector;
+
+    ensure_logger();
+    let doc = html::parse_utf8_fragment(
+        "<ul><li id=\"a\">1</li><li>2</li><li id=\"c\">3</li></ul>"
+            .as_bytes()
+    );
+    let sel: CssSelector = "li#a, li:last-child".parse().expect("valid");
+    assert_eq!("li#a, li:last-child", sel.to_string());
+
+    let root = doc.root_element_ref().expect("root");
+    let f1: Vec<_> = root
+        .select(|n| sel.matches(n))
+        .map(|n| n.text().unwrap().to_string())
+        .collect();
+    assert_eq!(f1, vec!["1", "3"]);
+
+    // Text and the document node never match
+    assert!(!sel.matches(&doc.document_node_ref()));
+    assert!(!root.descendants()
+        .any(|n| n.as_text().is_some() && sel.matches(&n)));
+}
+
+#[test]
+#[cfg(feature = "css")]
+fn test_select_css_error() {
+    use crate::css::CssSelector;
+
+    ensure_logger();
+    let doc = html::parse_utf8_fragment("<p>text</p>".as_bytes());
+    assert!(doc.select_css("p >").is_err());
+    assert!(doc.select_css("p::before").is_err());
+    let err = CssSelector::parse("div,\n  p[").unwrap_err();
+    assert_eq!(2, err.line());
+    debug!("css error: {}", err);
+}
+
+#[test]
+fn test_serialize_pretty() {
+    ensure_logger();
+    let doc = html::parse_utf8(
+        "<!DOCTYPE html><html><head><title>Title</title></head><body>\
+         <div><p>Some <i>inline</i> text, which is long enough to \
+         require wrapping at the configured width.</p>\
+         <pre> keep\n  this </pre>\
+         <ul><li>one</li><li>two <b>bold</b></li></ul>\
+         text <textarea>  a\n b</textarea><!--c--></div>\
+         </body></html>".as_bytes()
+    );
+    let opts = PrettyOptions { max_width: 40, ..PrettyOptions::default() };
+    let mut out = Vec::new();
+    doc.serialize_pretty(&mut out, &opts).unwrap();
+    assert_eq!(
+        "<!DOCTYPE html>\n\
+         <html>\n  \
+           <head>\n    \
+             <title>Title</title>\n  \
+           </head>\n  \
+           <body>\n    \
+             <div>\n      \
+               <p>\n        \
+                 Some <i>inline</i> text, which\n        \
+                 is long enough to require\n        \
+                 wrapping at the configured\n        \
+                 width.\n      \
+               </p>\n      \
+               <pre> keep\n  this </pre>\n      \
+               <ul>\n        \
+                 <li>one</li>\n        \
+                 <li>two <b>bold</b></li>\n      \
+               </ul>\n      \
+               text <textarea>  a\n b</textarea><!--c-->\n    \
+             </div>\n  \
+           </body>\n\
+         </html>\n",
+        String::from_utf8(out).unwrap()
+    );
+
+    let ul = doc.document_node_ref().find(|n| n.is_elem(t::UL)).unwrap();
+    let opts = PrettyOptions {
+        indent: 1,
+        indent_inline: true,
+        ..PrettyOptions::default()
+    };
+    let mut out = Vec::new();
+    ul.serialize_pretty(&mut out, &opts).unwrap();
+    assert_eq!(
+        "<ul>\n <li>one</li>\n <li>\n  two\n  <b>bold</b>\n </li>\n</ul>\n",
+        String::from_utf8(out).unwrap()
+    );
+
+    let pre = doc.document_node_ref().find(|n| n.is_elem(t::PRE)).unwrap();
+    let mut out = Vec::new();
+    pre.children().next().unwrap().serialize_pretty(&mut out, &opts).unwrap();
+    assert_eq!(" keep\n  this \n", String::from_utf8(out).unwrap());
+}
+
+#[test]
+fn test_xpath_select() {
+    use crate::xpath::{XNode, XPath};
+
+    ensure_logger();
+    let doc = html::parse_utf8(
+        "<div id=\"a\">\
+           <p class=\"lead\">one <a href=\"/1\">1</a></p>\
+           <p>two <a href=\"/2\">2</a><!--c--></p>\
+           <p>three</p>\
+         </div>".as_bytes()
+    );
+    let root = doc.document_node_ref();
+
+    let xp = XPath::compile("//p[@class='lead']//a/@href").unwrap();
+    let hrefs: Vec<_> = xp.select(root).unwrap()
+        .iter()
+        .map(XNode::string_value)
+        .collect();
+    assert_eq!(vec!["/1"], hrefs);
+
+    let xp = XPath::compile("//a/@href | //p[last()]").unwrap();
+    let vals: Vec<_> = xp.select(root).unwrap()
+        .iter()
+        .map(XNode::string_value)
+        .collect();
+    assert_eq!(vec!["/1", "/2", "three"], vals);
+
+    let xp = XPath::compile("/html/body/div/p[position() > 1]").unwrap();
+    assert_eq!(2, xp.select(root).unwrap().len());
+
+    let p2 = XPath::compile("//p[2]").unwrap().select(root).unwrap()[0];
+    let xp = XPath::compile("preceding-sibling::p/text()").unwrap();
+    let texts: Vec<_> = xp.select(p2.node_ref()).unwrap()
+        .iter()
+        .map(XNode::string_value)
+        .collect();
+    assert_eq!(vec!["one "], texts);
+
+    let xp = XPath::compile("ancestor::*[1]/@id").unwrap();
+    assert_eq!("a", xp.select(p2.node_ref()).unwrap()[0].string_value());
+
+    let xp = XPath::compile("..//comment()").unwrap();
+    assert_eq!(1, xp.select(p2.node_ref()).unwrap().len());
+}
+
+#[test]
+fn test_xpath_values() {
+    use crate::xpath::{Value, XPath};
+
+    ensure_logger();
+    let doc = html::parse_utf8_fragment(
+        "<ul><li>1</li><li> 2 </li><li>x</li></ul>".as_bytes()
+    );
+    let root = doc.document_node_ref();
+    let eval = |expr: &str| {
+        XPath::compile(expr).unwrap().evaluate(root).unwrap()
+    };
+
+    assert_eq!(Value::Number(3.0), eval("count(//li)"));
+    assert!(eval("sum(//li)").as_number().is_nan());
+    assert_eq!(Value::Number(3.0), eval("sum(//li[position() < 3])"));
+    assert_eq!(Value::Boolean(true), eval("//li = 'x'"));
+    assert_eq!(Value::Boolean(true), eval("//li = 2"));
+    assert_eq!(Value::Boolean(false), eval("//li > 2"));
+    assert_eq!(Value::String("2".to_string()),
+               eval("normalize-space(//li[2])"));
+    assert_eq!(Value::String("ULx".to_string()),
+               eval("concat(translate(name(//ul), 'ul', 'UL'), //li[3])"));
+    assert_eq!(Value::String("234".to_string()),
+               eval("substring('12345', 1.5, 2.6)"));
+    assert_eq!(Value::String("-0.5".to_string()),
+               eval("string(-1 div 2)"));
+    assert_eq!("Infinity", eval("1 div 0").as_string());
+    assert_eq!("NaN", eval("number('1e3')").as_string());
+    assert_eq!(Value::Number(2.0), eval("7 mod 5"));
+    assert_eq!(Value::Number(-2.0), eval("round(-2.5)"));
+    assert_eq!(Value::Boolean(true),
+               eval("starts-with(substring-after('a=b', '='), 'b')"));
+}
+
+#[test]
+fn test_xpath_error() {
+    use crate::xpath::{Value, XPath, XPathError};
+
+    ensure_logger();
+    assert!(XPath::compile("//p[").is_err());
+    assert!(XPath::compile("//p[@").is_err());
+    assert!(XPath::compile("$var").is_err());
+    assert!(XPath::compile("count()").is_err());
+    assert!(XPath::compile("frob(1)").is_err());
+    assert_eq!(
+        XPathError::UnknownPrefix("x".to_string()),
+        XPath::compile("//x:p").unwrap_err()
+    );
+
+    // Deep nesting is rejected, rather than overflowing the stack
+    let nested = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+    match XPath::compile(&nested) {
+        Err(XPathError::Syntax(_, msg)) => assert!(msg.contains("deeply")),
+        r => panic!("unexpected {:?}", r),
+    }
+    let nested = format!("{}1{}", "f(".repeat(100_000), ")".repeat(100_000));
+    assert!(XPath::compile(&nested.replace('f', "not")).is_err());
+    let nested = format!("{}{}", "//p[".repeat(100_000), "]".repeat(100_000));
+    assert!(XPath::compile(&nested).is_err());
+    assert!(XPath::compile(&format!("{}1{}", "(".repeat(64), ")".repeat(64)))
+        .is_ok());
+
+    // Long, flat operator chains are not nesting
+    let doc = html::parse_utf8_fragment("<p>text</p>".as_bytes());
+    let root = doc.document_node_ref();
+    let eval = |expr: &str| XPath::compile(expr).unwrap().evaluate(root);
+    assert_eq!(
+        Value::Number(100_000.0),
+        eval(&vec!["1"; 100_000].join("+")).unwrap()
+    );
+    assert_eq!(
+        Value::Boolean(true),
+        eval(&(vec!["false()"; 129].join(" or ") + " or true()")).unwrap()
+    );
+    assert_eq!(
+        Value::Boolean(false),
+        eval(&(vec!["1 = 1"; 1_000].join(" and ") + " and 0")).unwrap()
+    );
+    assert_eq!(
+        Value::Number(-1.0),
+        eval(&format!("{}1", "-".repeat(100_001))).unwrap()
+    );
+    assert_eq!(
+        Value::Number(7.0),
+        eval(&format!("{}'7'", "-".repeat(100_000))).unwrap()
+    );
+    assert_eq!(
+        Value::Number(1.0),
+        eval(&format!("count({})", vec!["//p"; 100_000].join("|"))).unwrap()
+    );
+    assert_eq!(Value::Number(-3.0), eval("1 - 2 * 3 + 4 div 2").unwrap());
+    assert_eq!(Value::Boolean(true), eval("1 < 2 = 1 > 0").unwrap());
+
+    let doc = html::parse_utf8_fragment("<p>text</p>".as_bytes());
+    let xp = XPath::compile("count(//p)").unwrap();
+    let err = xp.select(doc.document_node_ref()).unwrap_err();
+    debug!("xpath error: {}", err);
+    if let XPathError::Type(_) = err {} else { panic!("wrong error type") }
+}
+
+#[test]
+#[cfg(feature = "xml")]
+fn test_xpath_xml_ns() {
+    use crate::xpath::{XNode, XPath};
+
+    ensure_logger();
+    let doc = xml::parse_utf8(
+        r#"<doc xmlns="urn:d" xmlns:x="urn:x">
+             <x:item x:k="1" k="2">a</x:item>
+             <item>b</item>
+           </doc>"#.as_bytes()
+    ).unwrap();
+    let root = doc.document_node_ref();
+
+    assert!(XPath::compile("//item").unwrap().select(root).unwrap().is_empty());
+
+    let ns = [("d", "urn:d"), ("y", "urn:x")];
+    let xp = XPath::compile_ns("//y:item/@y:k | //d:item", &ns).unwrap();
+    let vals: Vec<_> = xp.select(root).unwrap()
+        .iter()
+        .map(XNode::string_value)
+        .collect();
+    assert_eq!(vec!["1", "b"], vals);
+
+    let xp = XPath::compile_ns("name(//y:item)", &ns).unwrap();
+    assert_eq!("x:item", xp.evaluate(root).unwrap().as_string());
+    let xp = XPath::compile_ns("namespace-uri(/*)", &ns).unwrap();
+    assert_eq!("urn:d", xp.evaluate(root).unwrap().as_string());
+    let xp = XPath::compile("//*[local-name() = 'item']/@k").unwrap();
+    assert_eq!(1, xp.select(root).unwrap().len());
+}
+
 #[test]
 fn test_tag_metadata() {
     let a_meta = TAG_META.get(&t::A).unwrap();
@@ -900,80 +1530,1119 @@ fn sample_file(fname: &str) -> File {
 }
 
 #[test]
-fn test_documento_utf8() {
+#[cfg(feature = "xml")]
+fn test_xml_shiftjis_decl() {
     ensure_logger();
     let eh = EncodingHint::shared_default(enc::UTF_8);
-    let mut reader = ShortRead(sample_file("documento_utf8.html"));
-    let doc = html::parse_buffered(eh, &mut reader).unwrap();
-    let root = doc.root_element_ref().expect("root");
-    let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
-    assert_eq!("¿De donde eres tú?", body.text().unwrap().as_ref().trim());
+    let mut reader = ShortRead(sample_file("feed_shiftjis_decl.xml"));
+    let doc = xml::parse_buffered(eh.clone(), &mut reader).unwrap();
+    let title = doc.document_node_ref()
+        .find(|n| n.is_elem("title"))
+        .expect("title");
+    assert_eq!("日本語のフィード", title.text().unwrap().as_ref());
+    assert_eq!(enc::SHIFT_JIS, eh.borrow().top().unwrap());
+    assert_eq!(0, eh.borrow().errors());
 }
 
 #[test]
-fn test_documento_utf8_bom() {
+#[cfg(feature = "xml")]
+fn test_xml_windows1251_decl() {
     ensure_logger();
     let eh = EncodingHint::shared_default(enc::UTF_8);
-    let mut reader = ShortRead(sample_file("documento_utf8_bom.html"));
-    let doc = html::parse_buffered(eh, &mut reader).unwrap();
-    let root = doc.root_element_ref().expect("root");
-    let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
-    assert_eq!("¿De donde eres tú?", body.text().unwrap().as_ref().trim());
+    let mut reader = ShortRead(sample_file("feed_windows1251_decl.xml"));
+    let doc = xml::parse_buffered(eh.clone(), &mut reader).unwrap();
+    let title = doc.document_node_ref()
+        .find(|n| n.is_elem("title"))
+        .expect("title");
+    assert_eq!("Новости дня", title.text().unwrap().as_ref());
+    assert_eq!(enc::WINDOWS_1251, eh.borrow().top().unwrap());
 }
 
 #[test]
-fn test_documento_utf16be_bom() {
+#[cfg(feature = "xml")]
+fn test_xml_utf16le_bom() {
     ensure_logger();
     let eh = EncodingHint::shared_default(enc::UTF_8);
-    let mut reader = ShortRead(sample_file("documento_utf16be_bom.html"));
-    let doc = html::parse_buffered(eh, &mut reader).unwrap();
-    let root = doc.root_element_ref().expect("root");
-    let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
-    assert_eq!("¿De donde eres tú?", body.text().unwrap().as_ref().trim());
+    let mut reader = ShortRead(sample_file("feed_utf16le_bom.xml"));
+    let doc = xml::parse_buffered(eh.clone(), &mut reader).unwrap();
+    let title = doc.document_node_ref()
+        .find(|n| n.is_elem("title"))
+        .expect("title");
+    assert_eq!("¿De donde eres tú?", title.text().unwrap().as_ref());
+    assert_eq!(enc::UTF_16LE, eh.borrow().top().unwrap());
 }
 
 #[test]
-fn test_documento_utf16be_meta_utf16le() {
+#[cfg(feature = "xml")]
+fn test_xml_buffered_error() {
     ensure_logger();
-    let eh = EncodingHint::shared_with_hint(enc::UTF_16BE, HTTP_CTYPE_CONF);
-    // The contained meta should be ignored, since, if it was correct, we
-    // couldn't read it from the initial UTF-16BE encoding!
-    let mut reader = ShortRead(sample_file("documento_utf16be_meta_utf16le.html"));
-    let doc = html::parse_buffered(eh, &mut reader).unwrap();
-    let root = doc.root_element_ref().expect("root");
-    let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
-    assert_eq!("¿De donde eres tú?", body.text().unwrap().as_ref().trim());
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = "<a><b></a>".as_bytes();
+    let err = xml::parse_buffered(eh, &mut reader).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    assert!(err.get_ref().unwrap().is::<xml::XmlError>());
 }
 
 #[test]
-fn test_documento_utf16le_bom() {
+#[cfg(feature = "xml")]
+fn test_xml_buffered_read_error() {
     ensure_logger();
     let eh = EncodingHint::shared_default(enc::UTF_8);
-    let mut reader = ShortRead(sample_file("documento_utf16le_bom.html"));
-    let doc = html::parse_buffered(eh, &mut reader).unwrap();
-    let root = doc.root_element_ref().expect("root");
-    let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
-    assert_eq!("¿De donde eres tú?", body.text().unwrap().as_ref().trim());
+    let xml = format!("<a>{}", "<b>text</b>".repeat(2_000));
+    let mut reader = xml.as_bytes().chain(FailRead);
+    let err = xml::parse_buffered(eh, &mut reader).unwrap_err();
+    assert_eq!(io::ErrorKind::Other, err.kind());
+    assert_eq!("fail read", err.to_string());
+}
+
+// A reader which always fails.
+#[cfg(feature = "xml")]
+struct FailRead;
+
+#[cfg(feature = "xml")]
+impl io::Read for FailRead {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::Other, "fail read"))
+    }
 }
 
 #[test]
-fn test_documento_utf16le() {
+#[cfg(feature = "detect")]
+fn test_detect_windows1251() {
     ensure_logger();
-    let eh = EncodingHint::shared_default(enc::UTF_16LE);
-    let mut reader = ShortRead(sample_file("documento_utf16le.html"));
-    let doc = html::parse_buffered(eh, &mut reader).unwrap();
+    let html = "<html><head><title>Новости</title></head>\n\
+                <body><p>Промышленность России: новые производства \
+                открываются в регионах, а экспорт продолжает расти.</p>\n\
+                </body></html>";
+    let (bytes, _, _) = enc::WINDOWS_1251.encode(html);
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = ShortRead(&bytes[..]);
+    let doc = html::parse_buffered(eh.clone(), &mut reader).unwrap();
+    assert_eq!(enc::WINDOWS_1251, eh.borrow().top().unwrap());
+    assert_eq!(DETECT_CONF, eh.borrow().confidence());
+    assert_eq!(0, eh.borrow().errors());
     let root = doc.root_element_ref().expect("root");
     let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
-    assert_eq!("¿De donde eres tú?", body.text().unwrap().as_ref().trim());
+    assert!(body.text().unwrap().contains("Промышленность"));
 }
 
 #[test]
-fn test_documento_utf16le_meta_utf8() {
+#[cfg(feature = "detect")]
+fn test_detect_below_content_type() {
     ensure_logger();
-    let eh = EncodingHint::shared_with_hint(enc::UTF_16LE, HTTP_CTYPE_CONF);
-    // The contained meta should be ignored, since, if it was correct, we
-    // couldn't read it from the initial UTF-16 encoding!
-    let mut reader = ShortRead(sample_file("documento_utf16le_meta_utf8.html"));
+    let html = "<p>Промышленность России: новые производства \
+                открываются в регионах, а экспорт продолжает расти.</p>";
+    let (bytes, _, _) = enc::WINDOWS_1251.encode(html);
+    let (eh, _) = EncodingHint::shared_with_content_type(
+        enc::UTF_8, "text/html; charset=KOI8-R"
+    );
+    let mut reader = ShortRead(&bytes[..]);
+    html::parse_buffered(eh.clone(), &mut reader).unwrap();
+    assert_eq!(enc::KOI8_R, eh.borrow().top().unwrap());
+    assert_eq!(HTTP_CTYPE_CONF, eh.borrow().confidence());
+    assert_eq!(None, eh.borrow().changed());
+}
+
+#[test]
+#[cfg(feature = "detect")]
+fn test_detect_ascii_unchanged() {
+    ensure_logger();
+    let eh = EncodingHint::shared_default(enc::WINDOWS_1252);
+    let mut reader = "<p>Plain ASCII</p>".as_bytes();
+    html::parse_buffered(eh.clone(), &mut reader).unwrap();
+    assert_eq!(enc::WINDOWS_1252, eh.borrow().top().unwrap());
+    assert_eq!(None, eh.borrow().changed());
+}
+
+#[test]
+fn test_parse_errors() {
+    let html = "<!DOCTYPE html>\n<p>one</div>\n\n<p>two</span>\n</b>";
+    let errors = html::ParseErrors::shared(10);
+    let opts = html::HtmlParseOptions {
+        errors: Some(errors.clone()),
+        ..html::HtmlParseOptions::default()
+    };
+    let doc = html::parse_utf8_with_options(html.as_bytes(), &opts).unwrap();
+    let errors = errors.borrow();
+    assert_eq!(
+        "<!DOCTYPE html><html><head></head><body><p>one\n\n</p><p>two\n\
+         </p></body></html>",
+        doc.to_string()
+    );
+    let lines: Vec<u64> = errors.errors().iter().map(|e| e.line()).collect();
+    assert_eq!(vec![2, 4, 5], lines, "{:?}", errors);
+    assert_eq!(3, errors.count());
+    assert!(!errors.truncated());
+    assert!(errors.errors()[0].to_string().starts_with("2: "));
+
+    let errors = html::ParseErrors::shared(1);
+    let opts = html::HtmlParseOptions {
+        errors: Some(errors.clone()),
+        ..html::HtmlParseOptions::default()
+    };
+    html::parse_utf8_with_options(html.as_bytes(), &opts).unwrap();
+    let errors = errors.borrow();
+    assert_eq!(1, errors.errors().len());
+    assert_eq!(3, errors.count());
+    assert!(errors.truncated());
+}
+
+fn spans_opts() -> html::HtmlParseOptions {
+    html::HtmlParseOptions { spans: true, ..html::HtmlParseOptions::default() }
+}
+
+#[test]
+fn test_html_source_spans() {
+    let html = "<!DOCTYPE html>\n<html>\n<body>\n<p>one\ntwo</p>\n\
+                <div>\n<br>\n</div>\n</body>\n</html>\n";
+    let doc = html::parse_utf8_with_options(html.as_bytes(), &spans_opts())
+        .unwrap();
+    let root = doc.root_element_ref().unwrap();
+    let lines = |n: NodeRef<'_>| {
+        let span = n.source_span().expect("span");
+        assert_eq!(None, span.start.column);
+        assert_eq!(None, span.start.offset);
+        (span.start.line, span.end.line)
+    };
+    let p = root.find(|n| n.is_elem(t::P)).unwrap();
+    assert_eq!((4, 5), lines(p));
+    assert_eq!((4, 5), lines(p.children().next().unwrap()));
+    let br = root.find(|n| n.is_elem(t::BR)).unwrap();
+    assert_eq!((7, 7), lines(br));
+    let div = br.parent().unwrap();
+    assert_eq!((6, 8), lines(div));
+    assert_eq!((2, 11), lines(root));
+
+    // Retained by compact
+    let mut doc = doc;
+    let br_id = doc.root_element_ref().unwrap()
+        .find(|n| n.is_elem(t::BR)).unwrap().id();
+    let span = doc.source_span(br_id);
+    let p_id = doc.root_element_ref().unwrap()
+        .find(|n| n.is_elem(t::P)).unwrap().id();
+    doc.unlink(p_id);
+    doc.compact();
+    let br = doc.root_element_ref().unwrap()
+        .find(|n| n.is_elem(t::BR)).unwrap();
+    assert_ne!(br_id, br.id());
+    assert_eq!(span, br.source_span());
+
+    // Not recorded by default
+    let doc = html::parse_utf8(html.as_bytes());
+    assert!(doc.nodes().all(|id| doc.source_span(id).is_none()));
+}
+
+#[test]
+#[cfg(feature = "xml")]
+fn test_xml_source_spans() {
+    let xml = "<?xml version=\"1.0\"?>\n\
+               <doc>\n  <é a=\"1\">text</é><b/>\n</doc>";
+    let opts = xml::XmlParseOptions {
+        spans: true,
+        ..xml::XmlParseOptions::default()
+    };
+    let doc = xml::parse_utf8_with_options(xml.as_bytes(), &opts).unwrap();
+    let root = doc.root_element_ref().unwrap();
+    let span = |n: NodeRef<'_>| {
+        let span = n.source_span().expect("span");
+        let (s, e) = (span.start, span.end);
+        assert_eq!(s.offset, Some(char_offset(xml, s.line, s.column)));
+        (s.line, s.column.unwrap(), e.line, e.column.unwrap())
+    };
+    let e = root.find(|n| n.is_elem("é")).unwrap();
+    assert_eq!((3, 3, 3, 20), span(e));
+    assert_eq!(
+        "<é a=\"1\">text</é>",
+        &xml[e.source_span().unwrap().start.offset.unwrap()..
+             e.source_span().unwrap().end.offset.unwrap()]
+    );
+    assert_eq!((3, 12, 3, 16), span(e.children().next().unwrap()));
+    let b = root.find(|n| n.is_elem("b")).unwrap();
+    assert_eq!((3, 20, 3, 24), span(b));
+    assert_eq!(2, span(root).0);
+}
+
+#[cfg(feature = "xml")]
+fn char_offset(s: &str, line: u32, column: Option<u32>) -> usize {
+    let start: usize = s.split('\n')
+        .take(line as usize - 1)
+        .map(|l| l.len() + 1)
+        .sum();
+    start + s[start..].chars()
+        .take(column.unwrap() as usize - 1)
+        .map(char::len_utf8)
+        .sum::<usize>()
+}
+
+#[test]
+fn test_fragment_in_context() {
+    let rows = "<tr><td>1</td></tr>\n<tr><td>2</td></tr>";
+    let tbody = QualName::new(None, ns::HTML, t::TBODY);
+    let doc = html::parse_fragment_in(tbody, rows.as_bytes());
+    assert_eq!(rows, doc.to_string());
+    assert_eq!(None, doc.root_element());
+    assert_eq!(
+        4,
+        doc.nodes().filter(|&id| doc[id].as_element().is_some()).count()
+    );
+    assert_eq!(doc.len() as usize, doc.nodes().count(), "no cruft");
+
+    let table = QualName::new(None, ns::HTML, t::TABLE);
+    let doc = html::parse_fragment_in(table, rows.as_bytes());
+    assert_eq!(format!("<tbody>{}</tbody>", rows), doc.to_string());
+    assert!(doc.root_element_ref().unwrap().is_elem(t::TBODY));
+
+    // Contrast with the div context of parse_utf8_fragment
+    let doc = html::parse_utf8_fragment(rows.as_bytes());
+    assert_eq!("<div>1\n2</div>", doc.to_string());
+
+    let select = QualName::new(None, ns::HTML, t::SELECT);
+    let doc = html::parse_fragment_in(
+        select,
+        b"<option>a<option selected>b<div>c</div>"
+    );
+    assert_eq!(
+        "<option>a</option><option selected=\"\">bc</option>",
+        doc.to_string()
+    );
+
+    let textarea = QualName::new(None, ns::HTML, t::TEXTAREA);
+    let doc = html::parse_fragment_in(textarea, b"<b>&amp;</b>");
+    assert_eq!(Some("<b>&</b>".into()), doc.text(Document::DOCUMENT_NODE_ID));
+}
+
+#[test]
+fn test_buffered_fragment_windows1252() {
+    ensure_logger();
+    let (bytes, _, _) = enc::WINDOWS_1252.encode("<p>¿De donde eres tú?</p>");
+    let eh = EncodingHint::shared_default(enc::WINDOWS_1252);
+    let mut reader = ShortRead(&bytes[..]);
+    let doc = html::parse_buffered_fragment(eh.clone(), &mut reader).unwrap();
+    assert_eq!("<p>¿De donde eres tú?</p>", doc.to_string());
+    assert_eq!(0, eh.borrow().errors());
+}
+
+#[test]
+fn test_buffered_fragment_shiftjis() {
+    ensure_logger();
+    let (bytes, _, _) = enc::SHIFT_JIS.encode("日本語の<b>断片</b>");
+    // A known encoding, which (short) detection shouldn't override
+    let eh = EncodingHint::shared_with_hint(enc::SHIFT_JIS, HTML_META_CONF);
+    let mut reader = ShortRead(&bytes[..]);
+    let doc = html::parse_buffered_fragment(eh.clone(), &mut reader).unwrap();
+    assert_eq!("<div>日本語の<b>断片</b></div>", doc.to_string());
+    assert_eq!(0, eh.borrow().errors());
+}
+
+#[test]
+fn test_buffered_fragment_utf8_bom() {
+    ensure_logger();
+    let eh = EncodingHint::shared_default(enc::WINDOWS_1252);
+    let mut reader = ShortRead("\u{FEFF}<p>tú</p>".as_bytes());
+    let doc = html::parse_buffered_fragment(eh.clone(), &mut reader).unwrap();
+    assert_eq!("<p>tú</p>", doc.to_string());
+    assert_eq!(enc::UTF_8, eh.borrow().top().unwrap());
+}
+
+#[test]
+fn test_options_scripting() {
+    ensure_logger();
+    let html = "<noscript><p>no script</p></noscript>";
+    let doc = html::parse_utf8_fragment(html.as_bytes());
+    let ns_id = doc.nodes().find(|&id| doc[id].is_elem(t::NOSCRIPT)).unwrap();
+    assert_eq!(1, doc.children(ns_id).count());
+    assert!(doc[doc[ns_id].first_child.unwrap()].as_text().is_some());
+
+    let opts = html::HtmlParseOptions {
+        scripting_enabled: false,
+        ..Default::default()
+    };
+    let doc = html::parse_utf8_fragment_with_options(html.as_bytes(), &opts)
+        .unwrap();
+    let ns_id = doc.nodes().find(|&id| doc[id].is_elem(t::NOSCRIPT)).unwrap();
+    assert!(doc[doc[ns_id].first_child.unwrap()].is_elem(t::P));
+}
+
+#[test]
+fn test_options_drop_doctype() {
+    ensure_logger();
+    let html = "<!DOCTYPE html><p>1</p>";
+    let opts = html::HtmlParseOptions {
+        drop_doctype: true,
+        ..Default::default()
+    };
+    let doc = html::parse_utf8_with_options(html.as_bytes(), &opts).unwrap();
+    assert_eq!(
+        "<html><head></head><body><p>1</p></body></html>",
+        doc.to_string()
+    );
+    assert_eq!(QuirksMode::NoQuirks, doc.quirks_mode());
+}
+
+#[test]
+fn test_quirks_mode() {
+    ensure_logger();
+    let doc = html::parse_utf8(b"<!DOCTYPE html><p>1</p>");
+    assert_eq!(QuirksMode::NoQuirks, doc.quirks_mode());
+
+    let doc = html::parse_utf8(b"<p>1</p>");
+    assert_eq!(QuirksMode::Quirks, doc.quirks_mode());
+    assert_eq!(QuirksMode::Quirks, doc.bulk_clone().quirks_mode());
+
+    let doc = html::parse_utf8(
+        b"<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\" \
+          \"http://www.w3.org/TR/html4/loose.dtd\"><p>1</p>"
+    );
+    assert_eq!(QuirksMode::LimitedQuirks, doc.quirks_mode());
+
+    let opts = html::HtmlParseOptions {
+        iframe_srcdoc: true,
+        ..Default::default()
+    };
+    let doc = html::parse_utf8_with_options(b"<p>1</p>", &opts).unwrap();
+    assert_eq!(QuirksMode::NoQuirks, doc.quirks_mode());
+
+    let opts = html::HtmlParseOptions {
+        quirks_mode: QuirksMode::Quirks,
+        ..Default::default()
+    };
+    let doc = html::parse_utf8_fragment_with_options(b"<p>1</p>", &opts)
+        .unwrap();
+    assert_eq!(QuirksMode::Quirks, doc.quirks_mode());
+}
+
+// Return the maximum depth of any node, where children of the document node
+// are at depth 1.
+fn max_depth(doc: &Document) -> usize {
+    doc.nodes()
+        .map(|id| doc.node_and_ancestors(id).count() - 1)
+        .max()
+        .unwrap_or(0)
+}
+
+// Parse HTML with the given limits.
+fn parse_limited(html: &[u8], limits: &ParseLimits)
+    -> Result<Document, LimitError>
+{
+    let opts = html::HtmlParseOptions {
+        limits: Some(limits.clone()),
+        ..html::HtmlParseOptions::default()
+    };
+    html::parse_utf8_with_options(html, &opts)
+}
+
+#[test]
+fn test_limits_default() {
+    ensure_logger();
+    let html = sample_bytes("github-dekellum.html");
+    let doc = parse_limited(&html, &ParseLimits::default()).unwrap();
+    assert_eq!(html::parse_utf8(&html).to_string(), doc.to_string());
+}
+
+#[test]
+fn test_limits_html_depth() {
+    ensure_logger();
+    let html = "<div>".repeat(2_000);
+    let limits = ParseLimits { max_depth: 50, ..ParseLimits::default() };
+    let err = parse_limited(html.as_bytes(), &limits).unwrap_err();
+    assert_eq!(Limit::Depth, err.limit());
+    assert_eq!(50, err.max());
+    assert_eq!(1, err.line());
+
+    let limits = ParseLimits { truncate: true, ..limits };
+    let doc = parse_limited(html.as_bytes(), &limits).unwrap();
+    assert_eq!(50, max_depth(&doc));
+    assert_eq!(doc.len() as usize, doc.nodes().count(), "compacted");
+}
+
+#[test]
+fn test_limits_html_nodes() {
+    ensure_logger();
+    let html = "<p>x</p>\n".repeat(1_000);
+    let limits = ParseLimits { max_nodes: 100, ..ParseLimits::default() };
+    let err = parse_limited(html.as_bytes(), &limits).unwrap_err();
+    assert_eq!(Limit::Nodes, err.limit());
+    assert!(err.line() > 1);
+
+    let limits = ParseLimits { truncate: true, ..limits };
+    let doc = parse_limited(html.as_bytes(), &limits).unwrap();
+    assert!(doc.len() <= 100);
+    assert!(doc.to_string().starts_with("<html><head></head><body><p>x</p>"));
+}
+
+#[test]
+fn test_limits_html_attrs() {
+    ensure_logger();
+    let html = "<div a=1 b=2 c=3 d=4 title=\"añb\">x</div>";
+    let limits = ParseLimits {
+        max_attributes: 5,
+        max_attr_value_len: 2,
+        ..ParseLimits::default()
+    };
+    let err = parse_limited(html.as_bytes(), &limits).unwrap_err();
+    assert_eq!(Limit::AttrValueLen, err.limit());
+
+    let limits = ParseLimits { max_attributes: 3, truncate: true, ..limits };
+    let doc = parse_limited(html.as_bytes(), &limits).unwrap();
+    let div = doc.root_element_ref().unwrap()
+        .find(|n| n.is_elem(t::DIV))
+        .unwrap();
+    assert_eq!(3, div.as_element().unwrap().attrs.len());
+
+    let limits = ParseLimits { max_attributes: 5, ..limits };
+    let doc = parse_limited(html.as_bytes(), &limits).unwrap();
+    let div = doc.root_element_ref().unwrap()
+        .find(|n| n.is_elem(t::DIV))
+        .unwrap();
+    assert_eq!(Some("a"), div.attr(a::TITLE).map(|v| v.as_ref()));
+}
+
+#[test]
+fn test_limits_html_text() {
+    ensure_logger();
+    let html = "<p>1234</p><!--5678--><p>9ñ</p><p>more</p>";
+    let limits = ParseLimits {
+        max_text_bytes: 10,
+        truncate: true,
+        ..ParseLimits::default()
+    };
+    let doc = parse_limited(html.as_bytes(), &limits).unwrap();
+    assert_eq!(
+        "<html><head></head><body><p>1234</p><!--5678--><p>9</p><p>m</p>\
+         </body></html>",
+        doc.to_string()
+    );
+}
+
+#[test]
+fn test_limits_html_buffered() {
+    ensure_logger();
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let html = "<div>".repeat(100);
+    let mut reader = ShortRead(html.as_bytes());
+    let opts = html::HtmlParseOptions {
+        limits: Some(ParseLimits { max_depth: 10, ..ParseLimits::default() }),
+        ..html::HtmlParseOptions::default()
+    };
+    let err = html::parse_buffered_with_options(eh, &mut reader, &opts)
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    let le = err.get_ref().unwrap().downcast_ref::<LimitError>().unwrap();
+    assert_eq!(Limit::Depth, le.limit());
+}
+
+#[test]
+fn test_limits_html_fragment() {
+    ensure_logger();
+    let limits = ParseLimits { max_nodes: 4, ..ParseLimits::default() };
+    let opts = html::HtmlParseOptions {
+        limits: Some(limits.clone()),
+        ..html::HtmlParseOptions::default()
+    };
+    let err = html::parse_utf8_fragment_with_options(b"<p>1</p><p>2</p>", &opts)
+        .unwrap_err();
+    assert_eq!(Limit::Nodes, err.limit());
+
+    // Truncated to no root element
+    let opts = html::HtmlParseOptions {
+        limits: Some(ParseLimits { max_nodes: 1, truncate: true, ..limits }),
+        ..opts
+    };
+    let doc = html::parse_utf8_fragment_with_options(b"<p>1</p>", &opts)
+        .unwrap();
+    assert_eq!("", doc.to_string());
+    let context = QualName::new(None, ns::HTML, t::TBODY);
+    let doc = html::parse_fragment_in_with_options(context, b"<tr/>", &opts)
+        .unwrap();
+    assert_eq!("", doc.to_string());
+}
+
+// Parse XML with the given limits.
+#[cfg(feature = "xml")]
+fn parse_xml_limited(xml: &[u8], limits: &ParseLimits)
+    -> Result<Document, xml::XmlError>
+{
+    let opts = xml::XmlParseOptions {
+        limits: Some(limits.clone()),
+        ..xml::XmlParseOptions::default()
+    };
+    xml::parse_utf8_with_options(xml, &opts)
+}
+
+#[test]
+#[cfg(feature = "xml")]
+fn test_limits_xml() {
+    ensure_logger();
+    let xml = "<doc><a><b><c>deep</c></b></a><d k=\"v\">text</d></doc>";
+    let limits = ParseLimits { max_depth: 3, ..ParseLimits::default() };
+    let err = parse_xml_limited(xml.as_bytes(), &limits).unwrap_err();
+    assert_eq!(Limit::Depth, err.limit().unwrap().limit());
+
+    let limits = ParseLimits { truncate: true, ..limits };
+    let doc = parse_xml_limited(xml.as_bytes(), &limits).unwrap();
+    assert_eq!(
+        "<doc><a><b></b></a><d k=\"v\">text</d></doc>",
+        doc.to_string()
+    );
+
+    let limits = ParseLimits {
+        max_nodes: 4,
+        max_text_bytes: 2,
+        truncate: true,
+        ..ParseLimits::default()
+    };
+    let doc = parse_xml_limited(xml.as_bytes(), &limits).unwrap();
+    assert_eq!(4, doc.len());
+    assert_eq!("<doc><a><b></b></a></doc>", doc.to_string());
+
+    let err = parse_xml_limited(b"<a><b></a>", &limits).unwrap_err();
+    assert!(err.limit().is_none());
+}
+
+#[test]
+#[cfg(feature = "xml")]
+fn test_limits_xml_lenient() {
+    ensure_logger();
+    let xml = "<doc><a><b><c>deep</a><d k=v>text &amp; more</d></doc>";
+    let limits = ParseLimits { max_depth: 3, ..ParseLimits::default() };
+    let opts = xml::XmlParseOptions {
+        limits: Some(limits.clone()),
+        ..xml::XmlParseOptions::default()
+    };
+    let err = xml::parse_utf8_lenient_with_options(xml.as_bytes(), &opts)
+        .unwrap_err();
+    assert_eq!(Limit::Depth, err.limit().unwrap().limit());
+
+    // Content of dropped elements is also dropped, and they are closed via
+    // the usual recovery.
+    let opts = xml::XmlParseOptions {
+        limits: Some(ParseLimits { truncate: true, ..limits }),
+        ..xml::XmlParseOptions::default()
+    };
+    let (doc, errors) =
+        xml::parse_utf8_lenient_with_options(xml.as_bytes(), &opts).unwrap();
+    assert_eq!(
+        "<doc><a><b></b></a><d k=\"v\">text &amp; more</d></doc>",
+        doc.to_string()
+    );
+    assert_eq!(3, errors.len());
+
+    let opts = xml::XmlParseOptions {
+        limits: Some(ParseLimits {
+            max_nodes: 4,
+            max_text_bytes: 6,
+            truncate: true,
+            ..ParseLimits::default()
+        }),
+        ..xml::XmlParseOptions::default()
+    };
+    let (doc, _) =
+        xml::parse_utf8_lenient_with_options(b"<a>text<b>more</b></a>", &opts)
+            .unwrap();
+    assert_eq!(4, doc.len());
+    assert_eq!("<a>text<b></b></a>", doc.to_string());
+}
+
+#[test]
+#[cfg(feature = "xml")]
+fn test_limits_xml_buffered() {
+    ensure_logger();
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let xml = "<a>".repeat(100);
+    let mut reader = ShortRead(xml.as_bytes());
+    let opts = xml::XmlParseOptions {
+        limits: Some(ParseLimits { max_depth: 10, ..ParseLimits::default() }),
+        ..xml::XmlParseOptions::default()
+    };
+    let err = xml::parse_buffered_with_options(eh, &mut reader, &opts)
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    let xe = err.get_ref().unwrap().downcast_ref::<xml::XmlError>().unwrap();
+    assert_eq!(Limit::Depth, xe.limit().unwrap().limit());
+}
+
+// Push the given bytes to a new `HtmlPushParser`, in random sized chunks.
+fn push_parse(hint: crate::SharedEncodingHint, bytes: &[u8]) -> Document {
+    let mut rng = rand::thread_rng();
+    let mut parser = html::HtmlPushParser::new(hint);
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let n = rng.gen_range(0, std::cmp::min(rest.len(), 3000) + 1);
+        parser.feed(&rest[..n]);
+        rest = &rest[n..];
+    }
+    parser.finish().unwrap()
+}
+
+fn sample_bytes(fname: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    sample_file(fname).read_to_end(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn test_push_parser_shiftjis_meta() {
+    ensure_logger();
+    let bytes = sample_bytes("iro0094_shiftjis_meta.html");
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let doc = push_parse(eh.clone(), &bytes);
+    assert_eq!(enc::SHIFT_JIS, eh.borrow().top().unwrap());
+    assert_eq!(0, eh.borrow().errors());
+
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let expected = html::parse_buffered(eh, &mut &bytes[..]).unwrap();
+    assert_eq!(expected.to_string(), doc.to_string());
+}
+
+#[test]
+fn test_push_parser_utf16le_bom() {
+    ensure_logger();
+    let bytes = sample_bytes("documento_utf16le_bom.html");
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut parser = html::HtmlPushParser::new(eh.clone());
+    for b in bytes.chunks(1) {
+        parser.feed(b);
+    }
+    let doc = parser.finish().unwrap();
+    assert_eq!(enc::UTF_16LE, eh.borrow().top().unwrap());
+    let root = doc.root_element_ref().expect("root");
+    let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
+    assert_eq!("¿De donde eres tú?", body.text().unwrap().as_ref().trim());
+}
+
+#[test]
+fn test_push_parser_large() {
+    ensure_logger();
+    let bytes = sample_bytes("github-dekellum.html");
+    assert!(bytes.len() > crate::INITIAL_BUFFER_SIZE as usize);
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let doc = push_parse(eh, &bytes);
+    assert_eq!(html::parse_utf8(&bytes).to_string(), doc.to_string());
+}
+
+#[test]
+fn test_push_parser_empty() {
+    ensure_logger();
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut parser = html::HtmlPushParser::new(eh);
+    parser.feed(b"");
+    let doc = parser.finish().unwrap();
+    assert_eq!("<html><head></head><body></body></html>", doc.to_string());
+}
+
+// An in-memory `AsyncRead` returning short reads, and pending every other
+// read.
+#[cfg(feature = "async")]
+struct ShortAsyncRead<'a> {
+    bytes: &'a [u8],
+    pending: bool,
+}
+
+#[cfg(feature = "async")]
+impl<'a> tokio::io::AsyncRead for ShortAsyncRead<'a> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>)
+        -> std::task::Poll<io::Result<()>>
+    {
+        self.pending = !self.pending;
+        if self.pending {
+            cx.waker().wake_by_ref();
+            return std::task::Poll::Pending;
+        }
+        let max = std::cmp::min(buf.remaining(), self.bytes.len());
+        let n = rand::thread_rng().gen_range(0, max + 1);
+        let n = if n == 0 { max } else { n };
+        buf.put_slice(&self.bytes[..n]);
+        self.bytes = &self.bytes[n..];
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+// Minimal executor, polling the given future until ready.
+#[cfg(feature = "async")]
+fn block_on<F: std::future::Future>(f: F) -> F::Output {
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    fn raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker { raw_waker() }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable =
+            RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+
+    let waker = unsafe { Waker::from_raw(raw_waker()) };
+    let mut cx = Context::from_waker(&waker);
+    let mut f = Box::pin(f);
+    loop {
+        if let Poll::Ready(out) = f.as_mut().poll(&mut cx) {
+            return out;
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "async")]
+fn test_async_shiftjis_meta() {
+    ensure_logger();
+    let bytes = sample_bytes("iro0094_shiftjis_meta.html");
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = ShortAsyncRead { bytes: &bytes, pending: false };
+    let doc = block_on(html::parse_buffered_async(eh.clone(), &mut reader))
+        .unwrap();
+    assert_eq!(enc::SHIFT_JIS, eh.borrow().top().unwrap());
+    assert_eq!(0, eh.borrow().errors());
+
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let expected = html::parse_buffered(eh, &mut &bytes[..]).unwrap();
+    assert_eq!(expected.to_string(), doc.to_string());
+}
+
+#[test]
+#[cfg(feature = "async")]
+fn test_async_utf16le_bom() {
+    ensure_logger();
+    let bytes = sample_bytes("documento_utf16le_bom.html");
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = ShortAsyncRead { bytes: &bytes, pending: false };
+    let doc = block_on(html::parse_buffered_async(eh.clone(), &mut reader))
+        .unwrap();
+    assert_eq!(enc::UTF_16LE, eh.borrow().top().unwrap());
+    let root = doc.root_element_ref().expect("root");
+    let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
+    assert_eq!("¿De donde eres tú?", body.text().unwrap().as_ref().trim());
+}
+
+#[test]
+#[cfg(all(feature = "async", feature = "xml"))]
+fn test_async_xml_windows1251_decl() {
+    ensure_logger();
+    let bytes = sample_bytes("feed_windows1251_decl.xml");
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = ShortAsyncRead { bytes: &bytes, pending: false };
+    let doc = block_on(xml::parse_buffered_async(eh.clone(), &mut reader))
+        .unwrap();
+    let title = doc.document_node_ref()
+        .find(|n| n.is_elem("title"))
+        .expect("title");
+    assert_eq!("Новости дня", title.text().unwrap().as_ref());
+    assert_eq!(enc::WINDOWS_1251, eh.borrow().top().unwrap());
+}
+
+#[test]
+#[cfg(all(feature = "async", feature = "xml"))]
+fn test_async_xml_limits() {
+    ensure_logger();
+    let opts = xml::XmlParseOptions {
+        limits: Some(ParseLimits {
+            max_nodes: 10,
+            max_attributes: 1,
+            max_attr_value_len: 10,
+            max_text_bytes: 10,
+            truncate: true,
+            ..ParseLimits::default()
+        }),
+        ..xml::XmlParseOptions::default()
+    };
+    let xml = "<a><b k=\"v\">text</b></a>";
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = ShortAsyncRead { bytes: xml.as_bytes(), pending: false };
+    let doc = block_on(
+        xml::parse_buffered_async_with_options(eh, &mut reader, &opts)
+    ).unwrap();
+    assert_eq!(xml, doc.to_string());
+
+    // Input is limited before parsing, regardless of truncate
+    let xml = format!("<a>{}</a>", "x".repeat(20_000));
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = ShortAsyncRead { bytes: xml.as_bytes(), pending: false };
+    let err = block_on(
+        xml::parse_buffered_async_with_options(eh, &mut reader, &opts)
+    ).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+}
+
+#[test]
+fn test_node_mut() {
+    ensure_logger();
+    let mut doc = html::parse_utf8_fragment(
+        b"<div><p>one</p><p>two</p><p>three</p></div>"
+    );
+    let root = doc.root_element().unwrap();
+    let ps = doc.children(root).collect::<Vec<_>>();
+
+    doc.node_mut(ps[0])
+        .set_attr("class", "first")
+        .insert_before(Node::new_elem(Element::new("span")))
+        .insert_after(Node::new_text("!"));
+    doc.node_mut(ps[1])
+        .prepend(Node::new_text("[ "))
+        .append(Node::new_text(" ]"))
+        .wrap_in(Element::new("blockquote"));
+    let nid = doc.node_mut(ps[2])
+        .replace_with(Node::new_elem(Element::new("pre")))
+        .set_text("3")
+        .id();
+    assert_ne!(nid, ps[2]);
+    assert_eq!(
+        "<div><span></span><p class=\"first\">one</p>!\
+         <blockquote><p>[ two ]</p></blockquote>\
+         <pre>3</pre></div>",
+        doc.to_string()
+    );
+
+    let bq = doc.node_mut(ps[1]).as_ref().parent().unwrap().id();
+    let data = doc.node_mut(bq).unwrap();
+    assert!(data.is_elem("blockquote"));
+    let data = doc.node_mut(ps[0]).remove();
+    assert!(data.is_elem(t::P));
+    let span = doc.children(root).next().unwrap();
+    doc.node_mut(span).remove();
+    let text = doc.children(root).next().unwrap();
+    doc.node_mut(text).set_text("?");
+    assert_eq!(
+        "<div>?<p>[ two ]</p><pre>3</pre></div>",
+        doc.to_string()
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_node_mut_set_attr_non_element() {
+    ensure_logger();
+    let mut doc = Document::new();
+    let tid = doc.append_child(
+        Document::DOCUMENT_NODE_ID,
+        Node::new_text("text")
+    );
+    doc.node_mut(tid).set_attr("class", "a");
+}
+
+#[test]
+fn test_template_contents() {
+    ensure_logger();
+    let mut doc = html::parse_utf8_fragment(
+        b"<div>\
+            <template id=\"t\"><p>inert</p>\
+              <template><b>nested</b></template>\
+              <script>alert(1)</script>\
+            </template>\
+            <p>live</p>\
+          </div>"
+    );
+    assert_eq!(
+        "<div><template id=\"t\"></template><p>live</p></div>",
+        doc.to_string()
+    );
+    assert_eq!(6, doc.len());
+    assert_eq!(5, doc.nodes().count());
+
+    let tid = doc.nodes().find(|&id| doc[id].is_elem(t::TEMPLATE)).unwrap();
+    assert!(doc[tid].first_child.is_none());
+    let contents = doc.template_contents(tid).unwrap();
+    assert_eq!(
+        "<p>inert</p><template></template><script>alert(1)</script>",
+        contents.to_string()
+    );
+    let nested = contents.nodes()
+        .find(|&id| contents[id].is_elem(t::TEMPLATE))
+        .unwrap();
+    assert_eq!(
+        "<b>nested</b>",
+        contents.template_contents(nested).unwrap().to_string()
+    );
+
+    // Filters don't apply to the contents
+    doc.filter(|_p: NodeRef<'_>, data: &mut NodeData| {
+        if data.is_elem(t::SCRIPT) { Action::Detach } else { Action::Continue }
+    });
+    doc.filter(filter::text_normalize);
+    let contents = doc.template_contents(tid).unwrap();
+    assert!(contents.to_string().contains("<script>alert(1)</script>"));
+
+    // Contents are retained by deep_clone, detach and compact
+    let cdoc = doc.deep_clone(Document::DOCUMENT_NODE_ID);
+    let ctid = cdoc.nodes().find(|&id| cdoc[id].is_elem(t::TEMPLATE)).unwrap();
+    assert!(cdoc.template_contents(ctid).is_some());
+    let det = doc.detach(tid);
+    doc.compact();
+    let root = det.root_element().unwrap();
+    let contents = det.template_contents(root).unwrap();
+    assert!(contents.to_string().starts_with("<p>"));
+    assert!(doc.template_contents(tid).is_none());
+}
+
+#[test]
+fn test_template_contents_set() {
+    ensure_logger();
+    let mut doc = html::parse_utf8(b"<template><p>1</p></template>");
+    let tid = doc.nodes().find(|&id| doc[id].is_elem(t::TEMPLATE)).unwrap();
+    let contents = doc.template_contents_mut(tid).unwrap();
+    let p = contents.root_element().unwrap();
+    contents.append_child(p, Node::new_text("!"));
+    let prior = doc.set_template_contents(tid, None).unwrap();
+    assert_eq!("<p>1!</p>", prior.to_string());
+    assert!(doc.template_contents(tid).is_none());
+    assert!(doc.set_template_contents(tid, Some(prior)).is_none());
+    let contents = doc.document_node_ref().find(|n| n.is_elem(t::TEMPLATE))
+        .and_then(|n| n.template_contents());
+    assert_eq!("<p>1!</p>", contents.unwrap().to_string());
+    assert_eq!(
+        "<html><head><template></template></head><body></body></html>",
+        doc.to_string()
+    );
+}
+
+#[test]
+fn test_report_shiftjis_meta() {
+    ensure_logger();
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = ShortRead(sample_file("iro0094_shiftjis_meta.html"));
+    let (_doc, report) = html::parse_buffered_with_report(
+        eh, &mut reader, &html::HtmlParseOptions::default()
+    ).unwrap();
+    assert_eq!(enc::SHIFT_JIS, report.encoding());
+    assert_eq!(HintSource::Meta, report.source());
+    assert!(report.confidence() >= HTML_META_CONF);
+    assert_eq!(HintSource::Default, report.hints()[0].source);
+    assert!(report.reparsed());
+    assert!(report.prior_replacements().unwrap() > 0);
+    assert_eq!(0, report.replacements());
+}
+
+#[test]
+fn test_report_utf16le_bom() {
+    ensure_logger();
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = ShortRead(sample_file("documento_utf16le_bom.html"));
+    let (_doc, report) = html::parse_buffered_with_report(
+        eh, &mut reader, &html::HtmlParseOptions::default()
+    ).unwrap();
+    assert_eq!(enc::UTF_16LE, report.encoding());
+    assert_eq!(HintSource::Bom, report.source());
+    assert!(report.reparsed());
+    assert_eq!(0, report.replacements());
+}
+
+#[test]
+fn test_report_errors() {
+    ensure_logger();
+    let (eh, _) = EncodingHint::shared_with_content_type(
+        enc::WINDOWS_1252, "text/html; charset=utf-8"
+    );
+    let mut reader = &b"<!DOCTYPE html>\n<p>cafe</div></p>"[..];
+    let (doc, report) = html::parse_buffered_with_report(
+        eh, &mut reader, &html::HtmlParseOptions::default()
+    ).unwrap();
+    assert_eq!(enc::UTF_8, report.encoding());
+    assert_eq!(HintSource::HttpContentType, report.source());
+    assert!(!report.reparsed());
+    assert_eq!(0, report.replacements());
+    let errors = report.parse_errors();
+    assert_eq!(1, errors.count(), "{:?}", errors);
+    assert_eq!(2, errors.errors()[0].line());
+    assert_eq!(
+        "<!DOCTYPE html><html><head></head><body><p>cafe</p></body></html>",
+        doc.to_string()
+    );
+}
+
+#[test]
+fn test_report_options() {
+    ensure_logger();
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = ShortRead(sample_file("iro0094_shiftjis_meta.html"));
+    let errors = html::ParseErrors::shared(1);
+    let opts = html::HtmlParseOptions {
+        errors: Some(errors.clone()),
+        spans: true,
+        ..html::HtmlParseOptions::default()
+    };
+    let (doc, report) = html::parse_buffered_with_report(eh, &mut reader, &opts)
+        .unwrap();
+    assert!(report.reparsed());
+    assert_eq!(errors.borrow().count(), report.parse_errors().count());
+    assert_eq!(1, report.parse_errors().errors().len());
+    assert!(doc.root_element_ref().unwrap().source_span().is_some());
+}
+
+#[test]
+fn test_documento_utf8() {
+    ensure_logger();
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = ShortRead(sample_file("documento_utf8.html"));
+    let doc = html::parse_buffered(eh, &mut reader).unwrap();
+    let root = doc.root_element_ref().expect("root");
+    let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
+    assert_eq!("¿De donde eres tú?", body.text().unwrap().as_ref().trim());
+}
+
+#[test]
+fn test_documento_utf8_bom() {
+    ensure_logger();
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = ShortRead(sample_file("documento_utf8_bom.html"));
+    let doc = html::parse_buffered(eh, &mut reader).unwrap();
+    let root = doc.root_element_ref().expect("root");
+    let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
+    assert_eq!("¿De donde eres tú?", body.text().unwrap().as_ref().trim());
+}
+
+#[test]
+fn test_documento_utf16be_bom() {
+    ensure_logger();
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = ShortRead(sample_file("documento_utf16be_bom.html"));
+    let doc = html::parse_buffered(eh, &mut reader).unwrap();
+    let root = doc.root_element_ref().expect("root");
+    let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
+    assert_eq!("¿De donde eres tú?", body.text().unwrap().as_ref().trim());
+}
+
+#[test]
+fn test_documento_utf16be_meta_utf16le() {
+    ensure_logger();
+    let eh = EncodingHint::shared_with_hint(enc::UTF_16BE, HTTP_CTYPE_CONF);
+    // The contained meta should be ignored, since, if it was correct, we
+    // couldn't read it from the initial UTF-16BE encoding!
+    let mut reader = ShortRead(sample_file("documento_utf16be_meta_utf16le.html"));
+    let doc = html::parse_buffered(eh, &mut reader).unwrap();
+    let root = doc.root_element_ref().expect("root");
+    let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
+    assert_eq!("¿De donde eres tú?", body.text().unwrap().as_ref().trim());
+}
+
+#[test]
+fn test_documento_utf16le_bom() {
+    ensure_logger();
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = ShortRead(sample_file("documento_utf16le_bom.html"));
+    let doc = html::parse_buffered(eh, &mut reader).unwrap();
+    let root = doc.root_element_ref().expect("root");
+    let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
+    assert_eq!("¿De donde eres tú?", body.text().unwrap().as_ref().trim());
+}
+
+#[test]
+fn test_documento_utf16le() {
+    ensure_logger();
+    let eh = EncodingHint::shared_default(enc::UTF_16LE);
+    let mut reader = ShortRead(sample_file("documento_utf16le.html"));
+    let doc = html::parse_buffered(eh, &mut reader).unwrap();
+    let root = doc.root_element_ref().expect("root");
+    let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
+    assert_eq!("¿De donde eres tú?", body.text().unwrap().as_ref().trim());
+}
+
+#[test]
+fn test_documento_utf16le_meta_utf8() {
+    ensure_logger();
+    let eh = EncodingHint::shared_with_hint(enc::UTF_16LE, HTTP_CTYPE_CONF);
+    // The contained meta should be ignored, since, if it was correct, we
+    // couldn't read it from the initial UTF-16 encoding!
+    let mut reader = ShortRead(sample_file("documento_utf16le_meta_utf8.html"));
     let doc = html::parse_buffered(eh, &mut reader).unwrap();
     let root = doc.root_element_ref().expect("root");
     let body = root.find_child(|n| n.is_elem(t::BODY)).expect("body");
@@ -1096,3 +2765,512 @@ fn test_russez_windows1251_meta() {
         "txt: {}", body.text().unwrap().as_ref()
     );
 }
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_serde_round_trip() {
+    ensure_logger();
+    let mut doc = html::parse_utf8_with_options(
+        b"<!DOCTYPE html>\n\
+          <html><head><title>t</title></head>\n\
+          <body class=\"a b\"><!--c-->\n\
+          <p>one<br>two</p>\n\
+          <template><i>inert</i></template>\n\
+          <svg><xlink:a xlink:href=\"#x\"/></svg>\n\
+          </body></html>",
+        &spans_opts()
+    ).unwrap();
+    let p = doc.nodes().find(|&id| doc[id].is_elem(t::P)).unwrap();
+    doc.unlink(p);
+
+    let json = serde_json::to_string(&doc).unwrap();
+    let rdoc: Document = serde_json::from_str(&json).unwrap();
+    assert_eq!(doc.len(), rdoc.len());
+    assert_eq!(doc.to_string(), rdoc.to_string());
+    assert_eq!(doc.quirks_mode(), rdoc.quirks_mode());
+    for id in doc.nodes() {
+        assert_eq!(doc.source_span(id), rdoc.source_span(id));
+    }
+    assert_eq!(json, serde_json::to_string(&rdoc).unwrap());
+
+    let tid = rdoc.nodes().find(|&id| rdoc[id].is_elem(t::TEMPLATE)).unwrap();
+    let contents = rdoc.template_contents(tid);
+    assert_eq!("<i>inert</i>", contents.unwrap().to_string());
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_serde_bincode_round_trip() {
+    ensure_logger();
+    let mut doc = html::parse_utf8(
+        b"<!DOCTYPE html>\n\
+          <body class=\"a b\"><!--c-->\n\
+          <p>one<br>two</p>\n\
+          <template><i>inert</i></template>\n\
+          <svg><xlink:a xlink:href=\"#x\"/></svg>\n\
+          </body>"
+    );
+    let p = doc.nodes().find(|&id| doc[id].is_elem(t::P)).unwrap();
+    doc.unlink(p);
+
+    let bin = bincode::serialize(&doc).unwrap();
+    let rdoc: Document = bincode::deserialize(&bin).unwrap();
+    assert_eq!(doc.len(), rdoc.len());
+    assert_eq!(doc.to_string(), rdoc.to_string());
+    assert_eq!(doc.quirks_mode(), rdoc.quirks_mode());
+    assert_eq!(bin, bincode::serialize(&rdoc).unwrap());
+
+    let tid = rdoc.nodes().find(|&id| rdoc[id].is_elem(t::TEMPLATE)).unwrap();
+    let contents = rdoc.template_contents(tid);
+    assert_eq!("<i>inert</i>", contents.unwrap().to_string());
+
+    let doc = html::parse_utf8_with_options(
+        b"<p>one<br>two</p>",
+        &spans_opts()
+    ).unwrap();
+    let bin = bincode::serialize(&doc).unwrap();
+    let rdoc: Document = bincode::deserialize(&bin).unwrap();
+    let p = rdoc.nodes().find(|&id| rdoc[id].is_elem(t::P)).unwrap();
+    assert!(rdoc.source_span(p).is_some());
+    for id in doc.nodes() {
+        assert_eq!(doc.source_span(id), rdoc.source_span(id));
+    }
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_serde_invalid_links() {
+    ensure_logger();
+    let doc = html::parse_utf8_fragment(b"<p>one</p><p>two</p>");
+    let json = serde_json::to_string(&doc).unwrap();
+    assert!(serde_json::from_str::<Document>(&json).is_ok());
+
+    for (from, to) in &[
+        ("\"parent\":3", "\"parent\":9"),
+        ("\"parent\":3", "\"parent\":0"),
+        ("\"next_sibling\":6", "\"next_sibling\":4"),
+        ("\"data\":\"Document\"", "\"data\":\"Hole\""),
+    ] {
+        assert!(json.contains(from), "{} in {}", from, json);
+        let bad = json.replacen(from, to, 1);
+        let res = serde_json::from_str::<Document>(&bad);
+        assert!(res.is_err(), "{}", bad);
+        debug!("{}", res.unwrap_err());
+    }
+}
+
+// Deserialize doc via JSON, after setting the given links, by node ID and
+// link field name.
+#[cfg(feature = "serde")]
+fn relinked(doc: &Document, links: &[(u32, &str, Option<u32>)])
+    -> Result<Document, serde_json::Error>
+{
+    let mut value = serde_json::to_value(doc).unwrap();
+    for &(id, link, to) in links {
+        value["nodes"][id as usize - 1][link] = serde_json::json!(to);
+    }
+    serde_json::from_value(value)
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_serde_invalid_tree() {
+    ensure_logger();
+    let check = |html: &[u8], links: &[(u32, &str, Option<u32>)], msg| {
+        let doc = html::parse_utf8_fragment(html);
+        assert!(relinked(&doc, &[]).is_ok());
+        let err = relinked(&doc, links).unwrap_err().to_string();
+        assert!(err.contains(msg), "{}", err);
+    };
+
+    // Two nodes as each other's parent and only child, otherwise unlinked
+    check(b"<p></p><b></b>", &[
+        (3, "first_child", None),
+        (3, "last_child", None),
+        (4, "parent", Some(5)),
+        (4, "next_sibling", None),
+        (4, "first_child", Some(5)),
+        (4, "last_child", Some(5)),
+        (5, "parent", Some(4)),
+        (5, "prev_sibling", None),
+        (5, "first_child", Some(4)),
+        (5, "last_child", Some(4)),
+    ], "not reachable");
+
+    // Sibling chain from first_child doesn't reach last_child
+    check(b"<p></p><b></b><i></i>", &[
+        (4, "next_sibling", None),
+        (5, "prev_sibling", None),
+    ], "inconsistent children");
+
+    // A text node as parent
+    check(b"<p>one</p><b></b>", &[
+        (3, "last_child", Some(4)),
+        (4, "next_sibling", None),
+        (5, "first_child", Some(6)),
+        (5, "last_child", Some(6)),
+        (6, "parent", Some(5)),
+        (6, "prev_sibling", None),
+    ], "can't have children");
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_serde_nested_json() {
+    ensure_logger();
+    let doc = html::parse_utf8(
+        b"<!DOCTYPE html><p id=x>one<!--c--><template><b>t</b></template></p>"
+    );
+    assert_eq!(
+        "[{\"doctype\":\"html\"},{\"tag\":\"html\",\"children\":[\
+          {\"tag\":\"head\"},{\"tag\":\"body\",\"children\":[\
+            {\"tag\":\"p\",\"attrs\":{\"id\":\"x\"},\"children\":[\
+              \"one\",{\"comment\":\"c\"},\
+              {\"tag\":\"template\",\"content\":[{\"tag\":\"b\",\
+                \"children\":[\"t\"]}]}\
+            ]}\
+          ]}\
+        ]}]",
+        doc.to_json()
+    );
+    let root = doc.root_element_ref().unwrap();
+    let p = root.find(|n| n.is_elem(t::P)).unwrap();
+    assert_eq!(
+        serde_json::to_string(&p).unwrap(),
+        p.to_json()
+    );
+}
+
+#[test]
+fn test_snapshot_round_trip() {
+    ensure_logger();
+    let mut doc = html::parse_utf8_with_options(
+        b"<!DOCTYPE html>\n\
+          <html><head><title>t</title></head>\n\
+          <body class=\"a b\"><!--c-->\n\
+          <p>one<br>two</p>\n\
+          <template><i class=\"a\">inert</i></template>\n\
+          <svg><a xlink:href=\"#x\"/></svg>\n\
+          </body></html>",
+        &spans_opts()
+    ).unwrap();
+    let p = doc.nodes().find(|&id| doc[id].is_elem(t::P)).unwrap();
+    doc.unlink(p);
+
+    let mut snap = Vec::new();
+    doc.write_snapshot(&mut snap).unwrap();
+    let rdoc = Document::read_snapshot(&mut &snap[..]).unwrap();
+    assert_eq!(doc.len(), rdoc.len());
+    assert_eq!(doc.to_string(), rdoc.to_string());
+    assert_eq!(doc.quirks_mode(), rdoc.quirks_mode());
+    for id in doc.nodes() {
+        assert_eq!(doc.source_span(id), rdoc.source_span(id));
+    }
+    let svg_a = rdoc.nodes()
+        .find(|&id| rdoc[id].is_elem(t::A))
+        .unwrap();
+    let attr = &rdoc[svg_a].as_element().unwrap().attrs[0];
+    assert_eq!("xlink", attr.name.prefix.as_ref().unwrap().as_ref());
+    assert_eq!("http://www.w3.org/1999/xlink", &*attr.name.ns);
+
+    let tid = rdoc.nodes().find(|&id| rdoc[id].is_elem(t::TEMPLATE)).unwrap();
+    let contents = rdoc.template_contents(tid);
+    assert_eq!("<i class=\"a\">inert</i>", contents.unwrap().to_string());
+
+    let mut snap2 = Vec::new();
+    rdoc.write_snapshot(&mut snap2).unwrap();
+    assert_eq!(snap, snap2);
+}
+
+#[test]
+fn test_snapshot_large_sample() {
+    ensure_logger();
+    let eh = EncodingHint::shared_default(enc::UTF_8);
+    let mut reader = sample_file("github-dekellum.html");
+    let doc = html::parse_buffered(eh, &mut reader).unwrap();
+    let mut snap = Vec::new();
+    doc.write_snapshot(&mut snap).unwrap();
+    let html = doc.to_string();
+    debug!("snapshot {} bytes, html {} bytes", snap.len(), html.len());
+    let rdoc = Document::read_snapshot(&mut ShortRead(&snap[..])).unwrap();
+    assert_eq!(html, rdoc.to_string());
+}
+
+#[test]
+fn test_snapshot_invalid() {
+    ensure_logger();
+    let mut snap = Vec::new();
+    Document::new().write_snapshot(&mut snap).unwrap();
+    assert!(Document::read_snapshot(&mut &snap[..]).is_ok());
+
+    let err = Document::read_snapshot(&mut &b"<html>"[..]).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+    // A later format version
+    let mut bad = snap.clone();
+    bad[4] = 0xff;
+    let err = Document::read_snapshot(&mut &bad[..]).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    assert!(err.to_string().contains("version"), "{}", err);
+
+    // Truncated
+    let err = Document::read_snapshot(&mut &snap[..snap.len() - 1])
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+
+    // Document node as its own parent
+    let mut bad = snap.clone();
+    assert_eq!(0, bad[9]);
+    bad[9] = 1;
+    let err = Document::read_snapshot(&mut &bad[..]).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+    // Link out of range
+    let mut bad = snap.clone();
+    bad[10] = 7;
+    let err = Document::read_snapshot(&mut &bad[..]).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+}
+
+fn assert_patches(old: &Document, new: &Document) -> usize {
+    let script = diff::diff(old, new);
+    let mut patched = old.bulk_clone();
+    script.apply(&mut patched);
+    assert_eq!(new.to_string(), patched.to_string());
+    assert!(new.document_node_ref()
+        .structurally_eq(&patched.document_node_ref()));
+
+    let mut patched = old.bulk_clone();
+    diff::patch(&mut patched, new);
+    assert_eq!(new.to_string(), patched.to_string());
+    assert!(new.document_node_ref()
+        .structurally_eq(&patched.document_node_ref()));
+    script.len()
+}
+
+#[test]
+fn test_diff_identical() {
+    ensure_logger();
+    let old = html::parse_utf8(
+        "<p>one <i>two</i></p><!--c--><p class=a>three</p>".as_bytes()
+    );
+    let new = old.deep_clone(Document::DOCUMENT_NODE_ID);
+    let script = diff::diff(&old, &new);
+    assert!(script.is_empty(), "{:?}", script.edits());
+    assert_eq!(0, assert_patches(&old, &new));
+}
+
+#[test]
+fn test_diff_edits() {
+    ensure_logger();
+    let old = html::parse_utf8_fragment(
+        b"<ul><li>one</li><li>two</li><li>three</li></ul>\
+          <p class=a>para <b>bold</b></p><div>gone</div>"
+    );
+    let new = html::parse_utf8_fragment(
+        b"<ul><li>three</li><li>one</li><li>two</li><li>four</li></ul>\
+          <p class=b>para! <b>bold</b></p>"
+    );
+    assert_patches(&old, &new);
+
+    let script = diff::diff(&old, &new);
+    let edits = script.edits();
+    debug!("edits: {:#?}", edits);
+
+    let moved = edits.iter().filter_map(|e| match *e {
+        Edit::Move { node, to, index, .. } => Some((node, to, index)),
+        _ => None,
+    }).collect::<Vec<_>>();
+    assert_eq!(1, moved.len());
+    let (node, to, index) = moved[0];
+    assert_eq!("three", node.text().unwrap().as_ref());
+    assert_eq!(node.text(), to.text());
+    assert_eq!(0, index);
+
+    assert!(edits.iter().any(|e| match *e {
+        Edit::Insert { node, index, .. } => {
+            node.is_elem(t::LI) &&
+                node.text().unwrap().as_ref() == "four" &&
+                index == 3
+        }
+        _ => false,
+    }));
+    assert!(edits.iter().any(|e| match *e {
+        Edit::Delete { node } => node.is_elem(t::DIV),
+        _ => false,
+    }));
+    assert!(edits.iter().any(|e| match *e {
+        Edit::Text { old, new } => {
+            old.as_text().unwrap().as_ref() == "para " &&
+                new.as_text().unwrap().as_ref() == "para! "
+        }
+        _ => false,
+    }));
+    assert!(edits.iter().any(|e| match *e {
+        Edit::Attrs { old, new } => {
+            old.attr(a::CLASS).unwrap().as_ref() == "a" &&
+                new.attr(a::CLASS).unwrap().as_ref() == "b"
+        }
+        _ => false,
+    }));
+}
+
+#[test]
+fn test_diff_move_to_new_parent() {
+    ensure_logger();
+    let old = html::parse_utf8_fragment(
+        b"<div><p>a long paragraph</p><p>another</p></div><hr>"
+    );
+    let new = html::parse_utf8_fragment(
+        b"<div><p>another</p></div>\
+          <section><hr><p>a long paragraph</p></section>"
+    );
+    assert_patches(&old, &new);
+
+    let script = diff::diff(&old, &new);
+    assert!(script.edits().iter().any(|e| match *e {
+        Edit::Move { node, parent, .. } => {
+            node.is_elem(t::P) && parent.is_elem(t::SECTION)
+        }
+        _ => false,
+    }), "{:?}", script.edits());
+}
+
+#[test]
+fn test_diff_templates() {
+    ensure_logger();
+    let old = html::parse_utf8_fragment(
+        b"<div><template><b>one</b></template></div><p>para</p>"
+    );
+    // Changed contents, and new templates inserted directly and as
+    // descendants of an inserted node.
+    let new = html::parse_utf8_fragment(
+        b"<div><template><b>two</b></template></div>\
+          <template><i>new</i></template><p>para</p>\
+          <section><template>nested</template></section>"
+    );
+    assert_patches(&old, &new);
+}
+
+#[test]
+fn test_diff_random_edits() {
+    ensure_logger();
+    let old = html::parse_utf8(
+        "<h1>Title</h1>\
+         <p>first <i>one</i> text</p>\
+         <ol><li>a</li><li>b <b>c</b></li><li>d</li></ol>\
+         <!--note--><p id=x>last <a href=foo>link</a></p>".as_bytes()
+    );
+    let mut rng = rand::thread_rng();
+    for _ in 0..50 {
+        let mut new = old.bulk_clone();
+        for _ in 0..rng.gen_range(1, 5) {
+            let ids = new.nodes()
+                .filter(|&id| new[id].parent.is_some())
+                .collect::<Vec<_>>();
+            if ids.is_empty() {
+                break;
+            }
+            let id = ids[rng.gen_range(0, ids.len())];
+            match rng.gen_range(0, 4) {
+                0 => { new.unlink(id); }
+                1 => {
+                    new.insert_before_sibling(id, Node::new_text("new"));
+                }
+                2 => {
+                    let parent = new[id].parent.unwrap();
+                    if let Some(first) = new[parent].first_child {
+                        if first != id {
+                            let span = new.insert_before_sibling(
+                                first,
+                                Node::new_elem(Element::new("span")));
+                            let node = new.detach(id);
+                            let root = node.children(Document::DOCUMENT_NODE_ID)
+                                .next().unwrap();
+                            new.append_deep_clone(span, &node, root);
+                        }
+                    }
+                }
+                _ => {
+                    if new[id].as_element().is_some() {
+                        new.node_mut(id).set_attr("class", "changed");
+                    } else if new[id].as_text().is_some() {
+                        new.node_mut(id).set_text("changed");
+                    }
+                }
+            }
+        }
+        new.compact();
+        assert_patches(&old, &new);
+    }
+}
+
+#[test]
+fn test_structurally_eq() {
+    ensure_logger();
+    let doc = html::parse_utf8(
+        "<div><p class=a id=b>one <i>two</i></p><!--c-->three</div>"
+            .as_bytes()
+    );
+    // Differing arena layout, via an unlinked node and then compaction
+    let mut other = doc.bulk_clone();
+    let p = other.nodes().find(|&id| other[id].is_elem(t::P)).unwrap();
+    let i = other.children(p).nth(1).unwrap();
+    let text = other.insert_before_sibling(i, Node::new_text(""));
+    other.unlink(text);
+    assert!(doc.document_node_ref().structurally_eq(
+        &other.document_node_ref()
+    ));
+    other.compact();
+    let (a, b) = (doc.document_node_ref(), other.document_node_ref());
+    assert!(a.structurally_eq(&b));
+    assert_eq!(a.content_hash(), b.content_hash());
+    assert_ne!(a, b);
+
+    // Adjacent text nodes compare as concatenated
+    let mut split = doc.bulk_clone();
+    let t1 = split.nodes()
+        .find(|&id| split[id].as_text().map(|t| t.as_ref()) == Some("one "))
+        .unwrap();
+    split[t1].as_text_mut().unwrap().pop_back(2);
+    split.node_mut(t1).insert_after(Node::new_text("e "));
+    assert_eq!(doc.to_string(), split.to_string());
+    assert!(a.structurally_eq(&split.document_node_ref()));
+
+    let p = doc.nodes().find(|&id| doc[id].is_elem(t::P)).unwrap();
+    assert!(!a.structurally_eq(&NodeRef::new(&doc, p)));
+
+    let variant = html::parse_utf8(
+        "<div>\n<p id=b class=a>one <i>two</i></p><!--x-->three</div>"
+            .as_bytes()
+    );
+    let v = variant.document_node_ref();
+    assert!(!a.structurally_eq(&v));
+    assert_ne!(a.content_hash(), v.content_hash());
+
+    let mut opts = CompareOptions {
+        ignore_attr_order: true,
+        ignore_comments: true,
+        ..CompareOptions::default()
+    };
+    assert!(!a.structurally_eq_with(&v, &opts));
+    opts.ignore_whitespace_text = true;
+    assert!(a.structurally_eq_with(&v, &opts));
+    assert_eq!(a.content_hash_with(&opts), v.content_hash_with(&opts));
+
+    // Text either side of an ignored comment is merged
+    let merged = html::parse_utf8(
+        "<div><p class=a id=b>one <i>two</i></p>th<!--c-->ree</div>"
+            .as_bytes()
+    );
+    assert!(a.structurally_eq_with(&merged.document_node_ref(), &opts));
+}
+
+#[test]
+fn test_content_hash_stable() {
+    let doc = html::parse_utf8_fragment(b"<p class=a>one <i>two</i></p>");
+    let hash = doc.document_node_ref().content_hash();
+    assert_eq!(0x446c_fdd0_a3a1_84da, hash, "{:x}", hash);
+    let hash = Document::new().document_node_ref().content_hash();
+    assert_eq!(0x082f_1a07_b4e8_7f2c, hash, "{:x}", hash);
+}
@@ -11,54 +11,173 @@
 //!
 //! This module is enabled at build time via the _xml_ non-default feature.
 
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::fmt;
 use std::error::Error as StdError;
+use std::io;
+use std::rc::Rc;
 
+use encoding_rs as enc;
+use log::debug;
+use tendril::{fmt as form, TendrilSink};
+
+use xml_rs::common::Position;
 use xml_rs::reader::XmlEvent;
 use xml_rs::attribute::OwnedAttribute;
 
 use crate::dom::{
-    Attribute, Document, Element, Node, NodeData,
-    ProcessingInstruction, QualName, StrTendril
+    Attribute, Document, Element, Node, NodeData, NodeId,
+    ProcessingInstruction, QualName, SourcePos, SourceSpan, StrTendril
 };
 use crate::chars::is_all_ctrl_ws;
+use crate::decode::BufferedDecoder;
+use crate::dom::limits::{check_limits, Limiter, SharedLimitError};
+use crate::dom::span::LineIndex;
+use crate::{HintSource, LimitError, SharedEncodingHint, XML_DECL_CONF};
+
+mod lenient;
+mod options;
+
+pub use self::options::XmlParseOptions;
 
 /// Parse XML document from UTF-8 bytes in RAM.
 pub fn parse_utf8(utf8_bytes: &[u8]) -> Result<Document, XmlError> {
+    parse_utf8_with_options(utf8_bytes, &XmlParseOptions::default())
+}
+
+/// Parse XML document from UTF-8 bytes in RAM, with the given options.
+pub fn parse_utf8_with_options(utf8_bytes: &[u8], opts: &XmlParseOptions)
+    -> Result<Document, XmlError>
+{
+    if opts.spans {
+        // Invalid UTF-8 will fail the parse, so lossy is only for the index.
+        let source = String::from_utf8_lossy(utf8_bytes);
+        parse(utf8_bytes, opts, Some(&LineIndex::new(&source)))
+    } else {
+        parse(utf8_bytes, opts, None)
+    }
+}
+
+// Parse from the given UTF-8 text, with any spans including byte offsets via
+// lines.
+fn parse<R>(utf8: R, opts: &XmlParseOptions, lines: Option<&LineIndex<'_>>)
+    -> Result<Document, XmlError>
+    where R: io::Read
+{
+    let limits = opts.limits.as_ref();
+    let exceeded = SharedLimitError::default();
+    let mut limiter = limits.map(|l| Limiter::new(l, exceeded.clone()));
+
+    // Depth of an element being skipped due to limits
+    let mut skip = 0usize;
+
     let mut document = Document::new();
     let mut current = Document::DOCUMENT_NODE_ID;
     let mut ancestors = Vec::new();
-    for event in xml_rs::EventReader::new(utf8_bytes) {
-        match event.map_err(XmlError)? {
+
+    // Nodes which end at the start of the next event
+    let mut ending: Vec<NodeId> = Vec::new();
+    let mut start = None;
+
+    let mut reader = xml_rs::EventReader::new(utf8);
+    loop {
+        if limiter.as_ref().map_or(false, |l| l.stop()) {
+            break;
+        }
+        let event = reader.next().map_err(XmlError::parse)?;
+        if skip > 0 {
+            match event {
+                XmlEvent::StartElement { .. } => skip += 1,
+                XmlEvent::EndElement { .. } => skip -= 1,
+                XmlEvent::EndDocument => break,
+                _ => {}
+            }
+            continue;
+        }
+        let line = reader.position().row + 1;
+        if opts.spans {
+            let p = reader.position();
+            let pos = match lines {
+                Some(lines) => lines.pos(p.row, p.column),
+                None => SourcePos {
+                    line: line as u32,
+                    column: Some(p.column as u32 + 1),
+                    offset: None,
+                },
+            };
+            for id in ending.drain(..) {
+                document.set_source_end(id, pos);
+            }
+            start = Some(pos);
+        }
+        match event {
             XmlEvent::StartElement { name, attributes, .. } => {
+                let mut attrs = attributes
+                    .into_iter()
+                    .map(|OwnedAttribute { name, value }| {
+                        Attribute {
+                            name: convert_name(name),
+                            value: value.into()
+                        }
+                    })
+                    .collect();
+                if let Some(ref limiter) = limiter {
+                    if !limiter.allow_node(&document, line) ||
+                        !limiter.allow_child(current, line)
+                    {
+                        skip = 1;
+                        continue;
+                    }
+                    limiter.limit_attrs(&mut attrs, line);
+                }
                 let id = document.push_node(
                     Node::new(NodeData::Elem(Element {
                         name: convert_name(name),
-                        attrs: attributes
-                            .into_iter()
-                            .map(|OwnedAttribute { name, value }| {
-                                Attribute {
-                                    name: convert_name(name),
-                                    value: value.into()
-                                }
-                            })
-                            .collect(),
+                        attrs,
                         _priv: ()
                     }))
                 );
                 document.append(current, id);
+                if let Some(ref mut limiter) = limiter {
+                    limiter.linked(id, current);
+                }
+                if let Some(pos) = start {
+                    document.set_source_span(id, SourceSpan::at(pos));
+                }
                 ancestors.push(current);
                 current = id;
             }
             XmlEvent::EndElement { .. } => {
+                if start.is_some() {
+                    ending.push(current);
+                }
                 current = ancestors.pop().unwrap()
             }
-            XmlEvent::CData(s) |
-            XmlEvent::Characters(s) => {
+            XmlEvent::CData(mut s) |
+            XmlEvent::Characters(mut s) => {
+                if let Some(ref mut limiter) = limiter {
+                    let len = limiter.allow_text(&s, line);
+                    if len == 0 {
+                        continue;
+                    }
+                    s.truncate(len);
+                }
                 if let Some(last_child) = document[current].last_child {
                     let node = &mut document[last_child];
                     if let NodeData::Text(t) = &mut node.data {
                         t.push_slice(&s);
+                        if start.is_some() {
+                            ending.push(last_child);
+                        }
+                        continue;
+                    }
+                }
+                if let Some(ref limiter) = limiter {
+                    if !limiter.allow_node(&document, line) ||
+                        !limiter.allow_child(current, line)
+                    {
                         continue;
                     }
                 }
@@ -66,30 +185,452 @@ pub fn parse_utf8(utf8_bytes: &[u8]) -> Result<Document, XmlError> {
                     Node::new(NodeData::Text(s.into()))
                 );
                 document.append(current, id);
+                if let Some(pos) = start {
+                    document.set_source_span(id, SourceSpan::at(pos));
+                    ending.push(id);
+                }
             }
             XmlEvent::Whitespace(s) => {
                 debug_assert!(is_all_ctrl_ws(&s.into()));
                 //FIXME: Push as above, if in "preserve mode"?
             }
-            XmlEvent::ProcessingInstruction { name: _, data } => {
-                let data = if let Some(s) = data {
-                    s.into()
-                } else {
-                    StrTendril::new()
-                };
+            XmlEvent::ProcessingInstruction { name, data } => {
+                let mut data = data.unwrap_or_default();
+                if let Some(ref mut limiter) = limiter {
+                    let len = limiter.allow_text(&data, line);
+                    data.truncate(len);
+                    if !limiter.allow_node(&document, line) ||
+                        !limiter.allow_child(current, line)
+                    {
+                        continue;
+                    }
+                }
+                let data = data.into();
                 let id = document.push_node(
                     Node::new(NodeData::Pi(
-                        ProcessingInstruction { data, _priv: () }
+                        ProcessingInstruction {
+                            target: name.into(),
+                            data,
+                            _priv: ()
+                        }
                     ))
                 );
                 document.append(current, id);
+                if let Some(pos) = start {
+                    document.set_source_span(id, SourceSpan::at(pos));
+                    ending.push(id);
+                }
             }
+            XmlEvent::EndDocument => break,
             XmlEvent::StartDocument { .. } |
-            XmlEvent::EndDocument |
             XmlEvent::Comment(_) => {}
         }
     }
-    Ok(document)
+    match limits {
+        Some(limits) => check_limits(document, limits, &exceeded)
+            .map_err(XmlError::limit_error),
+        None => Ok(document),
+    }
+}
+
+/// Parse and return an XML `Document`, reading from the given stream of bytes
+/// until end, decoding per the given encoding hint.
+///
+/// The [`SharedEncodingHint`] must have a top (e.g. default) encoding, which
+/// will be used initially for decoding bytes. As with
+/// [`html::parse_buffered`](crate::html::parse_buffered), the
+/// [`INITIAL_BUFFER_SIZE`](crate::INITIAL_BUFFER_SIZE) bytes of the stream are
+/// buffered, and if a compelling alternative encoding hint is found via a
+/// leading Byte-Order-Mark (BOM) or the encoding of the XML declaration
+/// (with [`XML_DECL_CONF`]), decoding will be restarted from the beginning
+/// with that encoding. With the _detect_ feature, statistical detection is
+/// also applied, as described for `html::parse_buffered`. Thereafter the
+/// stream is decoded and parsed incrementally, as read. XML parse errors are
+/// returned as an `io::Error` of kind `InvalidData`, wrapping the
+/// [`XmlError`].
+pub fn parse_buffered<R>(hint: SharedEncodingHint, r: &mut R)
+    -> Result<Document, io::Error>
+    where R: io::Read
+{
+    parse_buffered_with_options(hint, r, &XmlParseOptions::default())
+}
+
+/// Parse and return an XML `Document` as per [`parse_buffered`], with the
+/// given options.
+///
+/// Any exceeded limit is returned as an `io::Error` of kind `InvalidData`,
+/// wrapping the [`XmlError`]. Any spans do not include byte offsets.
+pub fn parse_buffered_with_options<R>(
+    hint: SharedEncodingHint,
+    r: &mut R,
+    opts: &XmlParseOptions)
+    -> Result<Document, io::Error>
+    where R: io::Read
+{
+    let mut reader = DecodedReader::new(hint.clone(), r);
+    let res = parse(&mut reader, opts, None);
+    debug!("Final encoding errors {}", hint.borrow().errors());
+    res.map_err(|e| reader.take_error().unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }))
+}
+
+/// Parse and return an XML `Document` as per [`parse_buffered`], reading from
+/// the given asynchronous stream (_async_ feature).
+///
+/// The stream is decoded as read, yielding to the executor between each
+/// chunk, but as the XML parser can't be suspended, the decoded text is held
+/// in memory until the end of the stream, and only then parsed, without
+/// yielding. As for
+/// [`html::parse_buffered_async`](crate::html::parse_buffered_async), the
+/// returned future is not `Send`.
+#[cfg(feature = "async")]
+pub async fn parse_buffered_async<R>(hint: SharedEncodingHint, r: &mut R)
+    -> Result<Document, io::Error>
+    where R: tokio::io::AsyncRead + Unpin
+{
+    let opts = XmlParseOptions::default();
+    parse_buffered_async_with_options(hint, r, &opts).await
+}
+
+/// Parse and return an XML `Document` as per [`parse_buffered_async`], with
+/// the given options (_async_ feature).
+///
+/// With `XmlParseOptions::limits`, at most a number of bytes derived from
+/// those limits is read from the stream, beyond which an `io::Error` of kind
+/// `InvalidData` is returned. This allows for all of `max_text_bytes`, and
+/// `max_nodes` elements with `max_attributes` values of
+/// `max_attr_value_len`, each in several times as many bytes (for markup,
+/// escaping or UTF-16), but is otherwise unlimited.
+#[cfg(feature = "async")]
+pub async fn parse_buffered_async_with_options<R>(
+    hint: SharedEncodingHint,
+    r: &mut R,
+    opts: &XmlParseOptions)
+    -> Result<Document, io::Error>
+    where R: tokio::io::AsyncRead + Unpin
+{
+    // All bytes are read here, so the reader's own stream is empty.
+    let mut empty = io::empty();
+    let mut reader = DecodedReader::new(hint.clone(), &mut empty);
+    let max = opts.limits.as_ref().map_or(std::usize::MAX, max_input_bytes);
+    reader.decode_async(r, max).await?;
+    let res = parse(&mut reader, opts, None);
+    debug!("Final encoding errors {}", hint.borrow().errors());
+    res.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Return the maximum bytes of input allowed for the given limits, for
+// `parse_buffered_async_with_options`.
+#[cfg(feature = "async")]
+fn max_input_bytes(limits: &crate::ParseLimits) -> usize {
+    // Bytes of input allowed for each byte of text or attribute value, or
+    // per node for markup.
+    const EXPANSION: usize = 8;
+    const NODE_BYTES: usize = 1024;
+
+    let attrs = limits.max_attr_value_len
+        .saturating_mul(limits.max_attributes);
+    let node = attrs.saturating_mul(EXPANSION).saturating_add(NODE_BYTES);
+    limits.max_text_bytes
+        .saturating_mul(EXPANSION)
+        .saturating_add(node.saturating_mul(limits.max_nodes as usize))
+}
+
+type TextQueue = Rc<RefCell<VecDeque<StrTendril>>>;
+
+// An `io::Read` of UTF-8 text, decoded from a byte stream of unknown
+// encoding via `BufferedDecoder` and `DeclSink`, as it is read.
+struct DecodedReader<'r, R, F>
+    where F: Fn(bool) -> DeclSink
+{
+    r: &'r mut R,
+    decoder: Option<BufferedDecoder<DeclSink, F>>,
+    queue: TextQueue,
+    // Current decoded text, and position of the next byte to be read.
+    text: StrTendril,
+    pos: usize,
+    // Any error from reading r, as the original is not given to xml-rs
+    error: Option<io::Error>,
+}
+
+impl<'r, R> DecodedReader<'r, R, Box<dyn Fn(bool) -> DeclSink>>
+    where R: io::Read
+{
+    fn new(hint: SharedEncodingHint, r: &'r mut R) -> Self {
+        let queue = TextQueue::default();
+        let q = queue.clone();
+        let h = hint.clone();
+        let new_sink: Box<dyn Fn(bool) -> DeclSink> = Box::new(
+            move |enc_check| {
+                // On restart, discard any text of the prior decoding
+                q.borrow_mut().clear();
+                DeclSink::new(h.clone(), q.clone(), enc_check)
+            }
+        );
+        DecodedReader {
+            r,
+            decoder: Some(BufferedDecoder::new(hint, new_sink)),
+            queue,
+            text: StrTendril::new(),
+            pos: 0,
+            error: None,
+        }
+    }
+}
+
+impl<'r, R, F> DecodedReader<'r, R, F>
+    where F: Fn(bool) -> DeclSink
+{
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    // Read and decode all of the given asynchronous stream, failing if more
+    // than max bytes are read. The decoded text is then available to read.
+    #[cfg(feature = "async")]
+    async fn decode_async<A>(&mut self, r: &mut A, max: usize)
+        -> Result<(), io::Error>
+        where A: tokio::io::AsyncRead + Unpin
+    {
+        let mut decoder = self.decoder.take()
+            .expect("DecodedReader not yet read");
+        let mut total = 0usize;
+        loop {
+            let n = decoder.read_chunk_async(r).await?;
+            if n == 0 {
+                break;
+            }
+            total += n;
+            if total > max {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("input exceeds {} bytes allowed by limits", max)
+                ));
+            }
+        }
+        decoder.finish();
+        Ok(())
+    }
+}
+
+impl<'r, R, F> io::Read for DecodedReader<'r, R, F>
+    where R: io::Read, F: Fn(bool) -> DeclSink
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.pos < self.text.len() {
+                let bytes = &self.text.as_bytes()[self.pos..];
+                let n = std::cmp::min(buf.len(), bytes.len());
+                buf[..n].copy_from_slice(&bytes[..n]);
+                self.pos += n;
+                return Ok(n);
+            }
+
+            // Text is only available once buffering has ended
+            let buffering = self.decoder.as_ref()
+                .map_or(false, |d| d.is_buffering());
+            if !buffering {
+                if let Some(text) = self.queue.borrow_mut().pop_front() {
+                    self.text = text;
+                    self.pos = 0;
+                    continue;
+                }
+            }
+
+            let decoder = match self.decoder {
+                Some(ref mut d) => d,
+                None => return Ok(0),
+            };
+            match decoder.read_chunk(self.r) {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.decoder.take().unwrap().finish();
+                }
+                Err(e) => {
+                    let kind = e.kind();
+                    self.error = Some(e);
+                    return Err(kind.into());
+                }
+            }
+        }
+    }
+}
+
+// A `TendrilSink` of decoded text, pushed to a queue, which also checks any
+// XML declaration for an encoding hint. As the text is now UTF-8, any BOM is
+// removed and any declared encoding, which xml-rs might otherwise act on, is
+// replaced.
+struct DeclSink {
+    queue: TextQueue,
+    // Leading text, held until any XML declaration is complete
+    head: Option<StrTendril>,
+    enc_hint: SharedEncodingHint,
+    enc_check: bool,
+}
+
+impl DeclSink {
+    fn new(enc_hint: SharedEncodingHint, queue: TextQueue, enc_check: bool)
+        -> DeclSink
+    {
+        DeclSink { queue, head: Some(StrTendril::new()), enc_hint, enc_check }
+    }
+
+    fn check_declaration(&mut self, text: &str) {
+        self.enc_check = false;
+        if let Some((start, end)) = decl_encoding(text) {
+            let label = text[start..end].trim();
+            debug!("found XML declaration encoding: {}", label);
+            if let Some(enc) = enc::Encoding::for_label(label.as_bytes()) {
+                let mut hints = self.enc_hint.borrow_mut();
+                if hints.could_read_from(enc) {
+                    hints.add_hint_from(
+                        enc, XML_DECL_CONF, HintSource::XmlDeclaration
+                    );
+                } else {
+                    debug!("Ignoring impossible hint: {}", enc.name());
+                }
+            }
+        }
+    }
+
+    // Check and push the head text, if complete, or if finishing.
+    fn push_head(&mut self, finish: bool) {
+        let head = self.head.take().unwrap();
+        let text = head.trim_start_matches('\u{FEFF}');
+        let partial = if text.starts_with("<?xml") {
+            !text.contains("?>")
+        } else {
+            "<?xml".starts_with(text)
+        };
+        if partial && text.len() < 1024 && !finish {
+            self.head = Some(head); // wait for more text
+            return;
+        }
+        if self.enc_check {
+            self.check_declaration(text);
+        }
+        let text = match decl_encoding(text) {
+            Some((start, end)) => StrTendril::from(
+                format!("{}UTF-8{}", &text[..start], &text[end..])
+            ),
+            None => StrTendril::from_slice(text),
+        };
+        self.queue.borrow_mut().push_back(text);
+    }
+}
+
+impl TendrilSink<form::UTF8> for DeclSink {
+    type Output = ();
+
+    fn process(&mut self, t: StrTendril) {
+        if let Some(ref mut head) = self.head {
+            head.push_tendril(&t);
+            self.push_head(false);
+        } else {
+            self.queue.borrow_mut().push_back(t);
+        }
+    }
+
+    fn error(&mut self, desc: Cow<'static, str>) {
+        if desc == "invalid byte sequence" {
+            // From tendril crate (src/stream.rs) or our Decoder
+            self.enc_hint.borrow_mut().increment_error();
+        } else {
+            debug!("decoder error: {}", desc);
+        }
+    }
+
+    fn finish(mut self) {
+        if self.head.is_some() {
+            self.push_head(true);
+        }
+    }
+}
+
+// Return the byte range of the encoding value of any XML declaration at the
+// start of text.
+fn decl_encoding(text: &str) -> Option<(usize, usize)> {
+    if !text.starts_with("<?xml") {
+        return None;
+    }
+    let decl = &text[..text.find("?>")?];
+    let i = decl.find("encoding")? + 8;
+    let rest = decl[i..].trim_start();
+    if !rest.starts_with('=') {
+        return None;
+    }
+    let rest = rest[1..].trim_start();
+    let quote = rest.chars().next()?;
+    if quote != '"' && quote != '\'' {
+        return None;
+    }
+    let start = decl.len() - rest.len() + 1;
+    let end = start + rest[1..].find(quote)?;
+    Some((start, end))
+}
+
+/// Parse XML document from UTF-8 bytes in RAM, in a lenient mode which
+/// recovers from errors.
+///
+/// Unlike [`parse_utf8`], this never fails, but instead returns a best-effort
+/// `Document` and a list of all errors recovered from, with their positions,
+/// in source order. The following are repaired: unclosed or mismatched
+/// elements, unescaped `&` or `<` in text, undeclared entities (replaced if
+/// a known HTML named entity, otherwise retained as text), unquoted or
+/// missing attribute values, duplicate attributes, undeclared namespace
+/// prefixes and invalid UTF-8 (replaced with U+FFFD). As with `parse_utf8`,
+/// comments and white-space only text between elements are not retained.
+pub fn parse_utf8_lenient(utf8_bytes: &[u8])
+    -> (Document, Vec<RecoveredError>)
+{
+    parse_lenient(utf8_bytes, None)
+}
+
+/// Parse XML document from UTF-8 bytes in RAM, in a lenient mode as per
+/// [`parse_utf8_lenient`], with the given options.
+///
+/// Any `limits` are enforced as with [`parse_utf8_with_options`], so this
+/// only fails with an exceeded limit, if not truncating. Source spans are not
+/// recorded.
+pub fn parse_utf8_lenient_with_options(
+    utf8_bytes: &[u8],
+    opts: &XmlParseOptions)
+    -> Result<(Document, Vec<RecoveredError>), XmlError>
+{
+    let limits = match opts.limits {
+        Some(ref limits) => limits,
+        None => return Ok(parse_lenient(utf8_bytes, None)),
+    };
+    let exceeded = SharedLimitError::default();
+    let limiter = Limiter::new(limits, exceeded.clone());
+    let (doc, errors) = parse_lenient(utf8_bytes, Some(limiter));
+    let doc = check_limits(doc, limits, &exceeded)
+        .map_err(XmlError::limit_error)?;
+    Ok((doc, errors))
+}
+
+// Parse leniently, with any invalid UTF-8 replaced and reported as the first
+// error.
+fn parse_lenient(utf8_bytes: &[u8], limiter: Option<Limiter>)
+    -> (Document, Vec<RecoveredError>)
+{
+    match std::str::from_utf8(utf8_bytes) {
+        Ok(src) => lenient::parse(src, limiter),
+        Err(e) => {
+            let src = String::from_utf8_lossy(utf8_bytes);
+            let (doc, mut errors) = lenient::parse(&src, limiter);
+            let valid = &src[..e.valid_up_to()];
+            let line = valid.matches('\n').count();
+            let column = valid.rsplit('\n').next().unwrap().chars().count();
+            errors.insert(0, RecoveredError {
+                line: line as u32 + 1,
+                column: column as u32 + 1,
+                message: "invalid UTF-8 (replaced)".to_owned()
+            });
+            (doc, errors)
+        }
+    }
 }
 
 fn convert_name(name: xml_rs::name::OwnedName) -> QualName {
@@ -102,16 +643,81 @@ fn convert_name(name: xml_rs::name::OwnedName) -> QualName {
 
 /// An XML parsing error.
 #[derive(PartialEq, Eq, Clone, Debug)]
-pub struct XmlError(xml_rs::reader::Error);
+pub struct XmlError(XmlErrorKind);
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+enum XmlErrorKind {
+    Parse(xml_rs::reader::Error),
+    Limit(LimitError),
+}
+
+impl XmlError {
+    fn parse(e: xml_rs::reader::Error) -> XmlError {
+        XmlError(XmlErrorKind::Parse(e))
+    }
+
+    fn limit_error(e: LimitError) -> XmlError {
+        XmlError(XmlErrorKind::Limit(e))
+    }
+
+    /// Return the [`LimitError`] if this error is due to a [`ParseLimits`]
+    /// limit being exceeded.
+    pub fn limit(&self) -> Option<&LimitError> {
+        match self.0 {
+            XmlErrorKind::Limit(ref e) => Some(e),
+            XmlErrorKind::Parse(_) => None,
+        }
+    }
+}
 
 impl fmt::Display for XmlError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.0.fmt(f)
+        match self.0 {
+            XmlErrorKind::Parse(ref e) => e.fmt(f),
+            XmlErrorKind::Limit(ref e) => e.fmt(f),
+        }
     }
 }
 
 impl StdError for XmlError {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
-        self.0.source()
+        match self.0 {
+            XmlErrorKind::Parse(ref e) => e.source(),
+            XmlErrorKind::Limit(ref e) => Some(e),
+        }
     }
 }
+
+/// An error recovered from in [`parse_utf8_lenient`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecoveredError {
+    line: u32,
+    column: u32,
+    message: String,
+}
+
+impl RecoveredError {
+    /// Return the (1-based) line number where the error was found.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// Return the (1-based) column number, in chars, where the error was
+    /// found.
+    pub fn column(&self) -> u32 {
+        self.column
+    }
+
+    /// Return a description of the error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for RecoveredError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl StdError for RecoveredError {}
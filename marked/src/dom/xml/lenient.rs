@@ -0,0 +1,620 @@
+//! Lenient, error recovering XML parser.
+
+use html5ever::data::NAMED_ENTITIES;
+
+use crate::dom::{
+    Attribute, Document, Element, LocalName, Namespace, Node, NodeData, NodeId,
+    ProcessingInstruction, QualName, StrTendril
+};
+use crate::chars::{is_all_ctrl_ws, is_xml_char};
+use crate::dom::limits::Limiter;
+
+use super::RecoveredError;
+
+const XML_URI: &str = "http://www.w3.org/XML/1998/namespace";
+
+struct OpenElement {
+    // None if dropped due to limits, along with its content
+    id: Option<NodeId>,
+    name: String,
+    scope_len: usize,
+}
+
+struct Parser<'s> {
+    src: &'s str,
+    pos: usize,
+    doc: Document,
+    open: Vec<OpenElement>,
+    // In-scope namespace bindings as (prefix, URI), where the empty prefix is
+    // the default namespace.
+    scope: Vec<(String, String)>,
+    text: String,
+    text_start: usize,
+    text_cdata: bool,
+    root_seen: bool,
+    line_starts: Vec<usize>,
+    errors: Vec<RecoveredError>,
+    limiter: Option<Limiter>,
+}
+
+/// Parse the given source, returning a best-effort `Document` and all errors
+/// recovered from, in source order. Any limits are enforced via the given
+/// `Limiter`.
+pub(super) fn parse(src: &str, limiter: Option<Limiter>)
+    -> (Document, Vec<RecoveredError>)
+{
+    let line_starts = std::iter::once(0)
+        .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let mut p = Parser {
+        src,
+        pos: if src.starts_with('\u{FEFF}') { 3 } else { 0 },
+        doc: Document::new(),
+        open: Vec::new(),
+        scope: vec![("xml".to_owned(), XML_URI.to_owned())],
+        text: String::new(),
+        text_start: 0,
+        text_cdata: false,
+        root_seen: false,
+        line_starts,
+        errors: Vec::new(),
+        limiter,
+    };
+    p.run();
+    (p.doc, p.errors)
+}
+
+impl<'s> Parser<'s> {
+    fn run(&mut self) {
+        while self.pos < self.src.len() {
+            if self.limiter.as_ref().map_or(false, |l| l.stop()) {
+                return;
+            }
+            let rest = &self.src[self.pos..];
+            if rest.starts_with("<!--") {
+                self.comment();
+            } else if rest.starts_with("<![CDATA[") {
+                self.cdata();
+            } else if rest.starts_with("<!") {
+                self.declaration();
+            } else if rest.starts_with("<?") {
+                self.pi();
+            } else if rest.starts_with("</") {
+                self.end_tag();
+            } else if rest.starts_with('<') &&
+                rest[1..].chars().next().map_or(false, is_name_start)
+            {
+                self.start_tag();
+            } else if rest.starts_with('<') {
+                self.error(self.pos, "unescaped '<' in text");
+                self.push_text("<", self.pos);
+                self.pos += 1;
+            } else {
+                let end = rest.find('<')
+                    .map_or(self.src.len(), |i| self.pos + i);
+                let raw = &self.src[self.pos..end];
+                let start = self.pos;
+                let decoded = self.decode(raw, start, false);
+                self.push_text(&decoded, start);
+                self.pos = end;
+            }
+        }
+        self.flush_text();
+        while let Some(open) = self.open.pop() {
+            let msg = format!("element <{}> not closed", open.name);
+            self.error(self.src.len(), &msg);
+        }
+    }
+
+    fn comment(&mut self) {
+        // Comments are dropped, as with the strict parser
+        match self.src[self.pos + 4..].find("-->") {
+            Some(i) => self.pos += 4 + i + 3,
+            None => {
+                self.error(self.pos, "comment not closed");
+                self.pos = self.src.len();
+            }
+        }
+    }
+
+    fn cdata(&mut self) {
+        let start = self.pos;
+        let body = start + 9;
+        let (end, next) = match self.src[body..].find("]]>") {
+            Some(i) => (body + i, body + i + 3),
+            None => {
+                self.error(start, "CDATA section not closed");
+                (self.src.len(), self.src.len())
+            }
+        };
+        let text = normalize_newlines(&self.src[body..end]);
+        self.push_text(&text, start);
+        self.text_cdata = true;
+        self.pos = next;
+    }
+
+    fn declaration(&mut self) {
+        // DOCTYPE or other declaration, skipped including any internal
+        // subset in brackets.
+        let mut depth = 0;
+        for (i, c) in self.src[self.pos..].char_indices() {
+            match c {
+                '[' => depth += 1,
+                ']' if depth > 0 => depth -= 1,
+                '>' if depth == 0 => {
+                    self.pos += i + 1;
+                    return;
+                }
+                _ => {}
+            }
+        }
+        self.error(self.pos, "declaration not closed");
+        self.pos = self.src.len();
+    }
+
+    fn pi(&mut self) {
+        let start = self.pos;
+        let body = start + 2;
+        let (end, next) = match self.src[body..].find("?>") {
+            Some(i) => (body + i, body + i + 2),
+            None => {
+                self.error(start, "processing instruction not closed");
+                (self.src.len(), self.src.len())
+            }
+        };
+        self.pos = next;
+        let content = &self.src[body..end];
+        let tlen = content.find(is_ws).unwrap_or(content.len());
+        let target = &content[..tlen];
+        if target.eq_ignore_ascii_case("xml") {
+            if !self.src[..start].trim_start_matches(is_ws).is_empty() {
+                self.error(start, "misplaced XML declaration");
+            }
+            return;
+        }
+        if target.is_empty() {
+            self.error(start, "processing instruction without target");
+        }
+        self.flush_text();
+        let parent = match self.current() {
+            Some(parent) => parent,
+            None => return,
+        };
+        let mut data = content[tlen..].trim_start_matches(is_ws);
+        if let Some(ref mut limiter) = self.limiter {
+            let line = line_of(&self.line_starts, start) as u64 + 1;
+            data = &data[..limiter.allow_text(data, line)];
+        }
+        self.append(parent, NodeData::Pi(
+            ProcessingInstruction {
+                target: target.into(),
+                data: data.into(),
+                _priv: ()
+            }
+        ), start);
+    }
+
+    fn start_tag(&mut self) {
+        let start = self.pos;
+        self.pos += 1;
+        let name = self.name().to_owned();
+        let mut raw_attrs: Vec<(String, String)> = Vec::new();
+        let mut empty = false;
+        loop {
+            self.skip_ws();
+            let rest = &self.src[self.pos..];
+            if rest.starts_with("/>") {
+                self.pos += 2;
+                empty = true;
+                break;
+            } else if rest.starts_with('>') {
+                self.pos += 1;
+                break;
+            } else if rest.is_empty() || rest.starts_with('<') {
+                let msg = format!("start tag <{}> not closed", name);
+                self.error(start, &msg);
+                break;
+            }
+            let apos = self.pos;
+            let aname = self.name().to_owned();
+            if aname.is_empty() {
+                let c = rest.chars().next().unwrap();
+                let msg = format!("unexpected '{}' in start tag", c);
+                self.error(apos, &msg);
+                self.pos += c.len_utf8();
+                continue;
+            }
+            self.skip_ws();
+            let value = if self.src[self.pos..].starts_with('=') {
+                self.pos += 1;
+                self.skip_ws();
+                self.attr_value(&aname)
+            } else {
+                let msg = format!("attribute {} without value", aname);
+                self.error(apos, &msg);
+                String::new()
+            };
+            if raw_attrs.iter().any(|(n, _)| *n == aname) {
+                let msg = format!("duplicate attribute {}", aname);
+                self.error(apos, &msg);
+            } else {
+                raw_attrs.push((aname, value));
+            }
+        }
+
+        self.flush_text();
+        if self.open.is_empty() {
+            if self.root_seen {
+                self.error(start, "multiple root elements");
+            }
+            self.root_seen = true;
+        }
+
+        let scope_len = self.scope.len();
+        for (aname, value) in &raw_attrs {
+            if aname == "xmlns" {
+                self.scope.push((String::new(), value.clone()));
+            } else if aname.starts_with("xmlns:") {
+                self.scope.push((aname[6..].to_owned(), value.clone()));
+            }
+        }
+
+        let qname = self.resolve(&name, true, start);
+        let mut attrs = Vec::with_capacity(raw_attrs.len());
+        for (aname, value) in raw_attrs {
+            if aname == "xmlns" || aname.starts_with("xmlns:") {
+                continue;
+            }
+            attrs.push(Attribute {
+                name: self.resolve(&aname, false, start),
+                value: value.into()
+            });
+        }
+
+        let id = match self.current() {
+            Some(parent) => self.append(parent, NodeData::Elem(Element {
+                name: qname,
+                attrs,
+                _priv: ()
+            }), start),
+            None => None,
+        };
+        if empty {
+            self.scope.truncate(scope_len);
+        } else {
+            self.open.push(OpenElement { id, name, scope_len });
+        }
+    }
+
+    fn attr_value(&mut self, aname: &str) -> String {
+        let start = self.pos;
+        let rest = &self.src[self.pos..];
+        let quote = rest.chars().next();
+        let (raw, next) = match quote {
+            Some(q) if q == '"' || q == '\'' => {
+                match rest[1..].find(q) {
+                    Some(i) => (&rest[1..i+1], self.pos + i + 2),
+                    None => {
+                        let msg = format!(
+                            "attribute {} value not closed", aname
+                        );
+                        self.error(start, &msg);
+                        let end = rest.find('>').unwrap_or(rest.len());
+                        (&rest[1..end], self.pos + end)
+                    }
+                }
+            }
+            _ => {
+                let msg = format!("attribute {} value not quoted", aname);
+                self.error(start, &msg);
+                let end = rest
+                    .find(|c| is_ws(c) || c == '>' || c == '<')
+                    .unwrap_or(rest.len());
+                let end = if rest[..end].ends_with('/') &&
+                    rest[end..].starts_with('>')
+                {
+                    end - 1
+                } else {
+                    end
+                };
+                (&rest[..end], self.pos + end)
+            }
+        };
+        if raw.contains('<') {
+            let msg = format!("'<' in attribute {} value", aname);
+            self.error(start, &msg);
+        }
+        self.pos = next;
+        self.decode(raw, start, true)
+    }
+
+    fn end_tag(&mut self) {
+        let start = self.pos;
+        self.pos += 2;
+        let name = self.name().to_owned();
+        self.skip_ws();
+        let rest = &self.src[self.pos..];
+        match rest.find(|c| c == '>' || c == '<') {
+            Some(i) if rest[i..].starts_with('>') => {
+                if i > 0 {
+                    self.error(self.pos, "unexpected content in end tag");
+                }
+                self.pos += i + 1;
+            }
+            Some(i) => {
+                self.error(start, "end tag not closed");
+                self.pos += i;
+            }
+            None => {
+                self.error(start, "end tag not closed");
+                self.pos = self.src.len();
+            }
+        }
+
+        self.flush_text();
+        match self.open.iter().rposition(|o| o.name == name) {
+            Some(i) => {
+                while self.open.len() > i + 1 {
+                    let open = self.open.pop().unwrap();
+                    let msg = format!("element <{}> not closed", open.name);
+                    self.error(start, &msg);
+                }
+                let open = self.open.pop().unwrap();
+                self.scope.truncate(open.scope_len);
+            }
+            None => {
+                let msg = format!("unexpected end tag </{}>", name);
+                self.error(start, &msg);
+            }
+        }
+    }
+
+    // Resolve a raw (possibly prefixed) name to a QualName, per the current
+    // namespace scope.
+    fn resolve(&mut self, raw: &str, is_elem: bool, pos: usize) -> QualName {
+        let (prefix, local) = match raw.find(':') {
+            Some(i) if i > 0 && i + 1 < raw.len() => {
+                (Some(&raw[..i]), &raw[i+1..])
+            }
+            _ => (None, raw),
+        };
+        match prefix {
+            Some(p) => {
+                if let Some(uri) = self.lookup(p) {
+                    let ns = Namespace::from(uri);
+                    QualName::new(Some(p.into()), ns, LocalName::from(local))
+                } else {
+                    let msg = format!("namespace prefix {} not declared", p);
+                    self.error(pos, &msg);
+                    QualName::new(None, ns!(), LocalName::from(raw))
+                }
+            }
+            None => {
+                let ns = if is_elem {
+                    self.lookup("").map_or(ns!(), Namespace::from)
+                } else {
+                    ns!()
+                };
+                QualName::new(None, ns, LocalName::from(local))
+            }
+        }
+    }
+
+    fn lookup(&self, prefix: &str) -> Option<&str> {
+        self.scope.iter().rev()
+            .find(|(p, _)| p == prefix)
+            .map(|(_, u)| u.as_str())
+            .filter(|u| !u.is_empty())
+    }
+
+    // Decode entity and character references, and normalize line breaks (and
+    // for attributes, other white-space).
+    fn decode(&mut self, raw: &str, offset: usize, attr: bool) -> String {
+        let mut out = String::with_capacity(raw.len());
+        let mut rest = raw;
+        while let Some(i) = rest.find(|c| c == '&' || c == '\r' ||
+                                      (attr && (c == '\n' || c == '\t')))
+        {
+            out.push_str(&rest[..i]);
+            let amp = &rest[i..];
+            if amp.starts_with("\r\n") {
+                out.push(if attr { ' ' } else { '\n' });
+                rest = &amp[2..];
+                continue;
+            } else if !amp.starts_with('&') {
+                out.push(if attr { ' ' } else { '\n' });
+                rest = &amp[1..];
+                continue;
+            }
+            let epos = offset + (raw.len() - amp.len());
+            let name_len = amp[1..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '#' || c == '_' ||
+                                  c == '-' || c == '.'))
+                .unwrap_or_else(|| amp.len() - 1);
+            let name = &amp[1..1 + name_len];
+            if name.is_empty() || !amp[1 + name_len..].starts_with(';') {
+                self.error(epos, "unescaped '&'");
+                out.push('&');
+                rest = &amp[1..];
+                continue;
+            }
+            rest = &amp[name_len + 2..];
+            if name.starts_with('#') {
+                let cp = if name.starts_with("#x") {
+                    u32::from_str_radix(&name[2..], 16).ok()
+                } else {
+                    name[1..].parse::<u32>().ok()
+                };
+                match cp.and_then(std::char::from_u32) {
+                    Some(c) if is_xml_char(c) => out.push(c),
+                    _ => {
+                        let msg = format!(
+                            "invalid character reference &{};", name
+                        );
+                        self.error(epos, &msg);
+                        out.push('\u{FFFD}');
+                    }
+                }
+                continue;
+            }
+            let c = match name {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                _ => {
+                    let key = &amp[1..name_len + 2];
+                    match NAMED_ENTITIES.get(key) {
+                        Some(&(c1, c2)) => {
+                            let msg = format!(
+                                "undeclared entity &{}; (replaced as HTML)",
+                                name
+                            );
+                            self.error(epos, &msg);
+                            out.extend(std::char::from_u32(c1));
+                            if c2 != 0 {
+                                out.extend(std::char::from_u32(c2));
+                            }
+                        }
+                        None => {
+                            let msg = format!("undeclared entity &{};", name);
+                            self.error(epos, &msg);
+                            out.push('&');
+                            out.push_str(key);
+                        }
+                    }
+                    continue;
+                }
+            };
+            out.push(c);
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn push_text(&mut self, text: &str, pos: usize) {
+        if self.text.is_empty() {
+            self.text_start = pos;
+        }
+        self.text.push_str(text);
+    }
+
+    // Append any pending text to the current node, unless only white-space
+    // (and not CDATA).
+    fn flush_text(&mut self) {
+        if self.text.is_empty() {
+            return;
+        }
+        let mut text: StrTendril =
+            std::mem::replace(&mut self.text, String::new()).into();
+        let cdata = self.text_cdata;
+        self.text_cdata = false;
+        if !cdata && is_all_ctrl_ws(&text) {
+            return;
+        }
+        if self.open.is_empty() {
+            self.error(self.text_start, "text outside of root element");
+        }
+        let current = match self.current() {
+            Some(current) => current,
+            None => return,
+        };
+        if let Some(ref mut limiter) = self.limiter {
+            let line = line_of(&self.line_starts, self.text_start) as u64 + 1;
+            let len = limiter.allow_text(&text, line);
+            if len == 0 {
+                return;
+            }
+            text.pop_back(text.len32() - len as u32);
+        }
+        if let Some(last_child) = self.doc[current].last_child {
+            if let NodeData::Text(t) = &mut self.doc[last_child].data {
+                t.push_tendril(&text);
+                return;
+            }
+        }
+        self.append(current, NodeData::Text(text), self.text_start);
+    }
+
+    // Return the current node to append to, or None if within an element
+    // dropped due to limits.
+    fn current(&self) -> Option<NodeId> {
+        match self.open.last() {
+            Some(open) => open.id,
+            None => Some(Document::DOCUMENT_NODE_ID),
+        }
+    }
+
+    // Append a new node to parent, unless dropped due to limits.
+    fn append(&mut self, parent: NodeId, mut data: NodeData, offset: usize)
+        -> Option<NodeId>
+    {
+        if let Some(ref mut limiter) = self.limiter {
+            let line = line_of(&self.line_starts, offset) as u64 + 1;
+            if !limiter.allow_node(&self.doc, line) ||
+                !limiter.allow_child(parent, line)
+            {
+                return None;
+            }
+            if let NodeData::Elem(ref mut elem) = data {
+                limiter.limit_attrs(&mut elem.attrs, line);
+            }
+        }
+        let id = self.doc.push_node(Node::new(data));
+        self.doc.append(parent, id);
+        if let Some(ref mut limiter) = self.limiter {
+            limiter.linked(id, parent);
+        }
+        Some(id)
+    }
+
+    fn name(&mut self) -> &'s str {
+        let rest = &self.src[self.pos..];
+        let len = rest
+            .find(|c: char| !is_name_char(c))
+            .unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn skip_ws(&mut self) {
+        let rest = &self.src[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches(is_ws).len();
+    }
+
+    fn error(&mut self, offset: usize, message: &str) {
+        let line = line_of(&self.line_starts, offset);
+        let column = self.src[self.line_starts[line]..offset].chars().count();
+        self.errors.push(RecoveredError {
+            line: line as u32 + 1,
+            column: column as u32 + 1,
+            message: message.to_owned(),
+        });
+    }
+}
+
+// Return the 0-based line containing the given byte offset.
+fn line_of(line_starts: &[usize], offset: usize) -> usize {
+    match line_starts.binary_search(&offset) {
+        Ok(l) => l,
+        Err(l) => l - 1,
+    }
+}
+
+fn normalize_newlines(s: &str) -> String {
+    s.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+fn is_ws(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\n' || c == '\r'
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == ':'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == ':' || c == '-' || c == '.' ||
+        c == '\u{B7}'
+}
@@ -0,0 +1,36 @@
+use crate::ParseLimits;
+
+/// Options for XML parsing, as accepted by the `xml::parse_*_with_options`
+/// functions.
+///
+/// Use struct update syntax to set specific options:
+///
+/// ```
+/// use marked::{xml::{self, XmlParseOptions}, ParseLimits};
+///
+/// let opts = XmlParseOptions {
+///     limits: Some(ParseLimits { max_depth: 1, ..ParseLimits::default() }),
+///     ..XmlParseOptions::default()
+/// };
+/// let err = xml::parse_utf8_with_options(b"<a><b/></a>", &opts).unwrap_err();
+/// assert!(err.limit().is_some());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct XmlParseOptions {
+    /// If true, record the [`SourceSpan`](crate::SourceSpan) of each node, as
+    /// available via [`NodeRef::source_span`](crate::NodeRef::source_span).
+    ///
+    /// Spans include line and column, with each node ending where the next
+    /// markup or text starts. When parsing UTF-8 bytes in RAM, spans also
+    /// include the byte offset. Default: false
+    pub spans: bool,
+
+    /// If set, the [`ParseLimits`] to enforce. If a limit is exceeded,
+    /// parsing stops and an `XmlError` is returned with the
+    /// [`LimitError`](crate::LimitError) available via
+    /// [`XmlError::limit`](super::XmlError::limit). If
+    /// `ParseLimits::truncate` is set, then any element exceeding a limit is
+    /// dropped, along with its content, and the truncated `Document` is
+    /// returned. Default: None
+    pub limits: Option<ParseLimits>,
+}
@@ -0,0 +1,417 @@
+use std::borrow::Cow;
+use std::io;
+use std::io::Write;
+
+use crate::chars::{
+    is_ncname, is_ncname_char, is_ncname_start_char, is_pubid_char,
+    is_xml_char,
+};
+use crate::dom::{
+    html::{ns, TAG_META},
+    Attribute, Document, Element, NodeData, NodeRef,
+};
+
+const XMLNS_URI: &str = "http://www.w3.org/2000/xmlns/";
+const XML_URI: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// Options for [`Document::serialize_xml`] and [`NodeRef::serialize_xml`].
+#[derive(Clone, Debug)]
+pub struct XmlOptions {
+    /// If true, write XHTML compatible output: HTML void elements (e.g.
+    /// `<br />`) are self-closing, but other empty HTML elements are written
+    /// with an end tag (e.g. `<p></p>`). Otherwise all empty elements are
+    /// self-closing. Default: false
+    pub xhtml: bool,
+
+    /// If true, start with an XML declaration `<?xml version="1.0"
+    /// encoding="UTF-8"?>`. Default: true
+    pub xml_declaration: bool,
+}
+
+impl Default for XmlOptions {
+    fn default() -> XmlOptions {
+        XmlOptions { xhtml: false, xml_declaration: true }
+    }
+}
+
+/// XML serialize convenience method.
+impl Document {
+    /// Serialize the contents of the document node and descendants in XML
+    /// syntax to the given stream, as UTF-8.
+    ///
+    /// The output is well-formed XML for any `Document`, including those
+    /// parsed as HTML. Namespace declarations (`xmlns` attributes) are written
+    /// as required by the namespace and prefix of each element and
+    /// attribute. Any `xmlns` attributes already present are preserved where
+    /// they don't conflict.  Characters not allowed in XML are replaced with
+    /// U+FFFD, and comments or processing instructions which would be
+    /// ill-formed are minimally altered. Element, processing instruction
+    /// target and document type names which are not valid XML names have
+    /// invalid characters replaced with `_`, and attributes with invalid
+    /// names are skipped. The reserved `xml` and `xmlns` prefixes are never
+    /// declared, and are replaced with generated prefixes where used with
+    /// other namespaces.
+    pub fn serialize_xml<W>(&self, writer: &mut W, opts: &XmlOptions)
+        -> io::Result<()>
+        where W: Write
+    {
+        XmlSerializer::new(writer, opts).write_document(self)
+    }
+}
+
+/// XML serialize convenience method.
+impl<'a> NodeRef<'a> {
+    /// Serialize the referenced node and its descendants in XML syntax to the
+    /// given stream, as UTF-8.
+    ///
+    /// See [`Document::serialize_xml`].
+    pub fn serialize_xml<W>(&self, writer: &mut W, opts: &XmlOptions)
+        -> io::Result<()>
+        where W: Write
+    {
+        let mut ser = XmlSerializer::new(writer, opts);
+        ser.write_declaration()?;
+        ser.write_node(*self)
+    }
+}
+
+struct XmlSerializer<'w, 'o, W: Write> {
+    out: &'w mut W,
+    opts: &'o XmlOptions,
+    // In-scope namespace bindings as (prefix, URI), where the empty prefix is
+    // the default namespace.
+    scope: Vec<(String, String)>,
+    generated: usize,
+}
+
+impl<'w, 'o, W: Write> XmlSerializer<'w, 'o, W> {
+    fn new(out: &'w mut W, opts: &'o XmlOptions) -> Self {
+        let scope = vec![
+            ("xml".to_owned(), XML_URI.to_owned()),
+            ("".to_owned(), "".to_owned()),
+        ];
+        XmlSerializer { out, opts, scope, generated: 0 }
+    }
+
+    fn write_document(&mut self, doc: &Document) -> io::Result<()> {
+        self.write_declaration()?;
+        for child in doc.document_node_ref().children() {
+            self.write_node(child)?;
+        }
+        Ok(())
+    }
+
+    fn write_declaration(&mut self) -> io::Result<()> {
+        if self.opts.xml_declaration {
+            self.out.write_all(
+                b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_node(&mut self, node: NodeRef<'_>) -> io::Result<()> {
+        match node.data {
+            NodeData::Document => {
+                for child in node.children() {
+                    self.write_node(child)?;
+                }
+                Ok(())
+            }
+            NodeData::DocType(ref dt) => {
+                self.out.write_all(b"<!DOCTYPE ")?;
+                self.out.write_all(xml_name(&dt.name).as_bytes())?;
+                if !dt.public_id.is_empty() {
+                    let public_id: String = dt.public_id.chars()
+                        .filter(|&c| is_pubid_char(c))
+                        .collect();
+                    self.out.write_all(b" PUBLIC \"")?;
+                    self.out.write_all(public_id.as_bytes())?;
+                    self.out.write_all(b"\"")?;
+                    self.write_system_id(&dt.system_id)?;
+                } else if !dt.system_id.is_empty() {
+                    self.out.write_all(b" SYSTEM")?;
+                    self.write_system_id(&dt.system_id)?;
+                }
+                self.out.write_all(b">\n")
+            }
+            NodeData::Text(ref t) => self.write_escaped(t, false),
+            NodeData::Comment(ref t) => {
+                // Separate all adjacent dashes, and any trailing dash
+                let mut text = String::with_capacity(t.len());
+                for c in t.chars() {
+                    if c == '-' && text.ends_with('-') {
+                        text.push(' ');
+                    }
+                    text.push(c);
+                }
+                if text.ends_with('-') {
+                    text.push(' ');
+                }
+                self.out.write_all(b"<!--")?;
+                self.write_chars(&text)?;
+                self.out.write_all(b"-->")
+            }
+            NodeData::Pi(ref pi) => {
+                self.out.write_all(b"<?")?;
+                self.out.write_all(xml_name(&pi.target).as_bytes())?;
+                if !pi.data.is_empty() {
+                    self.out.write_all(b" ")?;
+                    self.write_chars(&pi.data.replace("?>", "? >"))?;
+                }
+                self.out.write_all(b"?>")
+            }
+            NodeData::Elem(ref elm) => self.write_element(node, elm),
+            NodeData::Hole => panic!("Hole in Document"),
+        }
+    }
+
+    fn write_element(&mut self, node: NodeRef<'_>, elm: &Element)
+        -> io::Result<()>
+    {
+        let scope_len = self.scope.len();
+        let mut decls: Vec<(String, String)> = Vec::new();
+
+        let prefix = elm.name.prefix.as_ref().map_or("", |p| p.as_ref());
+        let prefix = if elm.name.ns.is_empty() {
+            String::new()
+        } else if elm.name.ns.as_ref() == XML_URI {
+            "xml".to_owned()
+        } else if !is_ncname(prefix) {
+            String::new()
+        } else if is_reserved(prefix) {
+            self.prefix_for(&elm.name.ns)
+        } else {
+            prefix.to_owned()
+        };
+        self.bind(&prefix, &elm.name.ns, &mut decls);
+        let qname = qualify(&prefix, &xml_name(&elm.name.local));
+        let mut used = vec![prefix];
+
+        let mut attrs = Vec::with_capacity(elm.attrs.len());
+        for attr in &elm.attrs {
+            if is_xmlns(attr) || !is_ncname(&attr.name.local) {
+                continue;
+            }
+            if attr.name.ns.is_empty() {
+                attrs.push((attr.name.local.to_string(), &attr.value));
+                continue;
+            }
+            let prefix = match attr.name.prefix {
+                Some(ref p) if is_ncname(p) &&
+                    !is_reserved(p) &&
+                    attr.name.ns.as_ref() != XML_URI =>
+                {
+                    let bound = self.lookup(p);
+                    if bound.is_none() || bound == Some(&attr.name.ns) {
+                        p.to_string()
+                    } else {
+                        self.generate_prefix()
+                    }
+                }
+                _ => self.prefix_for(&attr.name.ns),
+            };
+            self.bind(&prefix, &attr.name.ns, &mut decls);
+            attrs.push((qualify(&prefix, &attr.name.local), &attr.value));
+            used.push(prefix);
+        }
+
+        // Preserve any existing, non-conflicting namespace declarations
+        for attr in elm.attrs.iter().filter(|a| is_xmlns(a)) {
+            let prefix = if attr.name.local.as_ref() == "xmlns" {
+                ""
+            } else {
+                attr.name.local.as_ref()
+            };
+            if  is_reserved(prefix) ||
+                attr.value.as_ref() == XML_URI ||
+                attr.value.as_ref() == XMLNS_URI ||
+                used.iter().any(|p| p == prefix) ||
+                (!prefix.is_empty() && attr.value.is_empty())
+            {
+                continue;
+            }
+            self.bind(prefix, &attr.value, &mut decls);
+        }
+
+        self.out.write_all(b"<")?;
+        self.out.write_all(qname.as_bytes())?;
+        for (prefix, uri) in &decls {
+            if prefix.is_empty() {
+                self.out.write_all(b" xmlns=\"")?;
+            } else {
+                self.out.write_all(b" xmlns:")?;
+                self.out.write_all(prefix.as_bytes())?;
+                self.out.write_all(b"=\"")?;
+            }
+            self.write_escaped(uri, true)?;
+            self.out.write_all(b"\"")?;
+        }
+        for (name, value) in attrs {
+            self.out.write_all(b" ")?;
+            self.out.write_all(name.as_bytes())?;
+            self.out.write_all(b"=\"")?;
+            self.write_escaped(value, true)?;
+            self.out.write_all(b"\"")?;
+        }
+
+        if node.first_child.is_none() {
+            let is_html = elm.name.ns == ns::HTML;
+            if !self.opts.xhtml || !is_html {
+                self.out.write_all(b"/>")?;
+            } else if TAG_META.get(&elm.name.local)
+                .map_or(false, |m| m.is_empty())
+            {
+                self.out.write_all(b" />")?;
+            } else {
+                self.out.write_all(b"></")?;
+                self.out.write_all(qname.as_bytes())?;
+                self.out.write_all(b">")?;
+            }
+        } else {
+            self.out.write_all(b">")?;
+            for child in node.children() {
+                self.write_node(child)?;
+            }
+            self.out.write_all(b"</")?;
+            self.out.write_all(qname.as_bytes())?;
+            self.out.write_all(b">")?;
+        }
+
+        self.scope.truncate(scope_len);
+        Ok(())
+    }
+
+    // Ensure prefix is bound to uri, adding to scope and decls if not already
+    // in scope.
+    fn bind(
+        &mut self,
+        prefix: &str,
+        uri: &str,
+        decls: &mut Vec<(String, String)>)
+    {
+        if self.lookup(prefix) != Some(uri) {
+            self.scope.push((prefix.to_owned(), uri.to_owned()));
+            decls.push((prefix.to_owned(), uri.to_owned()));
+        }
+    }
+
+    fn lookup(&self, prefix: &str) -> Option<&str> {
+        self.scope.iter().rev()
+            .find(|(p, _)| p == prefix)
+            .map(|(_, u)| u.as_str())
+    }
+
+    // Return a non-empty prefix in scope for uri, or else a newly generated
+    // prefix (for uri to be bound).
+    fn prefix_for(&mut self, uri: &str) -> String {
+        let found = self.scope.iter().rev()
+            .find(|(p, u)| {
+                !p.is_empty() && u == uri && self.lookup(p) == Some(uri)
+            })
+            .map(|(p, _)| p.clone());
+        found.unwrap_or_else(|| self.generate_prefix())
+    }
+
+    fn generate_prefix(&mut self) -> String {
+        loop {
+            self.generated += 1;
+            let p = format!("ns{}", self.generated);
+            if self.lookup(&p).is_none() {
+                return p;
+            }
+        }
+    }
+
+    fn write_escaped(&mut self, text: &str, attr_mode: bool)
+        -> io::Result<()>
+    {
+        let mut last = 0;
+        for (i, c) in text.char_indices() {
+            let esc: &str = match c {
+                '&' => "&amp;",
+                '<' => "&lt;",
+                '>' if !attr_mode => "&gt;",
+                '"' if attr_mode => "&quot;",
+                '\t' if attr_mode => "&#9;",
+                '\n' if attr_mode => "&#10;",
+                '\r' => "&#13;",
+                c if is_xml_char(c) => continue,
+                _ => "\u{FFFD}",
+            };
+            self.out.write_all(&text.as_bytes()[last..i])?;
+            self.out.write_all(esc.as_bytes())?;
+            last = i + c.len_utf8();
+        }
+        self.out.write_all(&text.as_bytes()[last..])
+    }
+
+    // Write a quoted system literal, preceded by a space.
+    fn write_system_id(&mut self, id: &str) -> io::Result<()> {
+        let (quote, id) = if !id.contains('"') {
+            ('"', Cow::Borrowed(id))
+        } else if !id.contains('\'') {
+            ('\'', Cow::Borrowed(id))
+        } else {
+            ('"', Cow::Owned(id.replace('"', "")))
+        };
+        write!(self.out, " {}", quote)?;
+        self.write_chars(&id)?;
+        write!(self.out, "{}", quote)
+    }
+
+    // Write text without escaping, but replacing chars not allowed in XML.
+    fn write_chars(&mut self, text: &str) -> io::Result<()> {
+        if text.chars().all(is_xml_char) {
+            self.out.write_all(text.as_bytes())
+        } else {
+            let text: String = text.chars()
+                .map(|c| if is_xml_char(c) { c } else { '\u{FFFD}' })
+                .collect();
+            self.out.write_all(text.as_bytes())
+        }
+    }
+}
+
+// True if the prefix is reserved, and may not be declared.
+fn is_reserved(prefix: &str) -> bool {
+    prefix == "xml" || prefix == "xmlns"
+}
+
+fn is_xmlns(attr: &Attribute) -> bool {
+    attr.name.ns.as_ref() == XMLNS_URI ||
+        (attr.name.ns.is_empty() && attr.name.local.as_ref() == "xmlns")
+}
+
+fn qualify(prefix: &str, local: &str) -> String {
+    if prefix.is_empty() {
+        local.to_owned()
+    } else {
+        format!("{}:{}", prefix, local)
+    }
+}
+
+// Return name as a valid XML name (without any `:`), replacing any invalid
+// chars with `_`.
+fn xml_name(name: &str) -> Cow<'_, str> {
+    if is_ncname(name) {
+        return Cow::Borrowed(name);
+    }
+    let mut out = String::with_capacity(name.len() + 1);
+    for (i, c) in name.chars().enumerate() {
+        if i == 0 && !is_ncname_start_char(c) {
+            out.push('_');
+            if is_ncname_char(c) {
+                out.push(c);
+            }
+        } else if is_ncname_char(c) {
+            out.push(c);
+        } else {
+            out.push('_');
+        }
+    }
+    if out.is_empty() {
+        out.push('_');
+    }
+    Cow::Owned(out)
+}
@@ -0,0 +1,919 @@
+//! XPath 1.0 query evaluation over `Document`.
+//!
+//! An [`XPath`] is compiled once from an expression string and may then be
+//! evaluated any number of times, against any [`NodeRef`] context node. The
+//! result is a [`Value`]: a node-set, string, number or boolean.
+//!
+//! ### Namespaces
+//!
+//! Prefixes used in name tests must be bound at compile time, via
+//! [`XPath::compile_ns`]. These are matched against the `Namespace` of each
+//! element or attribute `QualName`, as produced by the XML parser. To work
+//! equally on HTML `Document`s, an unprefixed name test matches elements in
+//! either no namespace or the HTML namespace, while the `*` wildcard matches
+//! elements in any namespace. Unprefixed attribute name tests
+//! only match attributes in no namespace. Note that, as per XPath 1.0, an
+//! element in a (non-HTML) default namespace, declared via `xmlns="..."`, is
+//! only matched by a prefixed name test.
+//!
+//! ### Limitations
+//!
+//! Variable references and the `namespace::` axis are not supported. The
+//! latter always yields an empty node-set.
+//!
+//! ```
+//! use marked::{html, xpath::XPath};
+//!
+//! let doc = html::parse_utf8(
+//!     b"<article><p class=lead>See <a href=/x>this</a></p></article>"
+//! );
+//! let xp = XPath::compile("//article/p[@class='lead'][1]//a/@href")?;
+//! let hrefs: Vec<String> = xp.select(doc.document_node_ref())?
+//!     .iter()
+//!     .map(|n| n.string_value())
+//!     .collect();
+//! assert_eq!(vec!["/x"], hrefs);
+//! # Ok::<(), marked::xpath::XPathError>(())
+//! ```
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::error::Error as StdError;
+use std::f64;
+use std::fmt;
+
+use crate::dom::{
+    html::ns, Attribute, Document, NodeData, NodeRef, NodeStack1,
+};
+
+mod parser;
+
+use self::parser::{
+    ArithOp, Axis, CmpOp, Expr, Function, NodeTest, NsTest, Step
+};
+
+/// A compiled XPath 1.0 expression.
+#[derive(Clone, Debug)]
+pub struct XPath {
+    expr: Expr,
+}
+
+/// A node within an XPath node-set.
+///
+/// Since `Document` does not represent attributes as nodes, these are
+/// referenced by their owning element.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum XNode<'a> {
+    /// A document, element, text, comment or processing instruction node.
+    Node(NodeRef<'a>),
+
+    /// An attribute, by owning element and index into its attributes.
+    Attr(NodeRef<'a>, usize),
+}
+
+/// The result of evaluating an `XPath` expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value<'a> {
+    /// A set of nodes, in document order, without duplicates.
+    NodeSet(Vec<XNode<'a>>),
+
+    /// A string.
+    String(String),
+
+    /// A (IEEE 754 double precision) number.
+    Number(f64),
+
+    /// A boolean.
+    Boolean(bool),
+}
+
+/// An XPath compile or evaluation error.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum XPathError {
+    /// A syntax error at the given byte offset in the expression.
+    Syntax(usize, String),
+
+    /// A name test prefix without a namespace binding.
+    UnknownPrefix(String),
+
+    /// An unknown function, incorrect number of arguments, or other
+    /// unsupported feature.
+    Function(String),
+
+    /// A node-set was required but some other value type was found.
+    Type(String),
+}
+
+impl fmt::Display for XPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XPathError::Syntax(pos, msg) => {
+                write!(f, "XPath syntax error at {}: {}", pos, msg)
+            }
+            XPathError::UnknownPrefix(p) => {
+                write!(f, "XPath namespace prefix '{}' is not bound", p)
+            }
+            XPathError::Function(msg) => write!(f, "XPath: {}", msg),
+            XPathError::Type(msg) => write!(f, "XPath type error: {}", msg),
+        }
+    }
+}
+
+impl StdError for XPathError {}
+
+impl XPath {
+    /// Compile the given expression, without any namespace prefix bindings.
+    pub fn compile(expr: &str) -> Result<XPath, XPathError> {
+        XPath::compile_ns(expr, &[])
+    }
+
+    /// Compile the given expression, with namespace bindings, as a slice of
+    /// `(prefix, namespace URI)` tuples.
+    pub fn compile_ns(expr: &str, namespaces: &[(&str, &str)])
+        -> Result<XPath, XPathError>
+    {
+        Ok(XPath { expr: parser::parse(expr, namespaces)? })
+    }
+
+    /// Evaluate this expression with the given context node.
+    pub fn evaluate<'a>(&self, context: NodeRef<'a>)
+        -> Result<Value<'a>, XPathError>
+    {
+        let eval = Eval { doc: context.doc, order: RefCell::new(None) };
+        let ctx = Context { node: XNode::Node(context), position: 1, size: 1 };
+        eval.expr(&self.expr, &ctx)
+    }
+
+    /// Evaluate this expression with the given context node, returning the
+    /// node-set in document order, or an error if the expression does not
+    /// evaluate to a node-set.
+    pub fn select<'a>(&self, context: NodeRef<'a>)
+        -> Result<Vec<XNode<'a>>, XPathError>
+    {
+        match self.evaluate(context)? {
+            Value::NodeSet(nodes) => Ok(nodes),
+            _ => Err(XPathError::Type("result is not a node-set".to_string())),
+        }
+    }
+}
+
+impl<'a> XNode<'a> {
+    /// Return the `NodeRef` for this node, or the owning element of an
+    /// attribute.
+    pub fn node_ref(&self) -> NodeRef<'a> {
+        match *self {
+            XNode::Node(n) | XNode::Attr(n, _) => n,
+        }
+    }
+
+    /// Return the attribute, if this is an attribute node.
+    pub fn attr(&self) -> Option<&'a Attribute> {
+        match *self {
+            XNode::Node(_) => None,
+            XNode::Attr(n, i) => {
+                n.doc[n.id].as_element().map(|e| &e.attrs[i])
+            }
+        }
+    }
+
+    /// Return the XPath string-value of this node.
+    ///
+    /// For the document or element nodes, this is the concatenation of all
+    /// descendant text. For attributes it is the attribute value.
+    pub fn string_value(&self) -> String {
+        match *self {
+            XNode::Attr(..) => self.attr().unwrap().value.to_string(),
+            XNode::Node(n) => match &n.data {
+                NodeData::Document | NodeData::Elem(_) => {
+                    n.text().map_or_else(String::new, |t| t.to_string())
+                }
+                NodeData::Text(t) | NodeData::Comment(t) => t.to_string(),
+                NodeData::Pi(pi) => pi.data.to_string(),
+                NodeData::DocType(_) | NodeData::Hole => String::new(),
+            }
+        }
+    }
+}
+
+impl<'a> Value<'a> {
+    /// Convert to a string as per the XPath `string()` function.
+    pub fn as_string(&self) -> String {
+        match self {
+            Value::NodeSet(nodes) => {
+                nodes.first().map_or_else(String::new, |n| n.string_value())
+            }
+            Value::String(s) => s.clone(),
+            Value::Number(n) => number_to_string(*n),
+            Value::Boolean(b) => b.to_string(),
+        }
+    }
+
+    /// Convert to a number as per the XPath `number()` function.
+    pub fn as_number(&self) -> f64 {
+        match self {
+            Value::NodeSet(_) => string_to_number(&self.as_string()),
+            Value::String(s) => string_to_number(s),
+            Value::Number(n) => *n,
+            Value::Boolean(b) => if *b { 1.0 } else { 0.0 },
+        }
+    }
+
+    /// Convert to a boolean as per the XPath `boolean()` function.
+    pub fn as_bool(&self) -> bool {
+        match self {
+            Value::NodeSet(nodes) => !nodes.is_empty(),
+            Value::String(s) => !s.is_empty(),
+            Value::Number(n) => *n != 0.0 && !n.is_nan(),
+            Value::Boolean(b) => *b,
+        }
+    }
+}
+
+struct Context<'a> {
+    node: XNode<'a>,
+    position: usize,
+    size: usize,
+}
+
+struct Eval<'a> {
+    doc: &'a Document,
+    // Lazily computed document order position, indexed by NodeId
+    order: RefCell<Option<Vec<u32>>>,
+}
+
+impl<'a> Eval<'a> {
+    fn expr(&self, expr: &Expr, ctx: &Context<'a>)
+        -> Result<Value<'a>, XPathError>
+    {
+        let val = match expr {
+            Expr::Or(operands) => {
+                let mut val = false;
+                for e in operands {
+                    if self.expr(e, ctx)?.as_bool() {
+                        val = true;
+                        break;
+                    }
+                }
+                Value::Boolean(val)
+            }
+            Expr::And(operands) => {
+                let mut val = true;
+                for e in operands {
+                    if !self.expr(e, ctx)?.as_bool() {
+                        val = false;
+                        break;
+                    }
+                }
+                Value::Boolean(val)
+            }
+            Expr::Compare(first, rest) => {
+                let mut l = self.expr(first, ctx)?;
+                for (op, r) in rest {
+                    let r = self.expr(r, ctx)?;
+                    l = Value::Boolean(compare(*op, &l, &r));
+                }
+                l
+            }
+            Expr::Arith(first, rest) => {
+                let mut l = self.expr(first, ctx)?.as_number();
+                for (op, r) in rest {
+                    let r = self.expr(r, ctx)?.as_number();
+                    l = match op {
+                        ArithOp::Add => l + r,
+                        ArithOp::Sub => l - r,
+                        ArithOp::Mul => l * r,
+                        ArithOp::Div => l / r,
+                        ArithOp::Mod => l % r,
+                    };
+                }
+                Value::Number(l)
+            }
+            Expr::Neg(e) => Value::Number(-self.expr(e, ctx)?.as_number()),
+            Expr::Union(operands) => {
+                let mut nodes = Vec::new();
+                for e in operands {
+                    nodes.extend(self.node_set(e, ctx)?);
+                }
+                self.sort_unique(&mut nodes);
+                Value::NodeSet(nodes)
+            }
+            Expr::Literal(s) => Value::String(s.clone()),
+            Expr::Number(n) => Value::Number(*n),
+            Expr::Function(f, args) => self.function(*f, args, ctx)?,
+            Expr::Filter(primary, predicates) => {
+                let mut nodes = self.node_set(primary, ctx)?;
+                for p in predicates {
+                    nodes = self.predicate(nodes, p)?;
+                }
+                Value::NodeSet(nodes)
+            }
+            Expr::Path(start, steps) => {
+                let nodes = if let Some(start) = start {
+                    self.node_set(start, ctx)?
+                } else {
+                    vec![ctx.node]
+                };
+                Value::NodeSet(self.steps(nodes, steps)?)
+            }
+            Expr::Root(steps) => {
+                let root = NodeRef::new(self.doc, Document::DOCUMENT_NODE_ID);
+                Value::NodeSet(self.steps(vec![XNode::Node(root)], steps)?)
+            }
+        };
+        Ok(val)
+    }
+
+    fn node_set(&self, expr: &Expr, ctx: &Context<'a>)
+        -> Result<Vec<XNode<'a>>, XPathError>
+    {
+        match self.expr(expr, ctx)? {
+            Value::NodeSet(nodes) => Ok(nodes),
+            v => Err(XPathError::Type(format!(
+                "expected node-set, found {:?}", v
+            ))),
+        }
+    }
+
+    fn steps(&self, mut nodes: Vec<XNode<'a>>, steps: &[Step])
+        -> Result<Vec<XNode<'a>>, XPathError>
+    {
+        for step in steps {
+            let mut out = Vec::new();
+            for node in &nodes {
+                let mut found = Vec::new();
+                self.axis(step.axis, *node, &mut found);
+                found.retain(|n| self.node_test(step.axis, &step.test, n));
+                if step.axis.is_reverse() {
+                    // Predicates are applied in proximity (reverse) order
+                    found.reverse();
+                }
+                for p in &step.predicates {
+                    found = self.predicate(found, p)?;
+                }
+                out.extend(found);
+            }
+            self.sort_unique(&mut out);
+            nodes = out;
+        }
+        Ok(nodes)
+    }
+
+    fn predicate(&self, nodes: Vec<XNode<'a>>, pred: &Expr)
+        -> Result<Vec<XNode<'a>>, XPathError>
+    {
+        let size = nodes.len();
+        let mut out = Vec::with_capacity(size);
+        for (i, node) in nodes.into_iter().enumerate() {
+            let ctx = Context { node, position: i + 1, size };
+            let keep = match self.expr(pred, &ctx)? {
+                Value::Number(n) => n == (i + 1) as f64,
+                v => v.as_bool(),
+            };
+            if keep {
+                out.push(node);
+            }
+        }
+        Ok(out)
+    }
+
+    // Push nodes on the given axis, in document order.
+    fn axis(&self, axis: Axis, node: XNode<'a>, out: &mut Vec<XNode<'a>>) {
+        let nref = match node {
+            XNode::Node(n) => n,
+            XNode::Attr(elm, _) => {
+                // Attributes have a parent, ancestors and self, but no
+                // children or siblings. Following and preceding are relative
+                // to the owning element.
+                match axis {
+                    Axis::Self_ | Axis::AncestorOrSelf => {
+                        if axis == Axis::AncestorOrSelf {
+                            self.ancestors(elm, true, out);
+                        }
+                        out.push(node);
+                    }
+                    Axis::Parent => out.push(XNode::Node(elm)),
+                    Axis::Ancestor => self.ancestors(elm, true, out),
+                    Axis::Following => {
+                        for d in elm.descendants().skip(1) {
+                            out.push(XNode::Node(d));
+                        }
+                        self.following(elm, out);
+                    }
+                    Axis::Preceding => self.preceding(elm, out),
+                    _ => {}
+                }
+                return;
+            }
+        };
+
+        match axis {
+            Axis::Child => {
+                out.extend(nref.children().map(XNode::Node));
+            }
+            Axis::Descendant => {
+                out.extend(nref.descendants().skip(1).map(XNode::Node));
+            }
+            Axis::DescendantOrSelf => {
+                out.extend(nref.descendants().map(XNode::Node));
+            }
+            Axis::Parent => {
+                if let Some(p) = nref.parent() {
+                    out.push(XNode::Node(p));
+                }
+            }
+            Axis::Ancestor => {
+                if let Some(p) = nref.parent() {
+                    self.ancestors(p, true, out);
+                }
+            }
+            Axis::AncestorOrSelf => self.ancestors(nref, true, out),
+            Axis::FollowingSibling => {
+                let mut next = nref.next_sibling();
+                while let Some(n) = next {
+                    out.push(XNode::Node(n));
+                    next = n.next_sibling();
+                }
+            }
+            Axis::PrecedingSibling => {
+                let mut prev = nref.prev_sibling();
+                while let Some(n) = prev {
+                    out.push(XNode::Node(n));
+                    prev = n.prev_sibling();
+                }
+                out.reverse();
+            }
+            Axis::Following => self.following(nref, out),
+            Axis::Preceding => self.preceding(nref, out),
+            Axis::Attribute => {
+                if let Some(elm) = nref.as_element() {
+                    for i in 0..elm.attrs.len() {
+                        out.push(XNode::Attr(nref, i));
+                    }
+                }
+            }
+            Axis::Self_ => out.push(node),
+            Axis::Namespace => {}
+        }
+    }
+
+    // Push node (if inclusive) and all ancestors, in document order.
+    fn ancestors(&self, node: NodeRef<'a>, inclusive: bool,
+                 out: &mut Vec<XNode<'a>>)
+    {
+        let start = out.len();
+        let mut next = if inclusive { Some(node) } else { node.parent() };
+        while let Some(n) = next {
+            out.push(XNode::Node(n));
+            next = n.parent();
+        }
+        out[start..].reverse();
+    }
+
+    // Push all nodes following (excluding descendants of) node.
+    fn following(&self, node: NodeRef<'a>, out: &mut Vec<XNode<'a>>) {
+        let mut cur = Some(node);
+        while let Some(c) = cur {
+            let mut next = c.next_sibling();
+            while let Some(s) = next {
+                out.extend(s.descendants().map(XNode::Node));
+                next = s.next_sibling();
+            }
+            cur = c.parent();
+        }
+    }
+
+    // Push all nodes preceding (excluding ancestors of) node, in document
+    // order.
+    fn preceding(&self, node: NodeRef<'a>, out: &mut Vec<XNode<'a>>) {
+        let start = out.len();
+        let mut cur = Some(node);
+        while let Some(c) = cur {
+            let mut prev = c.prev_sibling();
+            while let Some(s) = prev {
+                let mut sub: Vec<_> = s.descendants()
+                    .map(XNode::Node)
+                    .collect();
+                sub.reverse();
+                out.extend(sub);
+                prev = s.prev_sibling();
+            }
+            cur = c.parent();
+        }
+        out[start..].reverse();
+    }
+
+    fn node_test(&self, axis: Axis, test: &NodeTest, node: &XNode<'a>)
+        -> bool
+    {
+        match node {
+            XNode::Attr(..) => {
+                let attr = node.attr().unwrap();
+                match test {
+                    NodeTest::Node => true,
+                    NodeTest::Name(nst, local) => {
+                        (match nst {
+                            NsTest::Any => true,
+                            NsTest::Default => attr.name.ns == ns!(),
+                            NsTest::Specific(ns) => attr.name.ns == *ns,
+                        }) && local.as_ref()
+                            .map_or(true, |l| attr.name.local == *l)
+                    }
+                    _ => false,
+                }
+            }
+            XNode::Node(n) => match test {
+                NodeTest::Node => true,
+                NodeTest::Text => n.as_text().is_some(),
+                NodeTest::Comment => {
+                    if let NodeData::Comment(_) = n.data { true } else { false }
+                }
+                NodeTest::Pi(target) => {
+                    if let NodeData::Pi(pi) = &n.data {
+                        target.as_ref()
+                            .map_or(true, |t| pi.target.as_ref() == t)
+                    } else {
+                        false
+                    }
+                }
+                NodeTest::Name(nst, local) => {
+                    // Principal node type of all axes here (attribute is
+                    // handled above) is element.
+                    debug_assert!(axis != Axis::Attribute);
+                    if let Some(elm) = n.as_element() {
+                        (match nst {
+                            NsTest::Any => true,
+                            NsTest::Default => {
+                                elm.name.ns == ns!() || elm.name.ns == ns::HTML
+                            }
+                            NsTest::Specific(ns) => elm.name.ns == *ns,
+                        }) && local.as_ref()
+                            .map_or(true, |l| elm.name.local == *l)
+                    } else {
+                        false
+                    }
+                }
+            }
+        }
+    }
+
+    fn function(&self, f: Function, args: &[Expr], ctx: &Context<'a>)
+        -> Result<Value<'a>, XPathError>
+    {
+        use self::Function as F;
+
+        let val = match f {
+            F::Last => Value::Number(ctx.size as f64),
+            F::Position => Value::Number(ctx.position as f64),
+            F::Count => {
+                Value::Number(self.node_set(&args[0], ctx)?.len() as f64)
+            }
+            F::Id => {
+                let ids = match self.expr(&args[0], ctx)? {
+                    Value::NodeSet(nodes) => nodes
+                        .iter()
+                        .map(|n| n.string_value())
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    v => v.as_string(),
+                };
+                let ids: Vec<&str> = ids.split_whitespace().collect();
+                let mut out: Vec<_> = NodeRef::new(
+                    self.doc, Document::DOCUMENT_NODE_ID
+                )
+                    .descendants()
+                    .filter(|n| {
+                        n.attr("id")
+                            .map_or(false, |v| ids.contains(&v.as_ref()))
+                    })
+                    .map(XNode::Node)
+                    .collect();
+                self.sort_unique(&mut out);
+                Value::NodeSet(out)
+            }
+            F::LocalName | F::NamespaceUri | F::Name => {
+                let node = if args.is_empty() {
+                    Some(ctx.node)
+                } else {
+                    let mut nodes = self.node_set(&args[0], ctx)?;
+                    self.sort_unique(&mut nodes);
+                    nodes.first().cloned()
+                };
+                let name = node.and_then(|n| match n {
+                    XNode::Attr(..) => Some(n.attr().unwrap().name.clone()),
+                    XNode::Node(n) => n.as_element().map(|e| e.name.clone()),
+                });
+                Value::String(match name {
+                    None => {
+                        if let Some(XNode::Node(n)) = node {
+                            if let NodeData::Pi(pi) = &n.data {
+                                if f != F::NamespaceUri {
+                                    pi.target.to_string()
+                                } else {
+                                    String::new()
+                                }
+                            } else {
+                                String::new()
+                            }
+                        } else {
+                            String::new()
+                        }
+                    }
+                    Some(q) => match f {
+                        F::LocalName => q.local.to_string(),
+                        F::NamespaceUri => q.ns.to_string(),
+                        _ => match q.prefix {
+                            Some(p) if !p.is_empty() => {
+                                format!("{}:{}", p, q.local)
+                            }
+                            _ => q.local.to_string(),
+                        }
+                    }
+                })
+            }
+            F::String => Value::String(
+                self.arg_or_context(args, ctx)?.as_string()
+            ),
+            F::Concat => {
+                let mut s = String::new();
+                for a in args {
+                    s.push_str(&self.expr(a, ctx)?.as_string());
+                }
+                Value::String(s)
+            }
+            F::StartsWith => {
+                let (a, b) = self.two_strings(args, ctx)?;
+                Value::Boolean(a.starts_with(&b))
+            }
+            F::Contains => {
+                let (a, b) = self.two_strings(args, ctx)?;
+                Value::Boolean(a.contains(&b))
+            }
+            F::SubstringBefore => {
+                let (a, b) = self.two_strings(args, ctx)?;
+                Value::String(match a.find(&b) {
+                    Some(i) => a[..i].to_string(),
+                    None => String::new(),
+                })
+            }
+            F::SubstringAfter => {
+                let (a, b) = self.two_strings(args, ctx)?;
+                Value::String(match a.find(&b) {
+                    Some(i) => a[i + b.len()..].to_string(),
+                    None => String::new(),
+                })
+            }
+            F::Substring => {
+                let s = self.expr(&args[0], ctx)?.as_string();
+                let start = round(self.expr(&args[1], ctx)?.as_number());
+                let end = if let Some(len) = args.get(2) {
+                    start + round(self.expr(len, ctx)?.as_number())
+                } else {
+                    f64::INFINITY
+                };
+                Value::String(
+                    s.chars()
+                        .enumerate()
+                        .filter(|(i, _)| {
+                            let p = (i + 1) as f64;
+                            p >= start && p < end
+                        })
+                        .map(|(_, c)| c)
+                        .collect()
+                )
+            }
+            F::StringLength => Value::Number(
+                self.arg_or_context(args, ctx)?
+                    .as_string()
+                    .chars()
+                    .count() as f64
+            ),
+            F::NormalizeSpace => {
+                let s = self.arg_or_context(args, ctx)?.as_string();
+                Value::String(
+                    s.split(|c| c == ' ' || c == '\t' || c == '\r' || c == '\n')
+                        .filter(|w| !w.is_empty())
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                )
+            }
+            F::Translate => {
+                let s = self.expr(&args[0], ctx)?.as_string();
+                let from: Vec<char> = self.expr(&args[1], ctx)?
+                    .as_string()
+                    .chars()
+                    .collect();
+                let to: Vec<char> = self.expr(&args[2], ctx)?
+                    .as_string()
+                    .chars()
+                    .collect();
+                Value::String(
+                    s.chars()
+                        .filter_map(|c| {
+                            match from.iter().position(|f| *f == c) {
+                                Some(i) => to.get(i).cloned(),
+                                None => Some(c),
+                            }
+                        })
+                        .collect()
+                )
+            }
+            F::Boolean => Value::Boolean(self.expr(&args[0], ctx)?.as_bool()),
+            F::Not => Value::Boolean(!self.expr(&args[0], ctx)?.as_bool()),
+            F::True => Value::Boolean(true),
+            F::False => Value::Boolean(false),
+            F::Lang => {
+                let lang = self.expr(&args[0], ctx)?.as_string().to_lowercase();
+                let found = self.doc
+                    .node_and_ancestors(ctx.node.node_ref().id())
+                    .filter_map(|id| {
+                        self.doc[id].as_element().and_then(|e| {
+                            e.attrs.iter()
+                                .find(|a| a.name.local.as_ref() == "lang" && (
+                                    a.name.ns == ns!(xml) || a.name.ns == ns!()
+                                ))
+                                .map(|a| a.value.to_lowercase())
+                        })
+                    })
+                    .next();
+                Value::Boolean(found.map_or(false, |l| {
+                    l == lang || l.starts_with(&format!("{}-", lang))
+                }))
+            }
+            F::Number => Value::Number(
+                self.arg_or_context(args, ctx)?.as_number()
+            ),
+            F::Sum => Value::Number(
+                self.node_set(&args[0], ctx)?
+                    .iter()
+                    .map(|n| string_to_number(&n.string_value()))
+                    .sum()
+            ),
+            F::Floor => Value::Number(
+                self.expr(&args[0], ctx)?.as_number().floor()
+            ),
+            F::Ceiling => Value::Number(
+                self.expr(&args[0], ctx)?.as_number().ceil()
+            ),
+            F::Round => Value::Number(
+                round(self.expr(&args[0], ctx)?.as_number())
+            ),
+        };
+        Ok(val)
+    }
+
+    fn arg_or_context(&self, args: &[Expr], ctx: &Context<'a>)
+        -> Result<Value<'a>, XPathError>
+    {
+        if let Some(a) = args.first() {
+            self.expr(a, ctx)
+        } else {
+            Ok(Value::NodeSet(vec![ctx.node]))
+        }
+    }
+
+    fn two_strings(&self, args: &[Expr], ctx: &Context<'a>)
+        -> Result<(String, String), XPathError>
+    {
+        Ok((
+            self.expr(&args[0], ctx)?.as_string(),
+            self.expr(&args[1], ctx)?.as_string()
+        ))
+    }
+
+    // Sort nodes in document order and remove duplicates.
+    fn sort_unique(&self, nodes: &mut Vec<XNode<'a>>) {
+        if nodes.len() < 2 {
+            return;
+        }
+        let mut order = self.order.borrow_mut();
+        if order.is_none() {
+            *order = Some(document_order(self.doc));
+        }
+        let order = order.as_ref().unwrap();
+        let key = |n: &XNode<'a>| -> (u32, usize) {
+            match *n {
+                XNode::Node(n) => (order[n.id.0.get() as usize], 0),
+                XNode::Attr(n, i) => (order[n.id.0.get() as usize], i + 1),
+            }
+        };
+        nodes.sort_by_key(key);
+        nodes.dedup_by(|a, b| key(a) == key(b) && a == b);
+    }
+}
+
+// Return document order positions, indexed by `NodeId`. Nodes not reachable
+// from the document node are ordered last.
+fn document_order(doc: &Document) -> Vec<u32> {
+    let mut order = vec![std::u32::MAX; doc.nodes.len()];
+    let mut ns = NodeStack1::new();
+    ns.push_if(Some(Document::DOCUMENT_NODE_ID));
+    let mut pos = 0;
+    while let Some(id) = ns.pop() {
+        order[id.0.get() as usize] = pos;
+        pos += 1;
+        let node = &doc[id];
+        if id != Document::DOCUMENT_NODE_ID {
+            ns.push_if(node.next_sibling);
+        }
+        ns.push_if(node.first_child);
+    }
+    order
+}
+
+fn compare(op: CmpOp, l: &Value<'_>, r: &Value<'_>) -> bool {
+    match (l, r) {
+        (Value::NodeSet(ln), Value::NodeSet(rn)) => {
+            let rs: Vec<String> = rn.iter().map(|n| n.string_value()).collect();
+            ln.iter().any(|n| {
+                let ls = n.string_value();
+                rs.iter().any(|rs| compare_atomic(
+                    op,
+                    &Value::String(ls.clone()),
+                    &Value::String(rs.clone())
+                ))
+            })
+        }
+        (Value::NodeSet(nodes), other) => {
+            if let Value::Boolean(_) = other {
+                return compare_atomic(op, &Value::Boolean(l.as_bool()), other);
+            }
+            nodes.iter().any(|n| {
+                compare_atomic(op, &Value::String(n.string_value()), other)
+            })
+        }
+        (other, Value::NodeSet(nodes)) => {
+            if let Value::Boolean(_) = other {
+                return compare_atomic(op, other, &Value::Boolean(r.as_bool()));
+            }
+            nodes.iter().any(|n| {
+                compare_atomic(op, other, &Value::String(n.string_value()))
+            })
+        }
+        _ => compare_atomic(op, l, r),
+    }
+}
+
+fn compare_atomic(op: CmpOp, l: &Value<'_>, r: &Value<'_>) -> bool {
+    match op {
+        CmpOp::Eq | CmpOp::Ne => {
+            let eq = match (l, r) {
+                (Value::Boolean(_), _) | (_, Value::Boolean(_)) => {
+                    l.as_bool() == r.as_bool()
+                }
+                (Value::Number(_), _) | (_, Value::Number(_)) => {
+                    l.as_number() == r.as_number()
+                }
+                _ => l.as_string() == r.as_string(),
+            };
+            if op == CmpOp::Eq { eq } else { !eq }
+        }
+        _ => {
+            let ord = l.as_number().partial_cmp(&r.as_number());
+            match (op, ord) {
+                (_, None) => false,
+                (CmpOp::Lt, Some(o)) => o == Ordering::Less,
+                (CmpOp::Le, Some(o)) => o != Ordering::Greater,
+                (CmpOp::Gt, Some(o)) => o == Ordering::Greater,
+                (CmpOp::Ge, Some(o)) => o != Ordering::Less,
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+fn round(n: f64) -> f64 {
+    if n.is_nan() || n.is_infinite() || n == 0.0 {
+        n
+    } else if (-0.5..0.0).contains(&n) {
+        -0.0
+    } else {
+        (n + 0.5).floor()
+    }
+}
+
+// Convert as per XPath `number()`: optional whitespace, optional minus,
+// digits with optional decimal point. Anything else is NaN.
+fn string_to_number(s: &str) -> f64 {
+    let s = s.trim_matches(|c| c == ' ' || c == '\t' || c == '\r' || c == '\n');
+    let digits = if s.starts_with('-') { &s[1..] } else { s };
+    if  digits.is_empty() ||
+        digits == "." ||
+        !digits.chars().all(|c| c.is_ascii_digit() || c == '.') ||
+        digits.matches('.').count() > 1
+    {
+        return f64::NAN;
+    }
+    s.parse().unwrap_or(f64::NAN)
+}
+
+fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if n == 0.0 {
+        "0".to_string()
+    } else {
+        n.to_string()
+    }
+}
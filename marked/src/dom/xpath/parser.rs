@@ -0,0 +1,914 @@
+//! XPath 1.0 expression tokenizer and recursive descent parser.
+
+use crate::dom::{LocalName, Namespace};
+use super::XPathError;
+
+/// Parsed expression tree.
+#[derive(Clone, Debug)]
+pub(super) enum Expr {
+    // Operator chains are kept flat, rather than as nested binary
+    // expressions, so that long chains don't deepen the tree.
+    Or(Vec<Expr>),
+    And(Vec<Expr>),
+    /// A first operand, then each left associative comparison.
+    Compare(Box<Expr>, Vec<(CmpOp, Expr)>),
+    /// A first operand, then each left associative operation.
+    Arith(Box<Expr>, Vec<(ArithOp, Expr)>),
+    Neg(Box<Expr>),
+    Union(Vec<Expr>),
+    Literal(String),
+    Number(f64),
+    Function(Function, Vec<Expr>),
+    /// A primary expression with predicates.
+    Filter(Box<Expr>, Vec<Expr>),
+    /// An optional filter expression start, then location steps.
+    Path(Option<Box<Expr>>, Vec<Step>),
+    /// An absolute location path from the document node.
+    Root(Vec<Step>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) enum CmpOp { Eq, Ne, Lt, Le, Gt, Ge }
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) enum ArithOp { Add, Sub, Mul, Div, Mod }
+
+#[derive(Clone, Debug)]
+pub(super) struct Step {
+    pub axis: Axis,
+    pub test: NodeTest,
+    pub predicates: Vec<Expr>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) enum Axis {
+    Ancestor,
+    AncestorOrSelf,
+    Attribute,
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Following,
+    FollowingSibling,
+    Namespace,
+    Parent,
+    Preceding,
+    PrecedingSibling,
+    Self_,
+}
+
+impl Axis {
+    /// True for axes where proximity position is in reverse document order.
+    pub fn is_reverse(self) -> bool {
+        match self {
+            Axis::Ancestor | Axis::AncestorOrSelf |
+            Axis::Preceding | Axis::PrecedingSibling => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(super) enum NodeTest {
+    /// `node()`
+    Node,
+    /// `text()`
+    Text,
+    /// `comment()`
+    Comment,
+    /// `processing-instruction()`, with any literal argument
+    Pi(Option<String>),
+    /// A name test, where `None` is the `*` wildcard.
+    Name(NsTest, Option<LocalName>),
+}
+
+#[derive(Clone, Debug)]
+pub(super) enum NsTest {
+    /// The unprefixed `*` wildcard, matching any namespace.
+    Any,
+    /// An unprefixed name test, which matches elements in no namespace or the
+    /// HTML namespace, and attributes in no namespace.
+    Default,
+    /// A namespace bound via a prefix.
+    Specific(Namespace),
+}
+
+/// Core function library.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) enum Function {
+    Last,
+    Position,
+    Count,
+    Id,
+    LocalName,
+    NamespaceUri,
+    Name,
+    String,
+    Concat,
+    StartsWith,
+    Contains,
+    SubstringBefore,
+    SubstringAfter,
+    Substring,
+    StringLength,
+    NormalizeSpace,
+    Translate,
+    Boolean,
+    Not,
+    True,
+    False,
+    Lang,
+    Number,
+    Sum,
+    Floor,
+    Ceiling,
+    Round,
+}
+
+impl Function {
+    fn lookup(name: &str) -> Option<(Function, usize, Option<usize>)> {
+        use Function::*;
+        // (function, minimum args, maximum args or None if unbounded)
+        let f = match name {
+            "last"              => (Last, 0, Some(0)),
+            "position"          => (Position, 0, Some(0)),
+            "count"             => (Count, 1, Some(1)),
+            "id"                => (Id, 1, Some(1)),
+            "local-name"        => (LocalName, 0, Some(1)),
+            "namespace-uri"     => (NamespaceUri, 0, Some(1)),
+            "name"              => (Name, 0, Some(1)),
+            "string"            => (String, 0, Some(1)),
+            "concat"            => (Concat, 2, None),
+            "starts-with"       => (StartsWith, 2, Some(2)),
+            "contains"          => (Contains, 2, Some(2)),
+            "substring-before"  => (SubstringBefore, 2, Some(2)),
+            "substring-after"   => (SubstringAfter, 2, Some(2)),
+            "substring"         => (Substring, 2, Some(3)),
+            "string-length"     => (StringLength, 0, Some(1)),
+            "normalize-space"   => (NormalizeSpace, 0, Some(1)),
+            "translate"         => (Translate, 3, Some(3)),
+            "boolean"           => (Boolean, 1, Some(1)),
+            "not"               => (Not, 1, Some(1)),
+            "true"              => (True, 0, Some(0)),
+            "false"             => (False, 0, Some(0)),
+            "lang"              => (Lang, 1, Some(1)),
+            "number"            => (Number, 0, Some(1)),
+            "sum"               => (Sum, 1, Some(1)),
+            "floor"             => (Floor, 1, Some(1)),
+            "ceiling"           => (Ceiling, 1, Some(1)),
+            "round"             => (Round, 1, Some(1)),
+            _ => return None,
+        };
+        Some(f)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Dot,
+    DotDot,
+    At,
+    Comma,
+    ColonColon,
+    Slash,
+    DoubleSlash,
+    Pipe,
+    Plus,
+    Minus,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Multiply operator (`*` in operator position)
+    Multiply,
+    And,
+    Or,
+    Mod,
+    Div,
+    Literal(String),
+    Number(f64),
+    Variable(String),
+    /// A name test: optional prefix and local name or `*`.
+    Name(Option<String>, String),
+    FunctionName(String),
+    NodeType(String),
+    AxisName(String),
+}
+
+impl Token {
+    // Per XPath 1.0 § 3.7 lexical structure: if there is a preceding token
+    // and it is not one of these, then `*` is a multiply operator and an
+    // NCName is an operator name.
+    fn allows_name_after(&self) -> bool {
+        match self {
+            Token::At | Token::ColonColon | Token::LParen | Token::LBracket |
+            Token::Comma | Token::Slash | Token::DoubleSlash | Token::Pipe |
+            Token::Plus | Token::Minus | Token::Eq | Token::Ne | Token::Lt |
+            Token::Le | Token::Gt | Token::Ge | Token::Multiply | Token::And |
+            Token::Or | Token::Mod | Token::Div => true,
+            _ => false,
+        }
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.' ||
+        c == '\u{B7}'
+}
+
+fn is_xml_space(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\r' || c == '\n'
+}
+
+struct Lexer<'s> {
+    input: &'s str,
+    pos: usize,
+    tokens: Vec<(usize, Token)>,
+}
+
+impl<'s> Lexer<'s> {
+    fn peek_char(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn peek_char_at(&self, offset: usize) -> Option<char> {
+        self.input[self.pos..].chars().nth(offset)
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek_char() {
+            if is_xml_space(c) {
+                self.pos += c.len_utf8();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn name_operator_context(&self) -> bool {
+        match self.tokens.last() {
+            Some((_, t)) => !t.allows_name_after(),
+            None => false,
+        }
+    }
+
+    fn ncname(&mut self) -> &'s str {
+        let start = self.pos;
+        while let Some(c) = self.peek_char() {
+            if is_name_char(c) {
+                self.pos += c.len_utf8();
+            } else {
+                break;
+            }
+        }
+        &self.input[start..self.pos]
+    }
+
+    // Return the next non-space char, after the current position.
+    fn next_significant(&self) -> Option<char> {
+        self.input[self.pos..].chars().find(|c| !is_xml_space(*c))
+    }
+
+    fn error<T>(&self, pos: usize, msg: &str) -> Result<T, XPathError> {
+        Err(XPathError::Syntax(pos, msg.to_string()))
+    }
+
+    fn tokenize(mut self) -> Result<Vec<(usize, Token)>, XPathError> {
+        loop {
+            self.skip_space();
+            let start = self.pos;
+            let c = match self.peek_char() {
+                Some(c) => c,
+                None => break,
+            };
+            let tok = match c {
+                '(' => { self.pos += 1; Token::LParen }
+                ')' => { self.pos += 1; Token::RParen }
+                '[' => { self.pos += 1; Token::LBracket }
+                ']' => { self.pos += 1; Token::RBracket }
+                '@' => { self.pos += 1; Token::At }
+                ',' => { self.pos += 1; Token::Comma }
+                '|' => { self.pos += 1; Token::Pipe }
+                '+' => { self.pos += 1; Token::Plus }
+                '-' => { self.pos += 1; Token::Minus }
+                '=' => { self.pos += 1; Token::Eq }
+                '!' => {
+                    if self.peek_char_at(1) == Some('=') {
+                        self.pos += 2;
+                        Token::Ne
+                    } else {
+                        return self.error(start, "expected '!='");
+                    }
+                }
+                '<' => {
+                    if self.peek_char_at(1) == Some('=') {
+                        self.pos += 2;
+                        Token::Le
+                    } else {
+                        self.pos += 1;
+                        Token::Lt
+                    }
+                }
+                '>' => {
+                    if self.peek_char_at(1) == Some('=') {
+                        self.pos += 2;
+                        Token::Ge
+                    } else {
+                        self.pos += 1;
+                        Token::Gt
+                    }
+                }
+                '/' => {
+                    if self.peek_char_at(1) == Some('/') {
+                        self.pos += 2;
+                        Token::DoubleSlash
+                    } else {
+                        self.pos += 1;
+                        Token::Slash
+                    }
+                }
+                ':' => {
+                    if self.peek_char_at(1) == Some(':') {
+                        self.pos += 2;
+                        Token::ColonColon
+                    } else {
+                        return self.error(start, "unexpected ':'");
+                    }
+                }
+                '"' | '\'' => {
+                    self.pos += 1;
+                    match self.input[self.pos..].find(c) {
+                        Some(end) => {
+                            let lit = &self.input[self.pos..self.pos + end];
+                            self.pos += end + 1;
+                            Token::Literal(lit.to_string())
+                        }
+                        None => {
+                            return self.error(start, "unterminated literal");
+                        }
+                    }
+                }
+                '.' => {
+                    match self.peek_char_at(1) {
+                        Some('.') => { self.pos += 2; Token::DotDot }
+                        Some(d) if d.is_ascii_digit() => self.number(),
+                        _ => { self.pos += 1; Token::Dot }
+                    }
+                }
+                '$' => {
+                    self.pos += 1;
+                    let name = self.ncname();
+                    if name.is_empty() {
+                        return self.error(start, "expected variable name");
+                    }
+                    Token::Variable(name.to_string())
+                }
+                '*' => {
+                    self.pos += 1;
+                    if self.name_operator_context() {
+                        Token::Multiply
+                    } else {
+                        Token::Name(None, "*".to_string())
+                    }
+                }
+                d if d.is_ascii_digit() => self.number(),
+                n if is_name_start(n) => {
+                    let name = self.ncname();
+                    if self.name_operator_context() {
+                        match name {
+                            "and" => Token::And,
+                            "or"  => Token::Or,
+                            "mod" => Token::Mod,
+                            "div" => Token::Div,
+                            _ => {
+                                return self.error(
+                                    start, "expected operator name");
+                            }
+                        }
+                    } else {
+                        self.name_token(start, name)?
+                    }
+                }
+                _ => return self.error(start, "unexpected character"),
+            };
+            self.tokens.push((start, tok));
+        }
+        Ok(self.tokens)
+    }
+
+    fn number(&mut self) -> Token {
+        let start = self.pos;
+        let mut dot = false;
+        while let Some(c) = self.peek_char() {
+            if c.is_ascii_digit() || (c == '.' && !dot) {
+                dot = dot || c == '.';
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        Token::Number(self.input[start..self.pos].parse().unwrap_or(0.0))
+    }
+
+    // Lex the remainder of a QName, NameTest, FunctionName, NodeType or
+    // AxisName, given the first NCName.
+    fn name_token(&mut self, start: usize, name: &'s str)
+        -> Result<Token, XPathError>
+    {
+        // Check for a prefixed name, `prefix:local` or `prefix:*`
+        if self.peek_char() == Some(':') && self.peek_char_at(1) != Some(':') {
+            self.pos += 1;
+            let local = if self.peek_char() == Some('*') {
+                self.pos += 1;
+                "*"
+            } else {
+                let local = self.ncname();
+                if local.is_empty() {
+                    return self.error(start, "expected local name");
+                }
+                local
+            };
+            if local != "*" && self.next_significant() == Some('(') {
+                return self.error(start, "unsupported extension function");
+            }
+            return Ok(Token::Name(
+                Some(name.to_string()),
+                local.to_string()
+            ));
+        }
+
+        match self.next_significant() {
+            Some('(') => {
+                match name {
+                    "comment" | "text" | "processing-instruction" | "node" => {
+                        Ok(Token::NodeType(name.to_string()))
+                    }
+                    _ => Ok(Token::FunctionName(name.to_string())),
+                }
+            }
+            Some(':') => {
+                let rest = self.input[self.pos..].trim_start();
+                if rest.starts_with("::") {
+                    Ok(Token::AxisName(name.to_string()))
+                } else {
+                    self.error(start, "unexpected ':'")
+                }
+            }
+            _ => Ok(Token::Name(None, name.to_string())),
+        }
+    }
+}
+
+/// Parse an XPath expression with the given namespace prefix bindings.
+pub(super) fn parse(input: &str, namespaces: &[(&str, &str)])
+    -> Result<Expr, XPathError>
+{
+    let lexer = Lexer { input, pos: 0, tokens: Vec::new() };
+    let tokens = lexer.tokenize()?;
+    let mut parser = Parser {
+        tokens, next: 0, end: input.len(), depth: 0, namespaces
+    };
+    let expr = parser.or_expr()?;
+    if parser.next < parser.tokens.len() {
+        return parser.error("unexpected trailing token");
+    }
+    Ok(expr)
+}
+
+// Maximum nesting depth of parsed sub-expressions (parenthesized,
+// predicates and function arguments), which bounds the stack used by parsing
+// and evaluation of untrusted expressions.
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'n> {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    end: usize,
+    depth: usize,
+    namespaces: &'n [(&'n str, &'n str)],
+}
+
+impl<'n> Parser<'n> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, t)| t)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.next).map(|(_, t)| t.clone());
+        if t.is_some() {
+            self.next += 1;
+        }
+        t
+    }
+
+    fn consume(&mut self, tok: &Token) -> bool {
+        if self.peek() == Some(tok) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, tok: &Token, msg: &str) -> Result<(), XPathError> {
+        if self.consume(tok) {
+            Ok(())
+        } else {
+            self.error(msg)
+        }
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T, XPathError> {
+        let pos = self.tokens.get(self.next).map_or(self.end, |(p, _)| *p);
+        Err(XPathError::Syntax(pos, msg.to_string()))
+    }
+
+    // Increment the nesting depth, failing if it exceeds MAX_DEPTH.
+    fn nest(&mut self) -> Result<(), XPathError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            self.error("expression nested too deeply")
+        } else {
+            Ok(())
+        }
+    }
+
+    fn or_expr(&mut self) -> Result<Expr, XPathError> {
+        let depth = self.depth;
+        self.nest()?;
+        let mut operands = vec![self.and_expr()?];
+        while self.consume(&Token::Or) {
+            operands.push(self.and_expr()?);
+        }
+        self.depth = depth;
+        Ok(chain(operands, Expr::Or))
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut operands = vec![self.equality_expr()?];
+        while self.consume(&Token::And) {
+            operands.push(self.equality_expr()?);
+        }
+        Ok(chain(operands, Expr::And))
+    }
+
+    fn equality_expr(&mut self) -> Result<Expr, XPathError> {
+        let first = self.relational_expr()?;
+        let mut rest = Vec::new();
+        loop {
+            let op = match self.peek() {
+                Some(Token::Eq) => CmpOp::Eq,
+                Some(Token::Ne) => CmpOp::Ne,
+                _ => break,
+            };
+            self.next += 1;
+            rest.push((op, self.relational_expr()?));
+        }
+        Ok(if rest.is_empty() {
+            first
+        } else {
+            Expr::Compare(Box::new(first), rest)
+        })
+    }
+
+    fn relational_expr(&mut self) -> Result<Expr, XPathError> {
+        let first = self.additive_expr()?;
+        let mut rest = Vec::new();
+        loop {
+            let op = match self.peek() {
+                Some(Token::Lt) => CmpOp::Lt,
+                Some(Token::Le) => CmpOp::Le,
+                Some(Token::Gt) => CmpOp::Gt,
+                Some(Token::Ge) => CmpOp::Ge,
+                _ => break,
+            };
+            self.next += 1;
+            rest.push((op, self.additive_expr()?));
+        }
+        Ok(if rest.is_empty() {
+            first
+        } else {
+            Expr::Compare(Box::new(first), rest)
+        })
+    }
+
+    fn additive_expr(&mut self) -> Result<Expr, XPathError> {
+        let first = self.multiplicative_expr()?;
+        let mut rest = Vec::new();
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => ArithOp::Add,
+                Some(Token::Minus) => ArithOp::Sub,
+                _ => break,
+            };
+            self.next += 1;
+            rest.push((op, self.multiplicative_expr()?));
+        }
+        Ok(if rest.is_empty() {
+            first
+        } else {
+            Expr::Arith(Box::new(first), rest)
+        })
+    }
+
+    fn multiplicative_expr(&mut self) -> Result<Expr, XPathError> {
+        let first = self.unary_expr()?;
+        let mut rest = Vec::new();
+        loop {
+            let op = match self.peek() {
+                Some(Token::Multiply) => ArithOp::Mul,
+                Some(Token::Div) => ArithOp::Div,
+                Some(Token::Mod) => ArithOp::Mod,
+                _ => break,
+            };
+            self.next += 1;
+            rest.push((op, self.unary_expr()?));
+        }
+        Ok(if rest.is_empty() {
+            first
+        } else {
+            Expr::Arith(Box::new(first), rest)
+        })
+    }
+
+    fn unary_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut negations = 0usize;
+        while self.consume(&Token::Minus) {
+            negations += 1;
+        }
+        let expr = self.union_expr()?;
+        Ok(match negations {
+            0 => expr,
+            // Any even count is only a conversion to number
+            n if n % 2 == 0 => Expr::Neg(Box::new(Expr::Neg(Box::new(expr)))),
+            _ => Expr::Neg(Box::new(expr)),
+        })
+    }
+
+    fn union_expr(&mut self) -> Result<Expr, XPathError> {
+        let mut operands = vec![self.path_expr()?];
+        while self.consume(&Token::Pipe) {
+            operands.push(self.path_expr()?);
+        }
+        Ok(chain(operands, Expr::Union))
+    }
+
+    fn path_expr(&mut self) -> Result<Expr, XPathError> {
+        match self.peek() {
+            Some(Token::Literal(_)) | Some(Token::Number(_)) |
+            Some(Token::Variable(_)) | Some(Token::LParen) |
+            Some(Token::FunctionName(_)) => {
+                let filter = self.filter_expr()?;
+                let mut steps = Vec::new();
+                if self.relative_continues(&mut steps)? {
+                    self.relative_path(&mut steps)?;
+                    Ok(Expr::Path(Some(Box::new(filter)), steps))
+                } else {
+                    Ok(filter)
+                }
+            }
+            Some(Token::Slash) => {
+                self.next += 1;
+                let mut steps = Vec::new();
+                if self.starts_step() {
+                    self.relative_path(&mut steps)?;
+                }
+                Ok(Expr::Root(steps))
+            }
+            Some(Token::DoubleSlash) => {
+                self.next += 1;
+                let mut steps = vec![descendant_or_self()];
+                self.relative_path(&mut steps)?;
+                Ok(Expr::Root(steps))
+            }
+            _ => {
+                let mut steps = Vec::new();
+                self.relative_path(&mut steps)?;
+                Ok(Expr::Path(None, steps))
+            }
+        }
+    }
+
+    // If the next token is `/` or `//` consume it, pushing any implied
+    // step, and return true.
+    fn relative_continues(&mut self, steps: &mut Vec<Step>)
+        -> Result<bool, XPathError>
+    {
+        if self.consume(&Token::Slash) {
+            Ok(true)
+        } else if self.consume(&Token::DoubleSlash) {
+            steps.push(descendant_or_self());
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn starts_step(&self) -> bool {
+        match self.peek() {
+            Some(Token::Dot) | Some(Token::DotDot) | Some(Token::At) |
+            Some(Token::Name(..)) | Some(Token::NodeType(_)) |
+            Some(Token::AxisName(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn relative_path(&mut self, steps: &mut Vec<Step>)
+        -> Result<(), XPathError>
+    {
+        loop {
+            steps.push(self.step()?);
+            if !self.relative_continues(steps)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<Step, XPathError> {
+        if self.consume(&Token::Dot) {
+            return Ok(Step {
+                axis: Axis::Self_, test: NodeTest::Node, predicates: vec![]
+            });
+        }
+        if self.consume(&Token::DotDot) {
+            return Ok(Step {
+                axis: Axis::Parent, test: NodeTest::Node, predicates: vec![]
+            });
+        }
+
+        let axis = match self.peek().cloned() {
+            Some(Token::At) => {
+                self.next += 1;
+                Axis::Attribute
+            }
+            Some(Token::AxisName(name)) => {
+                let axis = match name.as_str() {
+                    "ancestor"           => Axis::Ancestor,
+                    "ancestor-or-self"   => Axis::AncestorOrSelf,
+                    "attribute"          => Axis::Attribute,
+                    "child"              => Axis::Child,
+                    "descendant"         => Axis::Descendant,
+                    "descendant-or-self" => Axis::DescendantOrSelf,
+                    "following"          => Axis::Following,
+                    "following-sibling"  => Axis::FollowingSibling,
+                    "namespace"          => Axis::Namespace,
+                    "parent"             => Axis::Parent,
+                    "preceding"          => Axis::Preceding,
+                    "preceding-sibling"  => Axis::PrecedingSibling,
+                    "self"               => Axis::Self_,
+                    _ => return self.error("unknown axis name"),
+                };
+                self.next += 1;
+                self.expect(&Token::ColonColon, "expected '::'")?;
+                axis
+            }
+            _ => Axis::Child,
+        };
+
+        let test = match self.advance() {
+            Some(Token::Name(prefix, local)) => {
+                let ns = if let Some(prefix) = prefix {
+                    match self.namespaces.iter().find(|(p, _)| *p == prefix) {
+                        Some((_, uri)) => {
+                            NsTest::Specific(Namespace::from(*uri))
+                        }
+                        None => {
+                            self.next -= 1;
+                            return Err(XPathError::UnknownPrefix(prefix));
+                        }
+                    }
+                } else if local == "*" {
+                    NsTest::Any
+                } else {
+                    NsTest::Default
+                };
+                let local = if local == "*" {
+                    None
+                } else {
+                    Some(LocalName::from(local))
+                };
+                NodeTest::Name(ns, local)
+            }
+            Some(Token::NodeType(name)) => {
+                self.expect(&Token::LParen, "expected '('")?;
+                let test = match name.as_str() {
+                    "node" => NodeTest::Node,
+                    "text" => NodeTest::Text,
+                    "comment" => NodeTest::Comment,
+                    _ => {
+                        if let Some(Token::Literal(l)) = self.peek().cloned() {
+                            self.next += 1;
+                            NodeTest::Pi(Some(l))
+                        } else {
+                            NodeTest::Pi(None)
+                        }
+                    }
+                };
+                self.expect(&Token::RParen, "expected ')'")?;
+                test
+            }
+            _ => {
+                if self.next > 0 {
+                    self.next -= 1;
+                }
+                return self.error("expected node test");
+            }
+        };
+
+        let mut predicates = Vec::new();
+        while self.consume(&Token::LBracket) {
+            predicates.push(self.or_expr()?);
+            self.expect(&Token::RBracket, "expected ']'")?;
+        }
+        Ok(Step { axis, test, predicates })
+    }
+
+    fn filter_expr(&mut self) -> Result<Expr, XPathError> {
+        let primary = match self.advance() {
+            Some(Token::Literal(l)) => Expr::Literal(l),
+            Some(Token::Number(n)) => Expr::Number(n),
+            Some(Token::Variable(v)) => {
+                self.next -= 1;
+                return Err(XPathError::Function(
+                    format!("variable ${} is not supported", v)
+                ));
+            }
+            Some(Token::LParen) => {
+                let e = self.or_expr()?;
+                self.expect(&Token::RParen, "expected ')'")?;
+                e
+            }
+            Some(Token::FunctionName(name)) => {
+                let (func, min, max) = match Function::lookup(&name) {
+                    Some(f) => f,
+                    None => {
+                        return Err(XPathError::Function(
+                            format!("unknown function {}()", name)
+                        ));
+                    }
+                };
+                self.expect(&Token::LParen, "expected '('")?;
+                let mut args = Vec::new();
+                if !self.consume(&Token::RParen) {
+                    loop {
+                        args.push(self.or_expr()?);
+                        if self.consume(&Token::Comma) {
+                            continue;
+                        }
+                        self.expect(&Token::RParen, "expected ')'")?;
+                        break;
+                    }
+                }
+                if args.len() < min || max.map_or(false, |m| args.len() > m) {
+                    return Err(XPathError::Function(format!(
+                        "wrong number of arguments ({}) to {}()",
+                        args.len(), name
+                    )));
+                }
+                Expr::Function(func, args)
+            }
+            _ => unreachable!("filter_expr"),
+        };
+
+        let mut predicates = Vec::new();
+        while self.consume(&Token::LBracket) {
+            predicates.push(self.or_expr()?);
+            self.expect(&Token::RBracket, "expected ']'")?;
+        }
+        if predicates.is_empty() {
+            Ok(primary)
+        } else {
+            Ok(Expr::Filter(Box::new(primary), predicates))
+        }
+    }
+}
+
+// Return the single operand, or a chain expression of all operands.
+fn chain<F>(mut operands: Vec<Expr>, f: F) -> Expr
+    where F: FnOnce(Vec<Expr>) -> Expr
+{
+    if operands.len() == 1 {
+        operands.pop().unwrap()
+    } else {
+        f(operands)
+    }
+}
+
+fn descendant_or_self() -> Step {
+    Step {
+        axis: Axis::DescendantOrSelf,
+        test: NodeTest::Node,
+        predicates: vec![],
+    }
+}
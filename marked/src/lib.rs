@@ -30,6 +30,20 @@ pub const HTTP_CTYPE_CONF: f32    = 0.09;
 /// in meta elements.
 pub const HTML_META_CONF: f32     = 0.20;
 
+/// Recommended confidence for a hint from the encoding of an XML declaration,
+/// e.g. `<?xml version="1.0" encoding="Shift_JIS"?>`.
+pub const XML_DECL_CONF: f32      = 0.20;
+
+/// Confidence for a hint from statistical detection of the encoding of the
+/// [`INITIAL_BUFFER_SIZE`] bytes (or the entire document if shorter), added
+/// by `parse_buffered` functions with the _detect_ feature.
+///
+/// This exceeds [`DEFAULT_CONF`], but is less than [`HTTP_CTYPE_CONF`],
+/// [`HTML_META_CONF`] or [`BOM_CONF`], so that any explicitly declared
+/// charset takes precedence over a statistical guess. Detection is only
+/// applied if non-ASCII bytes are found, and not when a BOM is found.
+pub const DETECT_CONF: f32        = 0.05;
+
 /// Recommended confidence for hints based on a leading Byte-Order-Mark (BOM)
 /// at the start of a document stream.
 pub const BOM_CONF: f32           = 0.31;
@@ -38,22 +52,31 @@ mod chars;
 
 mod decode;
 pub use decode::{
-    Decoder, EncodingHint, SharedEncodingHint,
+    Decoder, EncodingHint, Hint, HintSource, ParseReport, SharedEncodingHint,
 };
 
+pub use mime::Mime;
+
 mod dom;
 pub use dom::{
     html,
     Document, DocumentType, Element,
-    Node, NodeData, NodeId, NodeRef, ProcessingInstruction,
-    Descender, Selector,
-    Attribute, LocalName, Namespace, QualName, StrTendril,
+    Node, NodeData, NodeId, NodeIdMap, NodeMut, NodeRef, ProcessingInstruction,
+    Descender, PrettyOptions, Selector, SourcePos, SourceSpan, XmlOptions,
+    Limit, LimitError, ParseLimits,
+    CompareOptions,
+    Attribute, LocalName, Namespace, QualName, QuirksMode, StrTendril,
 };
 
+pub use dom::diff;
 pub use dom::filter;
+pub use dom::xpath;
 
 #[cfg(feature = "xml")]
 pub use dom::xml;
 
+#[cfg(feature = "css")]
+pub use dom::css;
+
 #[doc(hidden)]
 pub mod logger;